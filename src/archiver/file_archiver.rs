@@ -1,18 +1,29 @@
 use std::io::Read;
 
 use anyhow::{anyhow, Result};
-use indicatif::ProgressBar;
+use lazy_static::lazy_static;
 use rayon::prelude::*;
 
 use crate::backend::{DecryptWriteBackend, ReadSourceOpen};
 use crate::blob::{BlobType, Node, NodeType, Packer, PackerStats};
-use crate::chunker::ChunkIter;
+use crate::chunker::{ChunkIter, MIN_SIZE};
 use crate::crypto::hash;
+use crate::id::Id;
 use crate::index::{IndexedBackend, SharedIndexer};
+use crate::progress::Progress;
 use crate::repofile::ConfigFile;
 
 use super::{ItemWithParent, ParentResult, TreeItem, TreeType};
 
+/// Holes of sparse files are saved as blobs of zeros of this size. Long runs of zeros are
+/// chunked to the minimum chunk size, so these blobs are shared with non-sparse files.
+pub const ZERO_BLOB_SIZE: u64 = MIN_SIZE as u64;
+
+lazy_static! {
+    /// The id of the blob of [`ZERO_BLOB_SIZE`] zeros
+    pub static ref ZERO_BLOB_ID: Id = hash(&vec![0; ZERO_BLOB_SIZE as usize]);
+}
+
 #[derive(Clone)]
 pub struct FileArchiver<BE: DecryptWriteBackend, I: IndexedBackend> {
     index: I,
@@ -41,7 +52,7 @@ impl<BE: DecryptWriteBackend, I: IndexedBackend> FileArchiver<BE, I> {
     pub fn process<O: ReadSourceOpen>(
         &self,
         item: ItemWithParent<Option<O>>,
-        p: ProgressBar,
+        p: Progress,
     ) -> Result<TreeItem> {
         Ok(match item {
             TreeType::NewTree(item) => TreeType::NewTree(item),
@@ -52,8 +63,11 @@ impl<BE: DecryptWriteBackend, I: IndexedBackend> FileArchiver<BE, I> {
                     p.inc(size);
                     (node, size)
                 } else if let NodeType::File = node.node_type() {
-                    let r = open.ok_or(anyhow!("cannot open file"))?.open()?;
-                    self.backup_reader(r, node, p)?
+                    let open = open.ok_or(anyhow!("cannot open file"))?;
+                    match open.open_sparse()? {
+                        (r, None) => self.backup_reader(r, node, p)?,
+                        (r, Some(segments)) => self.backup_sparse(r, &segments, node, &p)?,
+                    }
                 } else {
                     (node, 0)
                 };
@@ -66,9 +80,46 @@ impl<BE: DecryptWriteBackend, I: IndexedBackend> FileArchiver<BE, I> {
         &self,
         r: impl Read + Send + 'static,
         node: Node,
-        p: ProgressBar,
+        p: Progress,
     ) -> Result<(Node, u64)> {
-        let mut chunks: Vec<_> = ChunkIter::new(r, *node.meta().size() as usize, self.poly)
+        let (content, filesize) = self.save_chunks(r, *node.meta().size(), &p)?;
+        let mut node = node;
+        node.set_content(content);
+        Ok((node, filesize))
+    }
+
+    /// Backup a sparse file: the reader only reads the given data segments, the holes between
+    /// them are saved as blobs of zeros without reading them.
+    pub fn backup_sparse(
+        &self,
+        mut r: impl Read + Send,
+        segments: &[(u64, u64)],
+        node: Node,
+        p: &Progress,
+    ) -> Result<(Node, u64)> {
+        let mut content = Vec::new();
+        let mut filesize = 0;
+        for (offset, length) in segments {
+            filesize += self.save_zeros(&mut content, offset.saturating_sub(filesize), p)?;
+            let (ids, size) = self.save_chunks((&mut r).take(*length), *length, p)?;
+            content.extend(ids);
+            filesize += size;
+        }
+        filesize += self.save_zeros(&mut content, node.meta.size.saturating_sub(filesize), p)?;
+
+        let mut node = node;
+        node.set_content(content);
+        Ok((node, filesize))
+    }
+
+    // chunk the contents of the reader and save the chunks; returns the ids and the total size
+    fn save_chunks(
+        &self,
+        r: impl Read + Send,
+        size_hint: u64,
+        p: &Progress,
+    ) -> Result<(Vec<Id>, u64)> {
+        let mut chunks: Vec<_> = ChunkIter::new(r, size_hint as usize, self.poly)
             .enumerate() // see below
             .par_bridge()
             .map(|(num, chunk)| {
@@ -89,13 +140,113 @@ impl<BE: DecryptWriteBackend, I: IndexedBackend> FileArchiver<BE, I> {
 
         let filesize = chunks.iter().map(|x| x.2).sum();
         let content = chunks.into_iter().map(|x| x.1).collect();
+        Ok((content, filesize))
+    }
 
-        let mut node = node;
-        node.set_content(content);
-        Ok((node, filesize))
+    // save blobs of zeros for a hole of the given size
+    fn save_zeros(&self, content: &mut Vec<Id>, size: u64, p: &Progress) -> Result<u64> {
+        let (count, rest) = (size / ZERO_BLOB_SIZE, size % ZERO_BLOB_SIZE);
+        if count > 0 {
+            if !self.index.has_data(&ZERO_BLOB_ID) {
+                let chunk = vec![0; ZERO_BLOB_SIZE as usize];
+                self.data_packer.add(&chunk, &ZERO_BLOB_ID)?;
+            }
+            content.extend((0..count).map(|_| *ZERO_BLOB_ID));
+        }
+        if rest > 0 {
+            let chunk = vec![0; rest as usize];
+            let id = hash(&chunk);
+            if !self.index.has_data(&id) {
+                self.data_packer.add(&chunk, &id)?;
+            }
+            content.push(id);
+        }
+        p.inc(size);
+        Ok(size)
     }
 
     pub fn finalize(self) -> Result<PackerStats> {
         self.data_packer.finalize()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs::File;
+    use std::io::{Seek, SeekFrom, Write};
+
+    use clap::Parser;
+
+    use crate::backend::{
+        DecryptBackend, LocalBackend, LocalSource, LocalSourceOptions, ReadSource, WriteBackend,
+    };
+    use crate::blob::Metadata;
+    use crate::chunker::random_poly;
+    use crate::crypto::Key;
+    use crate::index::{IndexBackend, Indexer};
+
+    #[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd"))]
+    #[test]
+    fn backup_sparse_file() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let be = LocalBackend::new(dir.path().join("repo").to_str().unwrap())?;
+        be.create()?;
+        let be = DecryptBackend::new(&be, Key::new());
+        let config = ConfigFile::new(2, Id::random(), random_poly()?);
+        let index = IndexBackend::new(&be, Progress::hidden())?;
+        let indexer = Indexer::new(be.clone()).into_shared();
+        let archiver = FileArchiver::new(be, index, indexer, &config)?;
+
+        // data, a hole of two zero blobs and a tail, data and a hole of a zero blob and a tail
+        let (data, tail) = (1 << 16, 1 << 16);
+        let second = data + 2 * ZERO_BLOB_SIZE + tail;
+        let size = second + data + ZERO_BLOB_SIZE + tail;
+        let path = dir.path().join("sparse");
+        let mut file = File::create(&path)?;
+        file.set_len(size)?;
+        file.write_all(&vec![b'a'; data as usize])?;
+        _ = file.seek(SeekFrom::Start(second))?;
+        file.write_all(&vec![b'b'; data as usize])?;
+        file.sync_all()?;
+
+        let opts = LocalSourceOptions::parse_from(["backup", "--sparse"]);
+        let entry = LocalSource::new(opts, &[&path])?
+            .entries()
+            .next()
+            .unwrap()?;
+        let (r, segments) = entry.open.unwrap().open_sparse()?;
+        let Some(segments) = segments else {
+            // the file system doesn't support holes
+            return Ok(());
+        };
+        assert_eq!(segments, [(0, data), (second, data)]);
+
+        let node = Node::new_node(
+            "sparse".as_ref(),
+            NodeType::File,
+            Metadata {
+                size,
+                ..Default::default()
+            },
+        );
+        let (node, filesize) = archiver.backup_sparse(r, &segments, node, &Progress::hidden())?;
+        assert_eq!(filesize, size);
+        let zeros = hash(&vec![0; tail as usize]);
+        assert_eq!(
+            node.content.unwrap(),
+            [
+                hash(&vec![b'a'; data as usize]),
+                *ZERO_BLOB_ID,
+                *ZERO_BLOB_ID,
+                zeros,
+                hash(&vec![b'b'; data as usize]),
+                *ZERO_BLOB_ID,
+                zeros,
+            ]
+        );
+        _ = archiver.finalize()?;
+        Ok(())
+    }
+}
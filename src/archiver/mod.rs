@@ -8,18 +8,28 @@ pub use parent::*;
 pub use tree::*;
 pub use tree_archiver::*;
 
+use std::panic;
 use std::path::{Path, PathBuf};
+use std::thread;
 
 use anyhow::Result;
-use chrono::Local;
-use indicatif::ProgressBar;
+use chrono::{DateTime, Local};
+use crossbeam_channel::{bounded, Receiver, Sender};
 use log::*;
 
 use crate::backend::{DecryptWriteBackend, ReadSource, ReadSourceEntry};
 use crate::blob::BlobType;
 use crate::index::{IndexedBackend, Indexer, SharedIndexer};
+use crate::progress::Progress;
 use crate::repofile::{ConfigFile, SnapshotFile};
 
+/// Default number of files which are read concurrently
+pub const DEFAULT_READ_CONCURRENCY: usize = 2;
+
+/// Maximum number of items which are processed but not yet saved in their tree. This allows
+/// to continue processing small files while a large file is read.
+const MAX_PENDING_ITEMS: usize = 1000;
+
 pub struct Archiver<BE: DecryptWriteBackend, I: IndexedBackend> {
     file_archiver: FileArchiver<BE, I>,
     tree_archiver: TreeArchiver<BE, I>,
@@ -27,6 +37,9 @@ pub struct Archiver<BE: DecryptWriteBackend, I: IndexedBackend> {
     indexer: SharedIndexer<BE>,
     be: BE,
     snap: SnapshotFile,
+    // time the backup was started; used to compute the total duration
+    start_time: DateTime<Local>,
+    read_concurrency: usize,
 }
 
 impl<BE: DecryptWriteBackend, I: IndexedBackend> Archiver<BE, I> {
@@ -36,6 +49,7 @@ impl<BE: DecryptWriteBackend, I: IndexedBackend> Archiver<BE, I> {
         config: &ConfigFile,
         parent: Parent<I>,
         mut snap: SnapshotFile,
+        read_concurrency: usize,
     ) -> Result<Self> {
         let indexer = Indexer::new(be.clone()).into_shared();
         let mut summary = snap.summary.take().unwrap();
@@ -49,7 +63,9 @@ impl<BE: DecryptWriteBackend, I: IndexedBackend> Archiver<BE, I> {
             parent,
             be,
             indexer,
+            start_time: snap.time,
             snap,
+            read_concurrency,
         })
     }
 
@@ -58,8 +74,23 @@ impl<BE: DecryptWriteBackend, I: IndexedBackend> Archiver<BE, I> {
         src: impl ReadSource,
         backup_path: &Path,
         as_path: Option<&PathBuf>,
-        p: &ProgressBar,
+        p: &Progress,
     ) -> Result<SnapshotFile> {
+        self.archive_entries(src, backup_path, as_path, p)?;
+        let snap = self.finalize_snapshot()?;
+        p.finish_with_message("done");
+        Ok(snap)
+    }
+
+    /// Archive all entries of the source without saving the snapshot.
+    /// Use [`Archiver::finalize_snapshot`] to save the snapshot afterwards.
+    pub fn archive_entries(
+        &mut self,
+        src: impl ReadSource,
+        backup_path: &Path,
+        as_path: Option<&PathBuf>,
+        p: &Progress,
+    ) -> Result<()> {
         if !p.is_hidden() {
             if let Some(size) = src.size()? {
                 p.set_length(size);
@@ -99,7 +130,8 @@ impl<BE: DecryptWriteBackend, I: IndexedBackend> Archiver<BE, I> {
         let iter = TreeIterator::new(iter);
 
         // use parent snapshot
-        let iter = iter.filter_map(|item| match self.parent.process(item) {
+        let parent = &mut self.parent;
+        let iter = iter.filter_map(|item| match parent.process(item) {
             Ok(item) => Some(item),
             Err(err) => {
                 warn!("ignoring error reading parent snapshot: {err:?}");
@@ -107,23 +139,69 @@ impl<BE: DecryptWriteBackend, I: IndexedBackend> Archiver<BE, I> {
             }
         });
 
-        // archive files
-        let iter = iter.filter_map(|item| match self.file_archiver.process(item, p.clone()) {
-            Ok(item) => Some(item),
-            Err(err) => {
-                warn!("ignoring error: {err:?}");
-                None
+        let file_archiver = &self.file_archiver;
+        let tree_archiver = &mut self.tree_archiver;
+        let read_concurrency = self.read_concurrency.max(1);
+
+        thread::scope(|scope| {
+            // Files are read, chunked and saved by the worker threads. Each item is sent together
+            // with a channel to return the result; the receivers of these channels are passed in
+            // the original order to the tree archiver which then gets the results in this order.
+            let (job_tx, job_rx) = bounded::<(ItemWithParent<_>, Sender<Result<TreeItem>>)>(0);
+            let (result_tx, result_rx) = bounded::<Receiver<Result<TreeItem>>>(MAX_PENDING_ITEMS);
+
+            for _ in 0..read_concurrency {
+                let job_rx = job_rx.clone();
+                let p = p.clone();
+                scope.spawn(move || {
+                    for (item, tx) in job_rx {
+                        // the receiver might be gone if the tree archiver stopped with an error
+                        let _ = tx.send(file_archiver.process(item, p.clone()));
+                    }
+                });
             }
-        });
+            drop(job_rx);
+
+            // save items in trees
+            let tree_handle = scope.spawn(move || -> Result<()> {
+                for rx in result_rx {
+                    match rx.recv()? {
+                        Ok(item) => tree_archiver.add(item)?,
+                        Err(err) => warn!("ignoring error: {err:?}"),
+                    }
+                }
+                Ok(())
+            });
+
+            for item in iter {
+                let (tx, rx) = bounded(1);
+                if result_tx.send(rx).is_err() {
+                    // the tree archiver stopped; its error is returned below
+                    break;
+                }
+                // send errors can be ignored here: the tree archiver then misses the result and stops
+                match item {
+                    TreeType::Other(_) => {
+                        let _ = job_tx.send((item, tx));
+                    }
+                    // new and finished trees don't need to be processed by the workers
+                    _ => {
+                        let _ = tx.send(file_archiver.process(item, p.clone()));
+                    }
+                }
+            }
+            drop(job_tx);
+            drop(result_tx);
 
-        // save items in trees
-        for item in iter {
-            self.tree_archiver.add(item)?;
-        }
+            tree_handle
+                .join()
+                .unwrap_or_else(|err| panic::resume_unwind(err))
+        })
+    }
 
-        let snap = self.finalize_snapshot()?;
-        p.finish_with_message("done");
-        Ok(snap)
+    /// Set the time of the snapshot, e.g. to a time given by the backup source
+    pub fn set_snapshot_time(&mut self, time: DateTime<Local>) {
+        self.snap.time = time;
     }
 
     pub fn finalize_snapshot(mut self) -> Result<SnapshotFile> {
@@ -134,7 +212,7 @@ impl<BE: DecryptWriteBackend, I: IndexedBackend> Archiver<BE, I> {
 
         self.indexer.write().unwrap().finalize()?;
 
-        summary.finalize(self.snap.time)?;
+        summary.finalize(self.start_time)?;
         self.snap.summary = Some(summary);
 
         let id = self.be.save_file(&self.snap)?;
@@ -2,16 +2,18 @@ use anyhow::{bail, Result};
 use bytes::Bytes;
 
 use super::{FileType, Id, ReadBackend, WriteBackend};
-use super::{LocalBackend, RcloneBackend, RestBackend};
+use super::{LocalBackend, RcloneBackend, RestBackend, S3Backend, SftpBackend};
 
 #[derive(Clone)]
 pub enum ChooseBackend {
     Local(LocalBackend),
     Rest(RestBackend),
     Rclone(RcloneBackend),
+    S3(S3Backend),
+    Sftp(SftpBackend),
 }
 
-use ChooseBackend::{Local, Rclone, Rest};
+use ChooseBackend::{Local, Rclone, Rest, Sftp, S3};
 
 impl ChooseBackend {
     pub fn from_url(url: &str) -> Result<Self> {
@@ -21,6 +23,8 @@ impl ChooseBackend {
             Some(("rclone", path)) => Rclone(RcloneBackend::new(path)?),
             Some(("rest", path)) => Rest(RestBackend::new(path)?),
             Some(("local", path)) => Local(LocalBackend::new(path)?),
+            Some(("s3", path)) => S3(S3Backend::new(path)?),
+            Some(("sftp", path)) => Sftp(SftpBackend::new(path)?),
             Some((backend, _)) => bail!("backend {backend} is not supported!"),
             None => Local(LocalBackend::new(url)?),
         })
@@ -33,6 +37,8 @@ impl ReadBackend for ChooseBackend {
             Local(local) => local.location(),
             Rest(rest) => rest.location(),
             Rclone(rclone) => rclone.location(),
+            S3(s3) => s3.location(),
+            Sftp(sftp) => sftp.location(),
         }
     }
 
@@ -41,6 +47,8 @@ impl ReadBackend for ChooseBackend {
             Local(local) => local.set_option(option, value),
             Rest(rest) => rest.set_option(option, value),
             Rclone(rclone) => rclone.set_option(option, value),
+            S3(s3) => s3.set_option(option, value),
+            Sftp(sftp) => sftp.set_option(option, value),
         }
     }
 
@@ -49,6 +57,8 @@ impl ReadBackend for ChooseBackend {
             Local(local) => local.list_with_size(tpe),
             Rest(rest) => rest.list_with_size(tpe),
             Rclone(rclone) => rclone.list_with_size(tpe),
+            S3(s3) => s3.list_with_size(tpe),
+            Sftp(sftp) => sftp.list_with_size(tpe),
         }
     }
 
@@ -57,6 +67,8 @@ impl ReadBackend for ChooseBackend {
             Local(local) => local.read_full(tpe, id),
             Rest(rest) => rest.read_full(tpe, id),
             Rclone(rclone) => rclone.read_full(tpe, id),
+            S3(s3) => s3.read_full(tpe, id),
+            Sftp(sftp) => sftp.read_full(tpe, id),
         }
     }
 
@@ -72,6 +84,8 @@ impl ReadBackend for ChooseBackend {
             Local(local) => local.read_partial(tpe, id, cacheable, offset, length),
             Rest(rest) => rest.read_partial(tpe, id, cacheable, offset, length),
             Rclone(rclone) => rclone.read_partial(tpe, id, cacheable, offset, length),
+            S3(s3) => s3.read_partial(tpe, id, cacheable, offset, length),
+            Sftp(sftp) => sftp.read_partial(tpe, id, cacheable, offset, length),
         }
     }
 }
@@ -82,6 +96,8 @@ impl WriteBackend for ChooseBackend {
             Local(local) => local.create(),
             Rest(rest) => rest.create(),
             Rclone(rclone) => rclone.create(),
+            S3(s3) => s3.create(),
+            Sftp(sftp) => sftp.create(),
         }
     }
 
@@ -90,6 +106,8 @@ impl WriteBackend for ChooseBackend {
             Local(local) => local.write_bytes(tpe, id, cacheable, buf),
             Rest(rest) => rest.write_bytes(tpe, id, cacheable, buf),
             Rclone(rclone) => rclone.write_bytes(tpe, id, cacheable, buf),
+            S3(s3) => s3.write_bytes(tpe, id, cacheable, buf),
+            Sftp(sftp) => sftp.write_bytes(tpe, id, cacheable, buf),
         }
     }
 
@@ -98,6 +116,8 @@ impl WriteBackend for ChooseBackend {
             Local(local) => local.remove(tpe, id, cacheable),
             Rest(rest) => rest.remove(tpe, id, cacheable),
             Rclone(rclone) => rclone.remove(tpe, id, cacheable),
+            S3(s3) => s3.remove(tpe, id, cacheable),
+            Sftp(sftp) => sftp.remove(tpe, id, cacheable),
         }
     }
 }
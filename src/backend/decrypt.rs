@@ -3,12 +3,12 @@ use std::num::NonZeroU32;
 use anyhow::{bail, Result};
 use bytes::Bytes;
 use crossbeam_channel::{unbounded, Receiver};
-use indicatif::ProgressBar;
 use rayon::prelude::*;
 use zstd::stream::{copy_encode, decode_all};
 
 use super::{FileType, Id, ReadBackend, RepoFile, WriteBackend};
 use crate::crypto::{hash, CryptoKey};
+use crate::progress::Progress;
 
 pub trait DecryptFullBackend: DecryptWriteBackend + DecryptReadBackend {}
 impl<T: DecryptWriteBackend + DecryptReadBackend> DecryptFullBackend for T {}
@@ -50,7 +50,7 @@ pub trait DecryptReadBackend: ReadBackend {
         Ok(serde_json::from_slice(&data)?)
     }
 
-    fn stream_all<F: RepoFile>(&self, p: ProgressBar) -> Result<Receiver<Result<(Id, F)>>> {
+    fn stream_all<F: RepoFile>(&self, p: Progress) -> Result<Receiver<Result<(Id, F)>>> {
         let list = self.list(F::TYPE)?;
         self.stream_list(list, p)
     }
@@ -58,7 +58,7 @@ pub trait DecryptReadBackend: ReadBackend {
     fn stream_list<F: RepoFile>(
         &self,
         list: Vec<Id>,
-        p: ProgressBar,
+        p: Progress,
     ) -> Result<Receiver<Result<(Id, F)>>> {
         p.set_length(list.len() as u64);
         let (tx, rx) = unbounded();
@@ -86,7 +86,7 @@ pub trait DecryptWriteBackend: WriteBackend {
     fn save_list<'a, F: RepoFile, I: ExactSizeIterator<Item = &'a F> + Send>(
         &self,
         list: I,
-        p: ProgressBar,
+        p: Progress,
     ) -> Result<()> {
         p.set_length(list.len() as u64);
         list.par_bridge().try_for_each(|file| -> Result<_> {
@@ -103,7 +103,7 @@ pub trait DecryptWriteBackend: WriteBackend {
         tpe: FileType,
         cacheable: bool,
         list: I,
-        p: ProgressBar,
+        p: Progress,
     ) -> Result<()> {
         p.set_length(list.len() as u64);
         list.par_bridge().try_for_each(|id| -> Result<_> {
@@ -1,4 +1,6 @@
+use std::collections::VecDeque;
 use std::fs::{read_link, File};
+use std::io::{self, Read, Seek, SeekFrom};
 #[cfg(not(windows))]
 use std::os::unix::fs::{FileTypeExt, MetadataExt};
 use std::path::{Path, PathBuf};
@@ -9,7 +11,8 @@ use bytesize::ByteSize;
 use chrono::TimeZone;
 use chrono::{DateTime, Local, Utc};
 use clap::Parser;
-use ignore::{overrides::OverrideBuilder, DirEntry, Walk, WalkBuilder};
+use ignore::overrides::{Override, OverrideBuilder};
+use ignore::{DirEntry, Walk, WalkBuilder};
 use log::*;
 use merge::Merge;
 use serde::Deserialize;
@@ -20,13 +23,14 @@ use users::{Groups, Users, UsersCache};
 #[cfg(not(any(windows, target_os = "openbsd")))]
 use super::node::ExtendedAttribute;
 use super::node::{Metadata, NodeType};
-use super::{Node, ReadSource, ReadSourceEntry, ReadSourceOpen};
+use super::{DataSegments, Node, ReadSource, ReadSourceEntry, ReadSourceOpen};
 
 pub struct LocalSource {
     builder: WalkBuilder,
     walker: Walk,
     with_atime: bool,
     ignore_devid: bool,
+    sparse: bool,
     #[cfg(not(windows))]
     cache: UsersCache,
 }
@@ -45,6 +49,11 @@ pub struct LocalSourceOptions {
     #[merge(strategy = merge::bool::overwrite_false)]
     ignore_devid: bool,
 
+    /// Detect holes in sparse files and save them without reading them
+    #[clap(long)]
+    #[merge(strategy = merge::bool::overwrite_false)]
+    sparse: bool,
+
     /// Glob pattern to exclude/include (can be specified multiple times)
     #[clap(long, help_heading = "EXCLUDE OPTIONS")]
     #[merge(strategy = merge::vec::overwrite_empty)]
@@ -86,37 +95,50 @@ pub struct LocalSourceOptions {
     exclude_larger_than: Option<ByteSize>,
 }
 
-impl LocalSource {
-    pub fn new(opts: LocalSourceOptions, backup_paths: &[impl AsRef<Path>]) -> Result<Self> {
-        let mut walk_builder = WalkBuilder::new(&backup_paths[0]);
+/// Build the [`Override`] matcher from the given glob patterns and files containing glob patterns.
+/// Patterns starting with `!` exclude matching paths.
+pub fn glob_overrides(
+    globs: &[String],
+    iglobs: &[String],
+    glob_files: &[String],
+    iglob_files: &[String],
+) -> Result<Override> {
+    let mut override_builder = OverrideBuilder::new("/");
+
+    for g in globs {
+        override_builder.add(g)?;
+    }
 
-        for path in &backup_paths[1..] {
-            walk_builder.add(path);
+    for file in glob_files {
+        for line in std::fs::read_to_string(file)?.lines() {
+            override_builder.add(line)?;
         }
+    }
 
-        let mut override_builder = OverrideBuilder::new("/");
+    override_builder.case_insensitive(true)?;
+    for g in iglobs {
+        override_builder.add(g)?;
+    }
 
-        for g in opts.glob {
-            override_builder.add(&g)?;
+    for file in iglob_files {
+        for line in std::fs::read_to_string(file)?.lines() {
+            override_builder.add(line)?;
         }
+    }
 
-        for file in opts.glob_file {
-            for line in std::fs::read_to_string(file)?.lines() {
-                override_builder.add(line)?;
-            }
-        }
+    Ok(override_builder.build()?)
+}
 
-        override_builder.case_insensitive(true)?;
-        for g in opts.iglob {
-            override_builder.add(&g)?;
-        }
+impl LocalSource {
+    pub fn new(opts: LocalSourceOptions, backup_paths: &[impl AsRef<Path>]) -> Result<Self> {
+        let mut walk_builder = WalkBuilder::new(&backup_paths[0]);
 
-        for file in opts.iglob_file {
-            for line in std::fs::read_to_string(file)?.lines() {
-                override_builder.add(line)?;
-            }
+        for path in &backup_paths[1..] {
+            walk_builder.add(path);
         }
 
+        let overrides = glob_overrides(&opts.glob, &opts.iglob, &opts.glob_file, &opts.iglob_file)?;
+
         walk_builder
             .follow_links(false)
             .hidden(false)
@@ -125,7 +147,7 @@ impl LocalSource {
             .sort_by_file_path(Path::cmp)
             .same_file_system(opts.one_file_system)
             .max_filesize(opts.exclude_larger_than.map(|s| s.as_u64()))
-            .overrides(override_builder.build()?);
+            .overrides(overrides);
 
         if !opts.exclude_if_present.is_empty() {
             walk_builder.filter_entry(move |entry| match entry.file_type() {
@@ -149,23 +171,116 @@ impl LocalSource {
             walker,
             with_atime: opts.with_atime,
             ignore_devid: opts.ignore_devid,
+            sparse: opts.sparse,
             #[cfg(not(windows))]
             cache: UsersCache::new(),
         })
     }
 }
 
-pub struct OpenFile(PathBuf);
+pub struct OpenFile {
+    path: PathBuf,
+    sparse: bool,
+}
 
 impl ReadSourceOpen for OpenFile {
-    type Reader = File;
+    type Reader = LocalFile;
 
     fn open(self) -> Result<Self::Reader> {
-        let path = self.0;
-        File::open(&path).with_context(|| format!("Unable to open {}", path.display()))
+        let file = File::open(&self.path)
+            .with_context(|| format!("Unable to open {}", self.path.display()))?;
+        Ok(LocalFile {
+            file,
+            segments: None,
+        })
+    }
+
+    fn open_sparse(self) -> Result<(Self::Reader, Option<DataSegments>)> {
+        let sparse = self.sparse;
+        let mut reader = self.open()?;
+        if !sparse {
+            return Ok((reader, None));
+        }
+        let segments = data_segments(&reader.file)?;
+        // seeking for holes changes the position within the file
+        reader.file.rewind()?;
+        reader.segments = segments.clone().map(VecDeque::from);
+        Ok((reader, segments))
     }
 }
 
+/// [`LocalFile`] reads a local file; if data segments are given, only these are read.
+pub struct LocalFile {
+    file: File,
+    segments: Option<VecDeque<(u64, u64)>>,
+}
+
+impl Read for LocalFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let Some(segments) = &mut self.segments else {
+            return self.file.read(buf);
+        };
+        while let Some((offset, length)) = segments.front_mut() {
+            if *length == 0 {
+                segments.pop_front();
+                continue;
+            }
+            self.file.seek(SeekFrom::Start(*offset))?;
+            let max = usize::try_from(*length)
+                .unwrap_or(usize::MAX)
+                .min(buf.len());
+            let size = self.file.read(&mut buf[..max])?;
+            if size == 0 {
+                // the file has been truncated
+                break;
+            }
+            *offset += size as u64;
+            *length -= size as u64;
+            return Ok(size);
+        }
+        Ok(0)
+    }
+}
+
+/// Determine the data segments (offset, length) of a file by seeking over its holes.
+/// Returns `None` if the file has no holes or holes are not supported.
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd"))]
+fn data_segments(file: &File) -> Result<Option<DataSegments>> {
+    use nix::errno::Errno;
+    use nix::unistd::{lseek, Whence};
+    use std::os::unix::io::AsRawFd;
+
+    let fd = file.as_raw_fd();
+    let size = file.metadata()?.len();
+    let mut segments = Vec::new();
+    let mut pos = 0;
+    while pos < size {
+        let start = match lseek(fd, pos.try_into()?, Whence::SeekData) {
+            Ok(start) => start,
+            // no more data, the remaining file is a hole
+            Err(Errno::ENXIO) => break,
+            // the file system doesn't support seeking for holes
+            Err(Errno::EINVAL) => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        let end = lseek(fd, start, Whence::SeekHole)?;
+        let (start, end) = (u64::try_from(start)?, u64::try_from(end)?);
+        segments.push((start, end - start));
+        pos = end;
+    }
+
+    // a file consisting of a single data segment has no holes
+    if size == 0 || segments == [(0, size)] {
+        return Ok(None);
+    }
+    Ok(Some(segments))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "freebsd")))]
+fn data_segments(_file: &File) -> Result<Option<DataSegments>> {
+    Ok(None)
+}
+
 impl ReadSource for LocalSource {
     type Open = OpenFile;
     type Iter = Self;
@@ -203,6 +318,7 @@ impl Iterator for LocalSource {
                 e?,
                 self.with_atime,
                 self.ignore_devid,
+                self.sparse,
                 #[cfg(not(windows))]
                 &self.cache,
             )
@@ -215,6 +331,7 @@ fn map_entry(
     entry: DirEntry,
     with_atime: bool,
     _ignore_devid: bool,
+    sparse: bool,
 ) -> Result<ReadSourceEntry<OpenFile>> {
     let name = entry.file_name();
     let m = entry.metadata()?;
@@ -277,7 +394,10 @@ fn map_entry(
     };
 
     let path = entry.into_path();
-    let open = Some(OpenFile(path.clone()));
+    let open = Some(OpenFile {
+        path: path.clone(),
+        sparse,
+    });
     Ok(ReadSourceEntry { path, node, open })
 }
 
@@ -287,6 +407,7 @@ fn map_entry(
     entry: DirEntry,
     with_atime: bool,
     ignore_devid: bool,
+    sparse: bool,
     cache: &UsersCache,
 ) -> Result<ReadSourceEntry<OpenFile>> {
     let name = entry.file_name();
@@ -380,7 +501,10 @@ fn map_entry(
         Node::new_node(name, NodeType::File, meta)
     };
     let path = entry.into_path();
-    let open = Some(OpenFile(path.clone()));
+    let open = Some(OpenFile {
+        path: path.clone(),
+        sparse,
+    });
     Ok(ReadSourceEntry { path, node, open })
 }
 
@@ -402,13 +526,13 @@ pub mod mapper {
 
     // consts from man page inode(7)
     const S_IFFORMAT: u32 = 0o170000; // File mask
-    const S_IFSOCK: u32 = 0o140000; // socket
-    const S_IFLNK: u32 = 0o120000; // symbolic link
-    const S_IFREG: u32 = 0o100000; // regular file
-    const S_IFBLK: u32 = 0o060000; // block device
-    const S_IFDIR: u32 = 0o040000; // directory
-    const S_IFCHR: u32 = 0o020000; // character device
-    const S_IFIFO: u32 = 0o010000; // FIFO
+    pub const S_IFSOCK: u32 = 0o140000; // socket
+    pub const S_IFLNK: u32 = 0o120000; // symbolic link
+    pub const S_IFREG: u32 = 0o100000; // regular file
+    pub const S_IFBLK: u32 = 0o060000; // block device
+    pub const S_IFDIR: u32 = 0o040000; // directory
+    pub const S_IFCHR: u32 = 0o020000; // character device
+    pub const S_IFIFO: u32 = 0o010000; // FIFO
 
     const S_ISUID: u32 = 0o4000; // set-user-ID bit (see execve(2))
     const S_ISGID: u32 = 0o2000; // set-group-ID bit (see below)
@@ -481,3 +605,64 @@ pub mod mapper {
         mode
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Write;
+
+    #[test]
+    fn local_file_segments() -> Result<()> {
+        let mut file = tempfile::tempfile()?;
+        file.write_all(b"0123456789")?;
+        let mut reader = LocalFile {
+            file,
+            segments: Some(VecDeque::from([(1, 2), (5, 0), (6, 3)])),
+        };
+        let mut data = String::new();
+        _ = reader.read_to_string(&mut data)?;
+        assert_eq!(data, "12678");
+        Ok(())
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd"))]
+    #[test]
+    fn sparse_file() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("sparse");
+        let mut file = File::create(&path)?;
+        file.set_len(3 << 20)?;
+        file.write_all(&[b'a'; 1 << 16])?;
+        _ = file.seek(SeekFrom::Start(1 << 20))?;
+        file.write_all(&[b'b'; 1 << 16])?;
+        file.sync_all()?;
+
+        let open = OpenFile {
+            path: path.clone(),
+            sparse: true,
+        };
+        let (mut reader, segments) = open.open_sparse()?;
+        let Some(segments) = segments else {
+            // the file system doesn't support holes
+            return Ok(());
+        };
+        assert_eq!(segments, [(0, 1 << 16), (1 << 20, 1 << 16)]);
+        let mut data = Vec::new();
+        _ = reader.read_to_end(&mut data)?;
+        assert_eq!(data.len(), 2 << 16);
+        assert!(data[..1 << 16].iter().all(|b| *b == b'a'));
+        assert!(data[1 << 16..].iter().all(|b| *b == b'b'));
+
+        // files without holes are read completely
+        let open = OpenFile {
+            path: path.clone(),
+            sparse: false,
+        };
+        assert!(open.open_sparse()?.1.is_none());
+        let full = File::create(dir.path().join("full"))?;
+        (&full).write_all(b"data")?;
+        assert!(data_segments(&full)?.is_none());
+        Ok(())
+    }
+}
@@ -252,6 +252,34 @@ impl LocalDestination {
         Ok(())
     }
 
+    /// Create `item` as hard link to `target`, replacing an existing file
+    pub fn create_hardlink(&self, item: impl AsRef<Path>, target: impl AsRef<Path>) -> Result<()> {
+        let filename = self.path(item);
+        if fs::symlink_metadata(&filename).is_ok() {
+            fs::remove_file(&filename)?;
+        }
+        fs::hard_link(self.path(target), filename)?;
+        Ok(())
+    }
+
+    #[cfg(windows)]
+    pub fn is_hardlink(&self, _item: impl AsRef<Path>, _target: impl AsRef<Path>) -> bool {
+        false
+    }
+
+    #[cfg(not(windows))]
+    /// Check if `item` and `target` are hard links to the same file
+    pub fn is_hardlink(&self, item: impl AsRef<Path>, target: impl AsRef<Path>) -> bool {
+        use std::os::unix::fs::MetadataExt;
+        match (
+            fs::symlink_metadata(self.path(item)),
+            fs::symlink_metadata(self.path(target)),
+        ) {
+            (Ok(m1), Ok(m2)) => m1.dev() == m2.dev() && m1.ino() == m2.ino(),
+            _ => false,
+        }
+    }
+
     pub fn set_times(&self, item: impl AsRef<Path>, meta: &Metadata) -> Result<()> {
         let filename = self.path(item);
         if let Some(mtime) = meta.mtime.map(|t| FileTime::from_system_time(t.into())) {
@@ -451,3 +479,27 @@ impl LocalDestination {
         Ok(())
     }
 }
+
+#[cfg(not(windows))]
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hardlinks() {
+        let dir = tempfile::tempdir().unwrap();
+        let dest = LocalDestination::new(&dir.path().to_string_lossy(), false, false).unwrap();
+        fs::write(dir.path().join("a"), "a").unwrap();
+        fs::write(dir.path().join("b"), "b").unwrap();
+        assert!(!dest.is_hardlink("b", "a"));
+        assert!(!dest.is_hardlink("c", "a"));
+
+        // existing files are replaced
+        dest.create_hardlink("b", "a").unwrap();
+        dest.create_hardlink("c", "a").unwrap();
+        assert!(dest.is_hardlink("b", "a"));
+        assert!(dest.is_hardlink("c", "a"));
+        assert_eq!(fs::read_to_string(dir.path().join("b")).unwrap(), "a");
+        assert!(dest.create_hardlink("d", "missing").is_err());
+    }
+}
@@ -0,0 +1,134 @@
+use anyhow::{anyhow, bail, Result};
+use bytes::Bytes;
+
+use super::{
+    DecryptFullBackend, DecryptReadBackend, DecryptWriteBackend, FileType, Id, ReadBackend,
+    WriteBackend,
+};
+use crate::crypto::CryptoKey;
+use crate::repofile::{PackHeader, PackHeaderLength, LENGTH_LEN};
+
+/// [`MirrorBackend`] additionally saves all written index files, snapshots and tree packs to the
+/// mirror backend, if given. The files are saved under the same id but encrypted with the key of
+/// the mirror.
+#[derive(Clone)]
+pub struct MirrorBackend<BE: DecryptFullBackend, M: DecryptFullBackend> {
+    be: BE,
+    mirror: Option<M>,
+}
+
+impl<BE: DecryptFullBackend, M: DecryptFullBackend> MirrorBackend<BE, M> {
+    pub fn new(be: BE, mirror: Option<M>) -> Self {
+        Self { be, mirror }
+    }
+}
+
+// re-encrypt all blobs and the header of the pack using the key of the mirror; as the encryption
+// overhead is the same for both keys, the layout of the pack is unchanged.
+pub fn reencrypt_pack(
+    be: &impl DecryptReadBackend,
+    key: &impl CryptoKey,
+    data: &[u8],
+) -> Result<Vec<u8>> {
+    let len = data.len();
+    if len < LENGTH_LEN as usize {
+        bail!("pack is too small");
+    }
+    let header_len = PackHeaderLength::from_binary(&data[len - LENGTH_LEN as usize..])?.to_u32();
+    let header_start = len
+        .checked_sub((header_len + LENGTH_LEN) as usize)
+        .ok_or_else(|| anyhow!("invalid pack header length"))?;
+    let header = be.decrypt(&data[header_start..len - LENGTH_LEN as usize])?;
+
+    let mut res = Vec::with_capacity(len);
+    for blob in PackHeader::from_binary(&header)?.into_blobs() {
+        let start = blob.offset as usize;
+        let end = start + blob.length as usize;
+        res.extend(key.encrypt_data(&be.decrypt(&data[start..end])?)?);
+    }
+    res.extend(key.encrypt_data(&header)?);
+    res.extend_from_slice(&data[len - LENGTH_LEN as usize..]);
+    if res.len() != len {
+        bail!("re-encrypted pack has a different size");
+    }
+    Ok(res)
+}
+
+impl<BE: DecryptFullBackend, M: DecryptFullBackend> DecryptReadBackend for MirrorBackend<BE, M> {
+    fn decrypt(&self, data: &[u8]) -> Result<Vec<u8>> {
+        self.be.decrypt(data)
+    }
+}
+
+impl<BE: DecryptFullBackend, M: DecryptFullBackend> ReadBackend for MirrorBackend<BE, M> {
+    fn location(&self) -> String {
+        self.be.location()
+    }
+
+    fn set_option(&mut self, option: &str, value: &str) -> Result<()> {
+        self.be.set_option(option, value)
+    }
+
+    fn list_with_size(&self, tpe: FileType) -> Result<Vec<(Id, u32)>> {
+        self.be.list_with_size(tpe)
+    }
+
+    fn read_full(&self, tpe: FileType, id: &Id) -> Result<Bytes> {
+        self.be.read_full(tpe, id)
+    }
+
+    fn read_partial(
+        &self,
+        tpe: FileType,
+        id: &Id,
+        cacheable: bool,
+        offset: u32,
+        length: u32,
+    ) -> Result<Bytes> {
+        self.be.read_partial(tpe, id, cacheable, offset, length)
+    }
+}
+
+impl<BE: DecryptFullBackend, M: DecryptFullBackend> DecryptWriteBackend for MirrorBackend<BE, M> {
+    type Key = <BE as DecryptWriteBackend>::Key;
+
+    fn key(&self) -> &Self::Key {
+        self.be.key()
+    }
+
+    fn hash_write_full(&self, tpe: FileType, data: &[u8]) -> Result<Id> {
+        let id = self.be.hash_write_full(tpe, data)?;
+        if let Some(mirror) = &self.mirror {
+            if matches!(tpe, FileType::Index | FileType::Snapshot) {
+                let data = mirror.key().encrypt_data(data)?;
+                mirror.write_bytes(tpe, &id, true, data.into())?;
+            }
+        }
+        Ok(id)
+    }
+
+    fn set_zstd(&mut self, zstd: Option<i32>) {
+        self.be.set_zstd(zstd);
+    }
+}
+
+impl<BE: DecryptFullBackend, M: DecryptFullBackend> WriteBackend for MirrorBackend<BE, M> {
+    fn create(&self) -> Result<()> {
+        self.be.create()
+    }
+
+    fn write_bytes(&self, tpe: FileType, id: &Id, cacheable: bool, buf: Bytes) -> Result<()> {
+        if let Some(mirror) = &self.mirror {
+            // only tree packs are cacheable
+            if tpe == FileType::Pack && cacheable {
+                let data = reencrypt_pack(&self.be, mirror.key(), &buf)?;
+                mirror.write_bytes(tpe, id, cacheable, data.into())?;
+            }
+        }
+        self.be.write_bytes(tpe, id, cacheable, buf)
+    }
+
+    fn remove(&self, tpe: FileType, id: &Id, cacheable: bool) -> Result<()> {
+        self.be.remove(tpe, id, cacheable)
+    }
+}
@@ -14,10 +14,14 @@ pub mod dry_run;
 pub mod hotcold;
 pub mod ignore;
 pub mod local;
+pub mod mirror;
 pub mod node;
 pub mod rclone;
 pub mod rest;
+pub mod s3;
+pub mod sftp;
 pub mod stdin;
+pub mod tarball;
 
 pub use self::ignore::*;
 pub use cache::*;
@@ -26,17 +30,22 @@ pub use decrypt::*;
 pub use dry_run::*;
 pub use hotcold::*;
 pub use local::*;
+pub use mirror::*;
 use node::Node;
 pub use rclone::*;
 pub use rest::*;
+pub use s3::*;
+pub use sftp::*;
 pub use stdin::*;
+pub use tarball::*;
 
 /// All [`FileType`]s which are located in separated directories
-pub const ALL_FILE_TYPES: [FileType; 4] = [
+pub const ALL_FILE_TYPES: [FileType; 5] = [
     FileType::Key,
     FileType::Snapshot,
     FileType::Index,
     FileType::Pack,
+    FileType::Lock,
 ];
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -46,6 +55,7 @@ pub enum FileType {
     Key,
     Snapshot,
     Pack,
+    Lock,
 }
 
 impl FileType {
@@ -56,12 +66,13 @@ impl FileType {
             FileType::Index => "index",
             FileType::Key => "keys",
             FileType::Pack => "data",
+            FileType::Lock => "locks",
         }
     }
 
     pub fn is_cacheable(self) -> bool {
         match self {
-            FileType::Config | FileType::Key | FileType::Pack => false,
+            FileType::Config | FileType::Key | FileType::Pack | FileType::Lock => false,
             FileType::Snapshot | FileType::Index => true,
         }
     }
@@ -152,10 +163,23 @@ pub struct ReadSourceEntry<O> {
     pub open: Option<O>,
 }
 
-pub trait ReadSourceOpen {
+/// Data segments of a sparse file as (offset, length); all other parts of the file are holes
+pub type DataSegments = Vec<(u64, u64)>;
+
+pub trait ReadSourceOpen: Send {
     type Reader: Read + Send + 'static;
 
     fn open(self) -> Result<Self::Reader>;
+
+    /// Open the file and detect holes of sparse files. If holes are found, the data segments of
+    /// the file are returned and the reader only reads these segments.
+    /// Otherwise `None` is returned and the reader reads the whole file.
+    fn open_sparse(self) -> Result<(Self::Reader, Option<DataSegments>)>
+    where
+        Self: Sized,
+    {
+        Ok((self.open()?, None))
+    }
 }
 
 pub trait ReadSource {
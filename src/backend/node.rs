@@ -1,9 +1,11 @@
+use std::collections::{hash_map::Entry, HashMap};
 use std::ffi::{OsStr, OsString};
 use std::fmt::Debug;
 #[cfg(not(windows))]
 use std::fmt::Write;
 #[cfg(not(windows))]
 use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
 use anyhow::Result;
@@ -129,6 +131,46 @@ impl Node {
     }
 }
 
+/// [`HardLinks`] identifies files which are hard links to the same file by the device id and
+/// inode saved in the snapshot. Nodes are only linked if they also have the same size and
+/// contents, as unrelated files can have the same ids, e.g. when using `--ignore-devid` or in
+/// merged snapshots.
+#[derive(Default)]
+pub struct HardLinks(HashMap<(u64, u64), FirstLink>);
+
+// the first path of a file with several links
+struct FirstLink {
+    path: PathBuf,
+    size: u64,
+    content: Option<Vec<Id>>,
+}
+
+impl HardLinks {
+    /// Return the first path of the file if the node at `path` is a further link to it.
+    /// Otherwise, `path` is saved as first path if the node has several links.
+    pub fn link_target(&mut self, path: &Path, node: &Node) -> Option<&Path> {
+        let meta = &node.meta;
+        if node.node_type != NodeType::File || meta.links < 2 || meta.inode == 0 {
+            return None;
+        }
+        match self.0.entry((meta.device_id, meta.inode)) {
+            Entry::Vacant(entry) => {
+                entry.insert(FirstLink {
+                    path: path.to_path_buf(),
+                    size: meta.size,
+                    content: node.content.clone(),
+                });
+                None
+            }
+            Entry::Occupied(entry) => {
+                let first = entry.into_mut();
+                (first.size == meta.size && first.content == node.content)
+                    .then_some(first.path.as_path())
+            }
+        }
+    }
+}
+
 // TODO(Windows): This is not able to handle non-unicode filenames and
 // doesn't treat filenames which need and escape (like `\`, `"`, ...) correctly
 #[cfg(windows)]
@@ -273,6 +315,35 @@ mod tests {
     use quickcheck_macros::quickcheck;
     use rstest::rstest;
 
+    #[test]
+    fn hard_links() {
+        let file = |inode, links, content: &[Id]| {
+            let meta = Metadata {
+                inode,
+                device_id: 1,
+                links,
+                size: content.len() as u64,
+                ..Default::default()
+            };
+            let mut node = Node::new_node(OsStr::new("file"), NodeType::File, meta);
+            node.set_content(content.to_vec());
+            node
+        };
+        let (id1, id2) = (Id::random(), Id::random());
+
+        let mut links = HardLinks::default();
+        assert_eq!(links.link_target(Path::new("a"), &file(1, 2, &[id1])), None);
+        assert_eq!(
+            links.link_target(Path::new("b"), &file(1, 2, &[id1])),
+            Some(Path::new("a"))
+        );
+        // same inode, but different contents
+        assert_eq!(links.link_target(Path::new("c"), &file(1, 2, &[id2])), None);
+        // files with a single link or without inode are never linked
+        assert_eq!(links.link_target(Path::new("d"), &file(2, 1, &[id1])), None);
+        assert_eq!(links.link_target(Path::new("f"), &file(0, 2, &[id1])), None);
+    }
+
     #[quickcheck]
     fn escape_unescape_is_identity(bytes: Vec<u8>) -> bool {
         let name = OsStr::from_bytes(&bytes);
@@ -13,7 +13,7 @@ use serde::Deserialize;
 use super::{FileType, Id, ReadBackend, WriteBackend};
 
 // trait CheckError to add user-defined methoed check_error on Response
-trait CheckError {
+pub(super) trait CheckError {
     fn check_error(self) -> std::result::Result<Response, Error<reqwest::Error>>;
 }
 
@@ -33,7 +33,30 @@ impl CheckError for Response {
 }
 
 #[derive(Clone)]
-struct MaybeBackoff(Option<ExponentialBackoff>);
+pub(super) struct MaybeBackoff(Option<ExponentialBackoff>);
+
+impl MaybeBackoff {
+    pub(super) fn new() -> Self {
+        Self(Some(
+            ExponentialBackoffBuilder::new()
+                .with_max_elapsed_time(Some(Duration::from_secs(600)))
+                .build(),
+        ))
+    }
+
+    // parse the value of the option "retry"
+    pub(super) fn from_option(value: &str) -> Result<Self> {
+        Ok(match value {
+            "true" => Self(Some(
+                ExponentialBackoffBuilder::new()
+                    .with_max_elapsed_time(Some(Duration::from_secs(120)))
+                    .build(),
+            )),
+            "false" => Self(None),
+            val => bail!("value {val} not supported for option retry!"),
+        })
+    }
+}
 
 impl Backoff for MaybeBackoff {
     fn next_backoff(&mut self) -> Option<Duration> {
@@ -54,7 +77,7 @@ pub struct RestBackend {
     backoff: MaybeBackoff,
 }
 
-fn notify(err: reqwest::Error, duration: Duration) {
+pub(super) fn notify(err: reqwest::Error, duration: Duration) {
     warn!("Error {err} at {duration:?}, retrying");
 }
 
@@ -77,11 +100,7 @@ impl RestBackend {
         Ok(Self {
             url,
             client,
-            backoff: MaybeBackoff(Some(
-                ExponentialBackoffBuilder::new()
-                    .with_max_elapsed_time(Some(Duration::from_secs(600)))
-                    .build(),
-            )),
+            backoff: MaybeBackoff::new(),
         })
     }
 
@@ -113,19 +132,7 @@ impl ReadBackend for RestBackend {
 
     fn set_option(&mut self, option: &str, value: &str) -> Result<()> {
         if option == "retry" {
-            match value {
-                "true" => {
-                    self.backoff = MaybeBackoff(Some(
-                        ExponentialBackoffBuilder::new()
-                            .with_max_elapsed_time(Some(Duration::from_secs(120)))
-                            .build(),
-                    ));
-                }
-                "false" => {
-                    self.backoff = MaybeBackoff(None);
-                }
-                val => bail!("value {val} not supported for option retry!"),
-            }
+            self.backoff = MaybeBackoff::from_option(value)?;
         } else if option == "timeout" {
             let timeout = humantime::Duration::from_str(value)?;
             self.client = ClientBuilder::new().timeout(*timeout).build()?;
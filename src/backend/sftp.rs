@@ -0,0 +1,418 @@
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use aho_corasick::AhoCorasick;
+use anyhow::{anyhow, bail, Result};
+use bytes::Bytes;
+use log::*;
+use ssh2::{CheckResult, KnownHostFileKind, Session, Sftp};
+
+use super::{FileType, Id, ReadBackend, WriteBackend, ALL_FILE_TYPES};
+
+/// Backend for repositories accessed via SFTP.
+///
+/// The repository is given as `[user@]host[:port]:path`. Authentication is done using the ssh-agent
+/// or the default private keys in `~/.ssh`; the host key must be contained in `~/.ssh/known_hosts`.
+/// The options `post-create-command` and `post-delete-command` give commands which are run after a
+/// file has been written or removed. Unlike for local repositories, they are run on the remote host
+/// and `%file` is the path of the file on the remote host.
+#[derive(Clone)]
+pub struct SftpBackend {
+    user: String,
+    host: String,
+    port: u16,
+    path: PathBuf,
+    session: Session,
+    sftp: Arc<Sftp>,
+    post_create_command: Option<String>,
+    post_delete_command: Option<String>,
+}
+
+impl SftpBackend {
+    pub fn new(url: &str) -> Result<Self> {
+        let (user, host, port, path) = parse_location(url)?;
+        let session = Self::connect(&user, &host, port)?;
+        let sftp = Arc::new(session.sftp()?);
+
+        Ok(Self {
+            user,
+            host,
+            port,
+            path,
+            session,
+            sftp,
+            post_create_command: None,
+            post_delete_command: None,
+        })
+    }
+
+    fn connect(user: &str, host: &str, port: u16) -> Result<Session> {
+        let tcp = TcpStream::connect((host, port))?;
+        let mut session = Session::new()?;
+        session.set_tcp_stream(tcp);
+        session.handshake()?;
+
+        // verify the host key
+        let (key, _) = session
+            .host_key()
+            .ok_or_else(|| anyhow!("no host key received from {host}"))?;
+        let mut known_hosts = session.known_hosts()?;
+        let known_hosts_file = dirs::home_dir()
+            .ok_or_else(|| anyhow!("cannot determine home directory"))?
+            .join(".ssh")
+            .join("known_hosts");
+        if known_hosts_file.exists() {
+            known_hosts.read_file(&known_hosts_file, KnownHostFileKind::OpenSSH)?;
+        }
+        match known_hosts.check_port(host, port, key) {
+            CheckResult::Match => {}
+            CheckResult::NotFound => bail!(
+                "host key for {host} not found in {known_hosts_file:?}. Please connect once using ssh to add it."
+            ),
+            CheckResult::Mismatch => bail!("host key for {host} does not match the known host key!"),
+            CheckResult::Failure => bail!("error checking host key for {host}"),
+        }
+
+        // authenticate using the agent or the default keys
+        if let Err(err) = session.userauth_agent(user) {
+            debug!("authentication using ssh-agent failed: {err}");
+        }
+        if !session.authenticated() {
+            if let Some(ssh_dir) = dirs::home_dir().map(|dir| dir.join(".ssh")) {
+                for key in ["id_ed25519", "id_ecdsa", "id_rsa"] {
+                    let key = ssh_dir.join(key);
+                    if !key.exists() {
+                        continue;
+                    }
+                    match session.userauth_pubkey_file(user, None, &key, None) {
+                        Ok(()) => break,
+                        Err(err) => debug!("authentication using {key:?} failed: {err}"),
+                    }
+                }
+            }
+        }
+        if !session.authenticated() {
+            bail!("authentication as {user} at {host} failed");
+        }
+        Ok(session)
+    }
+
+    fn path(&self, tpe: FileType, id: &Id) -> PathBuf {
+        file_path(&self.path, tpe, id)
+    }
+
+    fn create_dir(&self, path: &Path) -> Result<()> {
+        if self.sftp.stat(path).is_err() {
+            self.sftp.mkdir(path, 0o700)?;
+        }
+        Ok(())
+    }
+
+    // list all files within dir together with their size
+    fn list_dir(&self, dir: &Path) -> Result<Vec<(Id, u32)>> {
+        Ok(self
+            .sftp
+            .readdir(dir)?
+            .into_iter()
+            .filter(|(_, stat)| stat.is_file())
+            .filter_map(|(path, stat)| {
+                let id = Id::from_hex(&path.file_name()?.to_string_lossy()).ok()?;
+                Some((id, stat.size?.try_into().ok()?))
+            })
+            .collect())
+    }
+
+    // call the command on the remote host
+    fn call_command(&self, tpe: FileType, id: &Id, filename: &Path, command: &str) -> Result<()> {
+        let actual_command = remote_command(command, tpe, id, filename);
+        debug!("calling {actual_command} on {}...", self.host);
+        let mut channel = self.session.channel_session()?;
+        channel.exec(&actual_command)?;
+        let mut output = String::new();
+        channel.read_to_string(&mut output)?;
+        channel.wait_close()?;
+        let status = channel.exit_status()?;
+        if status != 0 {
+            bail!(
+                "command was not successful for filename {}, type {}, id {id}. exit status: {status}",
+                filename.display(),
+                tpe.name(),
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Split a location `[user@]host[:port]:path` into user, host, port and path.
+/// If no user is given, the current user is used.
+fn parse_location(url: &str) -> Result<(String, String, u16, PathBuf)> {
+    let (user_host, path) = url
+        .split_once(':')
+        .ok_or_else(|| anyhow!("sftp location {url} must be given as [user@]host[:port]:path"))?;
+    let (user, host) = match user_host.split_once('@') {
+        Some((user, host)) => (user.to_string(), host),
+        None => (std::env::var("USER")?, user_host),
+    };
+    let (port, path) = match path.split_once(':') {
+        Some((port, path)) if !port.is_empty() && port.bytes().all(|b| b.is_ascii_digit()) => {
+            (port.parse()?, path)
+        }
+        _ => (22, path),
+    };
+    Ok((user, host.to_string(), port, path.into()))
+}
+
+// the path of the file within the repository located at base
+fn file_path(base: &Path, tpe: FileType, id: &Id) -> PathBuf {
+    let hex_id = id.to_hex();
+    match tpe {
+        FileType::Config => base.join("config"),
+        FileType::Pack => base.join("data").join(&hex_id[0..2]).join(hex_id),
+        _ => base.join(tpe.name()).join(hex_id),
+    }
+}
+
+// replace the placeholders %file, %type and %id within the command; the values are quoted as the
+// command is interpreted by the shell on the remote host
+fn remote_command(command: &str, tpe: FileType, id: &Id, filename: &Path) -> String {
+    let id = id.to_hex();
+    let patterns = &["%file", "%type", "%id"];
+    let ac = AhoCorasick::new(patterns);
+    let replace_with = &[
+        shell_quote(&filename.to_string_lossy()),
+        shell_quote(tpe.name()),
+        shell_quote(&id),
+    ];
+    ac.replace_all(command, replace_with)
+}
+
+// quote the value for a POSIX shell unless it only contains characters without special meaning
+fn shell_quote(value: &str) -> String {
+    let is_safe = |c: char| c.is_ascii_alphanumeric() || "_-./:,+=@%".contains(c);
+    if !value.is_empty() && value.chars().all(is_safe) {
+        value.to_string()
+    } else {
+        format!("'{}'", value.replace('\'', "'\\''"))
+    }
+}
+
+impl ReadBackend for SftpBackend {
+    fn location(&self) -> String {
+        let mut location = format!("sftp:{}@{}:", self.user, self.host);
+        if self.port != 22 {
+            location.push_str(&format!("{}:", self.port));
+        }
+        location.push_str(&self.path.to_string_lossy());
+        location
+    }
+
+    fn set_option(&mut self, option: &str, value: &str) -> Result<()> {
+        match option {
+            // the commands are run on the remote host
+            "post-create-command" => {
+                self.post_create_command = Some(value.to_string());
+            }
+            "post-delete-command" => {
+                self.post_delete_command = Some(value.to_string());
+            }
+            "timeout" => {
+                let timeout = humantime::Duration::from_str(value)?;
+                self.session
+                    .set_timeout(timeout.as_millis().try_into().unwrap_or(u32::MAX));
+            }
+            opt => {
+                warn!("Option {opt} is not supported! Ignoring it.");
+            }
+        }
+        Ok(())
+    }
+
+    fn list_with_size(&self, tpe: FileType) -> Result<Vec<(Id, u32)>> {
+        trace!("listing tpe: {tpe:?}");
+        if tpe == FileType::Config {
+            let path = self.path.join("config");
+            return Ok(match self.sftp.stat(&path) {
+                Ok(stat) => vec![(Id::default(), stat.size.unwrap_or(0).try_into()?)],
+                Err(_) => Vec::new(),
+            });
+        }
+
+        let path = self.path.join(tpe.name());
+        if tpe != FileType::Pack {
+            return self.list_dir(&path);
+        }
+
+        let mut list = Vec::new();
+        for (dir, stat) in self.sftp.readdir(&path)? {
+            if stat.is_dir() {
+                list.extend(self.list_dir(&dir)?);
+            }
+        }
+        Ok(list)
+    }
+
+    fn read_full(&self, tpe: FileType, id: &Id) -> Result<Bytes> {
+        trace!("reading tpe: {tpe:?}, id: {id}");
+        let mut file = self.sftp.open(self.path(tpe, id))?;
+        let mut vec = Vec::new();
+        file.read_to_end(&mut vec)?;
+        Ok(vec.into())
+    }
+
+    fn read_partial(
+        &self,
+        tpe: FileType,
+        id: &Id,
+        _cacheable: bool,
+        offset: u32,
+        length: u32,
+    ) -> Result<Bytes> {
+        trace!("reading tpe: {tpe:?}, id: {id}, offset: {offset}, length: {length}");
+        let mut file = self.sftp.open(self.path(tpe, id))?;
+        file.seek(SeekFrom::Start(u64::from(offset)))?;
+        let mut vec = vec![0; length.try_into()?];
+        file.read_exact(&mut vec)?;
+        Ok(vec.into())
+    }
+}
+
+impl WriteBackend for SftpBackend {
+    fn create(&self) -> Result<()> {
+        trace!("creating repo at {:?}", self.path);
+
+        // create all parent directories of the repository path
+        let mut path = PathBuf::new();
+        for component in self.path.components() {
+            path.push(component);
+            self.create_dir(&path)?;
+        }
+        for tpe in ALL_FILE_TYPES {
+            self.create_dir(&self.path.join(tpe.name()))?;
+        }
+        for i in 0u8..=255 {
+            self.create_dir(&self.path.join("data").join(hex::encode([i])))?;
+        }
+        Ok(())
+    }
+
+    fn write_bytes(&self, tpe: FileType, id: &Id, _cacheable: bool, buf: Bytes) -> Result<()> {
+        trace!("writing tpe: {:?}, id: {}", &tpe, &id);
+        let filename = self.path(tpe, id);
+        // write to a temporary file and rename it afterwards, so that no partial files are left
+        let mut tmp_filename = filename.clone().into_os_string();
+        tmp_filename.push(".tmp");
+        let tmp_filename = PathBuf::from(tmp_filename);
+
+        let mut file = self.sftp.create(&tmp_filename)?;
+        file.write_all(&buf)?;
+        file.fsync()?;
+        drop(file);
+
+        if let Err(err) = self.sftp.rename(&tmp_filename, &filename, None) {
+            // servers only supporting SFTP v3 refuse to overwrite an existing file
+            if self.sftp.stat(&filename).is_err() {
+                return Err(err.into());
+            }
+            self.sftp.unlink(&filename)?;
+            self.sftp.rename(&tmp_filename, &filename, None)?;
+        }
+
+        if let Some(command) = &self.post_create_command {
+            if let Err(err) = self.call_command(tpe, id, &filename, command) {
+                warn!("post-create: {err}");
+            }
+        }
+        Ok(())
+    }
+
+    fn remove(&self, tpe: FileType, id: &Id, _cacheable: bool) -> Result<()> {
+        trace!("removing tpe: {:?}, id: {}", &tpe, &id);
+        let filename = self.path(tpe, id);
+        self.sftp.unlink(&filename)?;
+        if let Some(command) = &self.post_delete_command {
+            if let Err(err) = self.call_command(tpe, id, &filename, command) {
+                warn!("post-delete: {err}");
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn location() -> Result<()> {
+        assert_eq!(
+            parse_location("user@host:2222:/repo")?,
+            (
+                "user".to_string(),
+                "host".to_string(),
+                2222,
+                PathBuf::from("/repo")
+            )
+        );
+        assert_eq!(
+            parse_location("user@host:repo:2222")?,
+            (
+                "user".to_string(),
+                "host".to_string(),
+                22,
+                PathBuf::from("repo:2222")
+            )
+        );
+        let (_, host, port, path) = parse_location("user@host::/repo")?;
+        assert_eq!(
+            (host.as_str(), port, path),
+            ("host", 22, PathBuf::from(":/repo"))
+        );
+        assert!(parse_location("host").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn paths() {
+        let id = Id::random();
+        let id_hex = id.to_hex();
+        let base = Path::new("/repo");
+        assert_eq!(
+            file_path(base, FileType::Config, &id),
+            PathBuf::from("/repo/config")
+        );
+        assert_eq!(
+            file_path(base, FileType::Pack, &id),
+            base.join("data").join(&id_hex[0..2]).join(id_hex.as_str())
+        );
+        assert_eq!(
+            file_path(base, FileType::Snapshot, &id),
+            base.join("snapshots").join(id_hex.as_str())
+        );
+    }
+
+    #[test]
+    fn hook_command() {
+        let id = Id::random();
+        let file = file_path(Path::new("/repo"), FileType::Index, &id);
+        assert_eq!(
+            remote_command("par2create %file # %type %id", FileType::Index, &id, &file),
+            format!(
+                "par2create /repo/index/{id} # index {id}",
+                id = id.to_hex().as_str()
+            )
+        );
+        let file = file_path(Path::new("/my repo/it's"), FileType::Index, &id);
+        assert_eq!(
+            remote_command("par2create %file", FileType::Index, &id, &file),
+            format!(
+                "par2create '/my repo/it'\\''s/index/{id}'",
+                id = id.to_hex().as_str()
+            )
+        );
+        assert_eq!(shell_quote("$(rm -rf /)"), "'$(rm -rf /)'");
+        assert_eq!(shell_quote(""), "''");
+    }
+}
@@ -1,33 +1,46 @@
-use std::io::{stdin, Stdin};
-use std::path::Path;
+use std::io::{stdin, Read, Stdin};
+use std::path::PathBuf;
 
 use anyhow::Result;
+use chrono::Local;
 
 use super::{node::Metadata, node::NodeType, Node, ReadSource};
 use super::{ReadSourceEntry, ReadSourceOpen};
 
-pub struct StdinSource {
-    finished: bool,
+/// [`StdinSource`] is a [`ReadSource`] which contains a single file read from stdin or from any
+/// other reader, e.g. the output of a command.
+pub struct StdinSource<R> {
+    path: PathBuf,
+    reader: Option<R>,
 }
 
-impl StdinSource {
-    pub fn new() -> Result<Self> {
-        Ok(Self { finished: false })
+impl StdinSource<Stdin> {
+    pub fn new(path: PathBuf) -> Result<Self> {
+        Ok(Self::from_reader(stdin(), path))
     }
 }
 
-pub struct OpenStdin();
+impl<R> StdinSource<R> {
+    pub fn from_reader(reader: R, path: PathBuf) -> Self {
+        Self {
+            path,
+            reader: Some(reader),
+        }
+    }
+}
+
+pub struct OpenStdin<R>(R);
 
-impl ReadSourceOpen for OpenStdin {
-    type Reader = Stdin;
+impl<R: Read + Send + 'static> ReadSourceOpen for OpenStdin<R> {
+    type Reader = R;
 
     fn open(self) -> Result<Self::Reader> {
-        Ok(stdin())
+        Ok(self.0)
     }
 }
 
-impl ReadSource for StdinSource {
-    type Open = OpenStdin;
+impl<R: Read + Send + 'static> ReadSource for StdinSource<R> {
+    type Open = OpenStdin<R>;
     type Iter = Self;
 
     fn size(&self) -> Result<Option<u64>> {
@@ -39,25 +52,41 @@ impl ReadSource for StdinSource {
     }
 }
 
-impl Iterator for StdinSource {
-    type Item = Result<ReadSourceEntry<OpenStdin>>;
+impl<R: Read + Send + 'static> Iterator for StdinSource<R> {
+    type Item = Result<ReadSourceEntry<OpenStdin<R>>>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.finished {
-            return None;
-        }
-        self.finished = true;
+        let reader = self.reader.take()?;
+
+        // the file is created now and belongs to the current user
+        #[cfg(not(windows))]
+        let (uid, gid, user, group) = (
+            Some(users::get_current_uid()),
+            Some(users::get_current_gid()),
+            users::get_current_username().map(|name| name.to_string_lossy().to_string()),
+            users::get_current_groupname().map(|name| name.to_string_lossy().to_string()),
+        );
+        #[cfg(windows)]
+        let (uid, gid, user, group) = (None, None, None, None);
+
+        let now = Local::now();
+        let meta = Metadata {
+            mode: Some(0o644),
+            mtime: Some(now),
+            atime: Some(now),
+            ctime: Some(now),
+            uid,
+            gid,
+            user,
+            group,
+            ..Default::default()
+        };
 
+        let name = self.path.file_name().unwrap_or(self.path.as_os_str());
         Some(Ok(ReadSourceEntry {
-            path: Path::new("stdin").to_path_buf(),
-            node: Node::new(
-                "stdin".to_string(),
-                NodeType::File,
-                Metadata::default(),
-                None,
-                None,
-            ),
-            open: Some(OpenStdin()),
+            path: self.path.clone(),
+            node: Node::new_node(name, NodeType::File, meta),
+            open: Some(OpenStdin(reader)),
         }))
     }
 }
@@ -0,0 +1,466 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{self, stdin, Read, Seek, SeekFrom, Take};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Local, TimeZone, Utc};
+use log::*;
+use tar::{Archive, Entry, EntryType};
+use tempfile::{NamedTempFile, TempPath};
+
+#[cfg(not(windows))]
+use super::mapper;
+use super::node::{ExtendedAttribute, Metadata, NodeType};
+use super::{Node, ReadSource, ReadSourceEntry, ReadSourceOpen};
+
+/// [`TarSource`] is a [`ReadSource`] which contains all entries of a tar archive.
+///
+/// The archiver needs the entries sorted by path, so all headers are read when creating the
+/// source. File contents are then read directly from their position within the archive; an
+/// archive read from stdin is therefore first saved into a temporary file.
+pub struct TarSource {
+    entries: Vec<TarEntry>,
+}
+
+type TarEntry = ReadSourceEntry<OpenTarFile>;
+
+/// A file containing file contents, either the tar archive or a temporary file.
+enum DataFile {
+    Path(PathBuf),
+    Temp(TempPath),
+}
+
+impl AsRef<Path> for DataFile {
+    fn as_ref(&self) -> &Path {
+        match self {
+            Self::Path(path) => path,
+            Self::Temp(path) => path,
+        }
+    }
+}
+
+impl DataFile {
+    fn open(&self) -> Result<File> {
+        let path = self.as_ref();
+        File::open(path).with_context(|| format!("Unable to open {}", path.display()))
+    }
+}
+
+/// The contents of a file are located at `offset` within the data file
+#[derive(Clone)]
+pub struct OpenTarFile {
+    file: Arc<DataFile>,
+    offset: u64,
+    size: u64,
+}
+
+impl ReadSourceOpen for OpenTarFile {
+    type Reader = Take<File>;
+
+    fn open(self) -> Result<Self::Reader> {
+        let mut file = self.file.open()?;
+        file.seek(SeekFrom::Start(self.offset))?;
+        Ok(file.take(self.size))
+    }
+}
+
+impl TarSource {
+    /// Read the tar archive from the given path; use - to read from stdin.
+    pub fn new(path: &Path) -> Result<Self> {
+        let file = if path == Path::new("-") {
+            let mut temp = NamedTempFile::new()?;
+            io::copy(&mut stdin(), &mut temp).context("error reading tar archive from stdin")?;
+            DataFile::Temp(temp.into_temp_path())
+        } else {
+            DataFile::Path(path.to_path_buf())
+        };
+        let mut archive = Archive::new(file.open()?);
+        let file = Arc::new(file);
+
+        // later entries replace earlier ones with the same path, as if the archive was extracted
+        let mut entries = BTreeMap::new();
+        // contents of files which can be referenced by hard links
+        let mut contents = HashMap::new();
+
+        for entry in archive.entries_with_seek()? {
+            let mut entry = entry?;
+            let raw_path = entry.path()?.to_path_buf();
+            let Some(path) = sanitize_path(&raw_path) else {
+                warn!(
+                    "{}: invalid path in tar archive, skipping",
+                    raw_path.display()
+                );
+                continue;
+            };
+            let Some(name) = path.file_name().map(ToOwned::to_owned) else {
+                // the root directory is not saved in the snapshot
+                continue;
+            };
+
+            let pax = pax_extensions(&mut entry)?;
+            if pax.iter().any(|(key, _)| key.starts_with("GNU.sparse.")) {
+                warn!(
+                    "{}: sparse files in pax format are not supported, skipping",
+                    path.display()
+                );
+                continue;
+            }
+
+            let mut open = None;
+            let node_type = match entry.header().entry_type() {
+                EntryType::Regular | EntryType::Continuous => {
+                    open = Some(OpenTarFile {
+                        file: file.clone(),
+                        offset: entry.raw_file_position(),
+                        size: entry.size(),
+                    });
+                    NodeType::File
+                }
+                EntryType::GNUSparse => {
+                    // the contents are not contiguous within the archive, so save them separately
+                    let mut temp = NamedTempFile::new()?;
+                    let size = io::copy(&mut entry, &mut temp)?;
+                    open = Some(OpenTarFile {
+                        file: Arc::new(DataFile::Temp(temp.into_temp_path())),
+                        offset: 0,
+                        size,
+                    });
+                    NodeType::File
+                }
+                EntryType::Link => {
+                    let target = link_name(&entry)?;
+                    match sanitize_path(&target).and_then(|target| contents.get(&target)) {
+                        Some(target) => {
+                            open = Some(OpenTarFile::clone(target));
+                            NodeType::File
+                        }
+                        None => {
+                            warn!(
+                                "{}: hard link target {} not found in tar archive, skipping",
+                                path.display(),
+                                target.display()
+                            );
+                            continue;
+                        }
+                    }
+                }
+                EntryType::Directory => NodeType::Dir,
+                EntryType::Symlink => NodeType::Symlink {
+                    linktarget: link_name(&entry)?.to_string_lossy().to_string(),
+                },
+                EntryType::Block => NodeType::Dev {
+                    device: device_id(&entry)?,
+                },
+                EntryType::Char => NodeType::Chardev {
+                    device: device_id(&entry)?,
+                },
+                EntryType::Fifo => NodeType::Fifo,
+                EntryType::XGlobalHeader => continue,
+                tpe => {
+                    warn!(
+                        "{}: unsupported entry type {tpe:?} in tar archive, skipping",
+                        path.display()
+                    );
+                    continue;
+                }
+            };
+
+            let mut meta = metadata(&entry, &pax, &node_type)?;
+            if let Some(open) = &open {
+                meta.size = open.size;
+                contents.insert(path.clone(), open.clone());
+            }
+            let node = Node::new_node(&name, node_type, meta);
+            entries.insert(path.clone(), ReadSourceEntry { path, node, open });
+        }
+
+        Ok(Self {
+            entries: entries.into_values().collect(),
+        })
+    }
+
+    /// The latest modification time of all entries of the archive
+    pub fn latest_mtime(&self) -> Option<DateTime<Local>> {
+        self.entries
+            .iter()
+            .filter_map(|entry| entry.node.meta.mtime)
+            .max()
+    }
+}
+
+impl ReadSource for TarSource {
+    type Open = OpenTarFile;
+    type Iter = std::iter::Map<std::vec::IntoIter<TarEntry>, fn(TarEntry) -> Result<TarEntry>>;
+
+    fn size(&self) -> Result<Option<u64>> {
+        Ok(Some(
+            self.entries
+                .iter()
+                .filter_map(|entry| entry.open.as_ref())
+                .map(|open| open.size)
+                .sum(),
+        ))
+    }
+
+    fn entries(self) -> Self::Iter {
+        self.entries.into_iter().map(Ok)
+    }
+}
+
+// the path relative to the archive root; None if the path leaves the archive root
+fn sanitize_path(path: &Path) -> Option<PathBuf> {
+    let mut result = PathBuf::new();
+    for comp in path.components() {
+        match comp {
+            Component::Normal(name) => result.push(name),
+            Component::ParentDir => return None,
+            _ => {}
+        }
+    }
+    Some(result)
+}
+
+fn link_name<R: Read>(entry: &Entry<'_, R>) -> Result<PathBuf> {
+    Ok(entry
+        .link_name()?
+        .ok_or_else(|| anyhow!("link without target"))?
+        .to_path_buf())
+}
+
+// combine major and minor number into a linux device id
+fn device_id<R: Read>(entry: &Entry<'_, R>) -> Result<u64> {
+    let header = entry.header();
+    let major = u64::from(header.device_major()?.unwrap_or_default());
+    let minor = u64::from(header.device_minor()?.unwrap_or_default());
+    Ok(((major & 0xfffff000) << 32)
+        | ((major & 0xfff) << 8)
+        | ((minor & 0xffffff00) << 12)
+        | (minor & 0xff))
+}
+
+// the pax extensions of the entry as key/value pairs
+fn pax_extensions<R: Read>(entry: &mut Entry<'_, R>) -> Result<Vec<(String, Vec<u8>)>> {
+    let mut result = Vec::new();
+    if let Some(extensions) = entry.pax_extensions()? {
+        for extension in extensions {
+            let extension = extension?;
+            if let Ok(key) = extension.key() {
+                result.push((key.to_string(), extension.value_bytes().to_vec()));
+            }
+        }
+    }
+    Ok(result)
+}
+
+fn metadata<R: Read>(
+    entry: &Entry<'_, R>,
+    pax: &[(String, Vec<u8>)],
+    node_type: &NodeType,
+) -> Result<Metadata> {
+    let mut meta = Metadata::default();
+    let mut mtime = (entry.header().mtime()?, 0);
+
+    for (key, value) in pax {
+        if let Some(name) = key.strip_prefix("SCHILY.xattr.") {
+            meta.extended_attributes.push(ExtendedAttribute {
+                name: name.to_string(),
+                value: value.clone(),
+            });
+        } else if key == "mtime" {
+            // pax saves the modification time with subsecond precision
+            if let Some(time) = std::str::from_utf8(value).ok().and_then(parse_pax_time) {
+                mtime = time;
+            }
+        }
+    }
+
+    let header = entry.header();
+    meta.mtime = Utc
+        .timestamp_opt(i64::try_from(mtime.0)?, mtime.1)
+        .single()
+        .map(|dt| dt.with_timezone(&Local));
+    // tar doesn't save the access time
+    meta.atime = meta.mtime;
+    meta.uid = header.uid()?.try_into().ok();
+    meta.gid = header.gid()?.try_into().ok();
+    meta.user = header
+        .username()
+        .ok()
+        .flatten()
+        .filter(|name| !name.is_empty())
+        .map(ToString::to_string);
+    meta.group = header
+        .groupname()
+        .ok()
+        .flatten()
+        .filter(|name| !name.is_empty())
+        .map(ToString::to_string);
+
+    #[cfg(not(windows))]
+    {
+        let file_type = match node_type {
+            NodeType::File => mapper::S_IFREG,
+            NodeType::Dir => mapper::S_IFDIR,
+            NodeType::Symlink { .. } => mapper::S_IFLNK,
+            NodeType::Dev { .. } => mapper::S_IFBLK,
+            NodeType::Chardev { .. } => mapper::S_IFCHR,
+            NodeType::Fifo => mapper::S_IFIFO,
+            NodeType::Socket => mapper::S_IFSOCK,
+        };
+        meta.mode = Some(mapper::map_mode_to_go(
+            file_type | (header.mode()? & 0o7777),
+        ));
+    }
+    #[cfg(windows)]
+    let _ = node_type;
+
+    Ok(meta)
+}
+
+// parse a pax time like "1700000000.123456789" into seconds and nanoseconds
+fn parse_pax_time(time: &str) -> Option<(u64, u32)> {
+    let (secs, frac) = time.split_once('.').unwrap_or((time, ""));
+    let frac: String = frac.chars().chain("000000000".chars()).take(9).collect();
+    Some((secs.parse().ok()?, frac.parse().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tar::{Builder, Header};
+
+    #[test]
+    fn pax_time() {
+        assert_eq!(parse_pax_time("1600000000"), Some((1600000000, 0)));
+        assert_eq!(
+            parse_pax_time("1600000000.25"),
+            Some((1600000000, 250000000))
+        );
+        assert_eq!(
+            parse_pax_time("1600000000.1234567891"),
+            Some((1600000000, 123456789))
+        );
+        assert_eq!(parse_pax_time("-1.5"), None);
+    }
+
+    #[test]
+    fn sanitize() {
+        assert_eq!(
+            sanitize_path(Path::new("./a/b")),
+            Some(PathBuf::from("a/b"))
+        );
+        assert_eq!(
+            sanitize_path(Path::new("/a/./b/")),
+            Some(PathBuf::from("a/b"))
+        );
+        assert_eq!(sanitize_path(Path::new("a/../../b")), None);
+    }
+
+    // a pax record is prefixed by its total length including the length itself
+    fn pax_record(key: &str, value: &[u8]) -> Vec<u8> {
+        let rest = key.len() + value.len() + 3;
+        let mut len = rest + rest.to_string().len();
+        len = rest + len.to_string().len();
+        let mut record = format!("{len} {key}=").into_bytes();
+        record.extend(value);
+        record.push(b'\n');
+        record
+    }
+
+    fn header(entry_type: EntryType, size: u64) -> Header {
+        let mut header = Header::new_gnu();
+        header.set_entry_type(entry_type);
+        header.set_size(size);
+        header.set_mode(0o644);
+        header.set_mtime(1_000);
+        header.set_uid(1_000);
+        header.set_gid(1_000);
+        header
+    }
+
+    #[test]
+    fn tar_entries() -> Result<()> {
+        let mut builder = Builder::new(Vec::new());
+        let mut dir = header(EntryType::Directory, 0);
+        dir.set_mode(0o755);
+        builder.append_data(&mut dir, "dir/", io::empty())?;
+
+        let pax = [
+            pax_record("mtime", b"1600000000.5"),
+            pax_record("SCHILY.xattr.user.test", b"value"),
+        ]
+        .concat();
+        let mut ext = header(EntryType::XHeader, pax.len() as u64);
+        builder.append_data(&mut ext, "PaxHeader/a", pax.as_slice())?;
+        builder.append_data(&mut header(EntryType::Regular, 5), "dir/a", &b"first"[..])?;
+        builder.append_link(&mut header(EntryType::Link, 0), "dir/link", "dir/a")?;
+
+        // the later entry wins
+        builder.append_data(&mut header(EntryType::Regular, 3), "dir/b", &b"old"[..])?;
+        builder.append_data(&mut header(EntryType::Regular, 3), "dir/b", &b"new"[..])?;
+
+        let mut dev = header(EntryType::Char, 0);
+        dev.set_device_major(1)?;
+        dev.set_device_minor(3)?;
+        builder.append_data(&mut dev, "dev", io::empty())?;
+
+        // the builder refuses paths leaving the archive root, so write the name directly
+        let mut evil = header(EntryType::Regular, 4);
+        evil.as_old_mut().name[..7].copy_from_slice(b"../evil");
+        evil.set_cksum();
+        builder.append(&evil, &b"evil"[..])?;
+
+        let mut file = NamedTempFile::new()?;
+        io::Write::write_all(&mut file, &builder.into_inner()?)?;
+        let entries = TarSource::new(file.path())?.entries;
+
+        let paths: Vec<_> = entries.iter().map(|e| e.path.to_str().unwrap()).collect();
+        assert_eq!(paths, ["dev", "dir", "dir/a", "dir/b", "dir/link"]);
+        let content = |idx: usize| -> Result<String> {
+            let mut content = String::new();
+            _ = entries[idx]
+                .open
+                .clone()
+                .unwrap()
+                .open()?
+                .read_to_string(&mut content)?;
+            Ok(content)
+        };
+
+        assert_eq!(
+            entries[0].node.node_type,
+            NodeType::Chardev { device: 0x103 }
+        );
+        assert!(entries[0].open.is_none());
+        assert_eq!(entries[1].node.node_type, NodeType::Dir);
+        assert_eq!(entries[1].node.meta.mtime.unwrap().timestamp(), 1_000);
+
+        let a = &entries[2].node.meta;
+        assert_eq!(content(2)?, "first");
+        assert_eq!(a.size, 5);
+        assert_eq!(a.uid, Some(1_000));
+        let mtime = a.mtime.unwrap();
+        assert_eq!(
+            (mtime.timestamp(), mtime.timestamp_subsec_nanos()),
+            (1_600_000_000, 500_000_000)
+        );
+        assert_eq!(
+            a.extended_attributes,
+            [ExtendedAttribute {
+                name: "user.test".to_string(),
+                value: b"value".to_vec(),
+            }]
+        );
+
+        assert_eq!(content(3)?, "new");
+        assert_eq!(entries[4].node.node_type, NodeType::File);
+        assert_eq!(entries[4].node.meta.size, 5);
+        assert_eq!(content(4)?, "first");
+        // the pax extensions only apply to the following entry
+        assert!(entries[4].node.meta.extended_attributes.is_empty());
+        Ok(())
+    }
+}
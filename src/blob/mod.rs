@@ -1,9 +1,11 @@
 mod packer;
+mod rewriter;
 mod tree;
 use std::ops::Add;
 
 pub use crate::backend::node::*;
 pub use packer::*;
+pub use rewriter::*;
 pub use tree::*;
 
 use derive_more::Constructor;
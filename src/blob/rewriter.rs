@@ -0,0 +1,126 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+use log::*;
+
+use crate::backend::DecryptWriteBackend;
+use crate::id::Id;
+use crate::index::IndexedBackend;
+
+use super::{Node, Packer, Tree};
+
+/// Modifies the nodes of the trees rewritten by a [`TreeRewriter`]
+pub trait NodeVisitor {
+    /// Called for each node before its subtree (if any) is rewritten; `path` is the path of the node.
+    /// Returns the (possibly modified) node or `None` to remove the node.
+    fn visit(&mut self, path: &Path, node: Node) -> Option<Node>;
+
+    /// Called for each dir node whose tree is missing or cannot be loaded. The tree is replaced by an
+    /// empty tree; return an error to abort rewriting instead.
+    fn missing_tree(&mut self, path: &Path, node: &mut Node) -> Result<()>;
+
+    /// Whether the result of visiting depends on the paths of the nodes. If not, each tree is only
+    /// rewritten once, regardless of the paths where it is contained.
+    fn path_dependent(&self) -> bool {
+        true
+    }
+}
+
+/// The result of rewriting a tree
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rewritten {
+    /// The tree is unchanged
+    Unchanged,
+    /// The tree has been changed and saved with the given id
+    Changed(Id),
+    /// The tree is missing or cannot be loaded and has been replaced by the empty tree with the given id
+    Missing(Id),
+}
+
+/// [`TreeRewriter`] rewrites trees recursively using a [`NodeVisitor`] and saves all changed trees
+pub struct TreeRewriter<'a, I: IndexedBackend, BE: DecryptWriteBackend> {
+    index: &'a I,
+    packer: &'a Packer<BE>,
+    dry_run: bool,
+    // trees which have already been rewritten; the path is only set for path dependent visitors
+    replaced: HashMap<(Option<PathBuf>, Option<Id>), Rewritten>,
+}
+
+impl<'a, I: IndexedBackend, BE: DecryptWriteBackend> TreeRewriter<'a, I, BE> {
+    /// Create a new [`TreeRewriter`]; if `dry_run` is set, changed trees are not saved.
+    pub fn new(index: &'a I, packer: &'a Packer<BE>, dry_run: bool) -> Self {
+        Self {
+            index,
+            packer,
+            dry_run,
+            replaced: HashMap::new(),
+        }
+    }
+
+    /// Rewrite the tree `id` located at `path`, where `None` means the tree is missing.
+    pub fn rewrite_tree(
+        &mut self,
+        visitor: &mut impl NodeVisitor,
+        path: &Path,
+        id: Option<Id>,
+    ) -> Result<Rewritten> {
+        let key = (visitor.path_dependent().then(|| path.to_path_buf()), id);
+        if let Some(r) = self.replaced.get(&key) {
+            return Ok(*r);
+        }
+
+        let tree = id
+            .ok_or_else(|| anyhow!("no tree given"))
+            .and_then(|id| Tree::from_backend(self.index, id));
+        let rewritten = match tree {
+            Err(err) => {
+                warn!("tree {} could not be loaded: {err}", path.display());
+                Rewritten::Missing(self.save(&Tree::new())?)
+            }
+            Ok(tree) => {
+                let mut changed = false;
+                let mut new_tree = Tree::new();
+                for node in tree {
+                    let node_path = path.join(node.name());
+                    let original = node.clone();
+                    let Some(mut node) = visitor.visit(&node_path, node) else {
+                        changed = true;
+                        continue;
+                    };
+                    changed |= node != original;
+                    if node.is_dir() {
+                        match self.rewrite_tree(visitor, &node_path, node.subtree)? {
+                            Rewritten::Unchanged => {}
+                            Rewritten::Changed(id) => {
+                                node.subtree = Some(id);
+                                changed = true;
+                            }
+                            Rewritten::Missing(id) => {
+                                visitor.missing_tree(&node_path, &mut node)?;
+                                node.subtree = Some(id);
+                                changed = true;
+                            }
+                        }
+                    }
+                    new_tree.add(node);
+                }
+                match changed {
+                    false => Rewritten::Unchanged,
+                    true => Rewritten::Changed(self.save(&new_tree)?),
+                }
+            }
+        };
+        self.replaced.insert(key, rewritten);
+        Ok(rewritten)
+    }
+
+    // save the tree, if it is not yet present in the repository
+    fn save(&self, tree: &Tree) -> Result<Id> {
+        let (chunk, id) = tree.serialize()?;
+        if !self.index.has_tree(&id) && !self.dry_run {
+            self.packer.add(&chunk, &id)?;
+        }
+        Ok(id)
+    }
+}
@@ -1,5 +1,5 @@
 use std::cmp::Ordering;
-use std::collections::{BinaryHeap, HashSet};
+use std::collections::{BinaryHeap, HashSet, VecDeque};
 use std::ffi::{OsStr, OsString};
 use std::mem;
 use std::path::{Component, Path, PathBuf, Prefix};
@@ -8,12 +8,13 @@ use std::str;
 use anyhow::{anyhow, bail, Result};
 use crossbeam_channel::{bounded, unbounded, Receiver, Sender};
 use derive_getters::Getters;
-use indicatif::ProgressBar;
+use ignore::overrides::Override;
 use serde::{Deserialize, Deserializer, Serialize};
 
 use crate::crypto::hash;
 use crate::id::Id;
 use crate::index::IndexedBackend;
+use crate::progress::Progress;
 use crate::repofile::SnapshotSummary;
 
 use super::{Metadata, Node, NodeType};
@@ -112,6 +113,12 @@ where
     inner: std::vec::IntoIter<Node>,
     path: PathBuf,
     be: BE,
+    // only nodes which are not ignored by the overrides are returned; the overrides are matched
+    // against the path of the node joined to the given base path
+    overrides: Option<(PathBuf, Override)>,
+    // dirs not matched by any whitelist glob; they are only returned once a node beneath them is
+    pending: Vec<(PathBuf, Node)>,
+    ready: VecDeque<(PathBuf, Node)>,
 }
 
 impl<BE> NodeStreamer<BE>
@@ -131,8 +138,21 @@ where
             open_iterators: Vec::new(),
             path: PathBuf::new(),
             be,
+            overrides: None,
+            pending: Vec::new(),
+            ready: VecDeque::new(),
         })
     }
+
+    /// Create a [`NodeStreamer`] which skips nodes (including the contents of dirs) which are
+    /// ignored by the given glob overrides. The paths are matched as if they were located in `base`.
+    /// If whitelist globs are given, dirs which are not matched themselves are only returned if
+    /// some node beneath them is returned.
+    pub fn new_with_glob(be: BE, node: &Node, base: &Path, overrides: Override) -> Result<Self> {
+        let mut streamer = Self::new(be, node)?;
+        streamer.overrides = Some((base.to_path_buf(), overrides));
+        Ok(streamer)
+    }
 }
 
 type NodeStreamItem = Result<(PathBuf, Node)>;
@@ -145,10 +165,22 @@ where
     type Item = NodeStreamItem;
 
     fn next(&mut self) -> Option<Self::Item> {
+        if let Some(item) = self.ready.pop_front() {
+            return Some(Ok(item));
+        }
         loop {
             match self.inner.next() {
                 Some(node) => {
                     let path = self.path.join(node.name());
+                    let mut defer = false;
+                    if let Some((base, overrides)) = &self.overrides {
+                        let matched = overrides.matched(base.join(&path), node.is_dir());
+                        if matched.is_ignore() {
+                            continue;
+                        }
+                        defer =
+                            matched.is_none() && node.is_dir() && overrides.num_whitelists() > 0;
+                    }
                     if let Some(id) = node.subtree() {
                         self.path.push(node.name());
                         let be = self.be.clone();
@@ -160,11 +192,22 @@ where
                         self.open_iterators.push(old_inner);
                     }
 
-                    return Some(Ok((path, node)));
+                    if defer {
+                        self.pending.push((path, node));
+                        continue;
+                    }
+                    // first return the pending parent dirs
+                    self.ready.extend(self.pending.drain(..));
+                    self.ready.push_back((path, node));
+                    return self.ready.pop_front().map(Ok);
                 }
                 None => match self.open_iterators.pop() {
                     Some(it) => {
                         self.inner = it;
+                        // nothing beneath the dir was returned, so skip it
+                        if matches!(self.pending.last(), Some((path, _)) if path == &self.path) {
+                            _ = self.pending.pop();
+                        }
                         self.path.pop();
                     }
                     None => return None,
@@ -178,8 +221,8 @@ where
 pub struct TreeStreamerOnce {
     visited: HashSet<Id>,
     queue_in: Option<Sender<(PathBuf, Id, usize)>>,
-    queue_out: Receiver<Result<(PathBuf, Tree, usize)>>,
-    p: ProgressBar,
+    queue_out: Receiver<Result<(PathBuf, Id, Tree, usize)>>,
+    p: Progress,
     counter: Vec<usize>,
     finished_ids: usize,
 }
@@ -187,7 +230,7 @@ pub struct TreeStreamerOnce {
 const MAX_TREE_LOADER: usize = 4;
 
 impl TreeStreamerOnce {
-    pub fn new<BE: IndexedBackend>(be: BE, ids: Vec<Id>, p: ProgressBar) -> Result<Self> {
+    pub fn new<BE: IndexedBackend>(be: BE, ids: Vec<Id>, p: Progress) -> Result<Self> {
         p.set_length(ids.len() as u64);
 
         let (out_tx, out_rx) = bounded(MAX_TREE_LOADER);
@@ -200,7 +243,7 @@ impl TreeStreamerOnce {
             std::thread::spawn(move || {
                 for (path, id, count) in in_rx {
                     out_tx
-                        .send(Tree::from_backend(&be, id).map(|tree| (path, tree, count)))
+                        .send(Tree::from_backend(&be, id).map(|tree| (path, id, tree, count)))
                         .unwrap();
                 }
             });
@@ -239,16 +282,15 @@ impl TreeStreamerOnce {
 
 type TreeStreamItem = Result<(PathBuf, Tree)>;
 
-impl Iterator for TreeStreamerOnce {
-    type Item = TreeStreamItem;
-
-    fn next(&mut self) -> Option<Self::Item> {
+impl TreeStreamerOnce {
+    /// Same as `next()`, but also returns the ID of the tree
+    pub fn next_with_id(&mut self) -> Option<Result<(PathBuf, Id, Tree)>> {
         if self.counter.len() == self.finished_ids {
             drop(self.queue_in.take());
             self.p.finish();
             return None;
         }
-        let (path, tree, count) = match self.queue_out.recv() {
+        let (path, id, tree, count) = match self.queue_out.recv() {
             Ok(Ok(res)) => res,
             Err(err) => return Some(Err(err.into())),
             Ok(Err(err)) => return Some(Err(err)),
@@ -269,7 +311,16 @@ impl Iterator for TreeStreamerOnce {
             self.p.inc(1);
             self.finished_ids += 1;
         }
-        Some(Ok((path, tree)))
+        Some(Ok((path, id, tree)))
+    }
+}
+
+impl Iterator for TreeStreamerOnce {
+    type Item = TreeStreamItem;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_with_id()
+            .map(|item| item.map(|(path, _, tree)| (path, tree)))
     }
 }
 
@@ -8,7 +8,7 @@ use crate::cdc::{Polynom, Polynom64, Rabin64, RollingHash64};
 const SPLITMASK: u64 = (1u64 << 20) - 1;
 const KB: usize = 1024;
 const MB: usize = 1024 * KB;
-const MIN_SIZE: usize = 512 * KB;
+pub const MIN_SIZE: usize = 512 * KB;
 const MAX_SIZE: usize = 8 * MB;
 const BUF_SIZE: usize = 64 * KB;
 
@@ -118,7 +118,7 @@ impl<R: Read + Send> Iterator for ChunkIter<R> {
             self.pos += 1;
             self.rabin.slide(byte);
         }
-        self.size_hint -= vec.len();
+        self.size_hint = self.size_hint.saturating_sub(vec.len());
         Some(Ok(vec))
     }
 }
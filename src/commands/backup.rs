@@ -1,9 +1,11 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
 use std::str::FromStr;
 
-use anyhow::{bail, Result};
-use chrono::Local;
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Local};
 use clap::{AppSettings, Parser};
+use itertools::Itertools;
 use log::*;
 use merge::Merge;
 use path_dedot::ParseDot;
@@ -11,13 +13,19 @@ use serde::Deserialize;
 use toml::Value;
 
 use super::{bytes, progress_bytes, progress_counter, RusticConfig};
-use crate::archiver::{Archiver, Parent};
-use crate::backend::{DryRunBackend, LocalSource, LocalSourceOptions, StdinSource};
-use crate::index::IndexBackend;
+use crate::archiver::{Archiver, Parent, DEFAULT_READ_CONCURRENCY};
+use crate::backend::{
+    DecryptFullBackend, DryRunBackend, FileType, LocalSource, LocalSourceOptions, MirrorBackend,
+    ReadBackend, StdinSource, TarSource, WriteBackend,
+};
+use crate::crypto::hash;
+use crate::index::{IndexBackend, IndexedBackend};
+use crate::metrics::backup_metrics;
+use crate::progress::json_summary;
 use crate::repofile::{
-    PathList, SnapshotFile, SnapshotGroup, SnapshotGroupCriterion, SnapshotOptions,
+    KeyFile, PathList, SnapshotFile, SnapshotGroup, SnapshotGroupCriterion, SnapshotOptions,
 };
-use crate::repository::OpenRepository;
+use crate::repository::{parse_command, OpenRepository};
 
 #[derive(Clone, Default, Parser, Deserialize, Merge)]
 #[clap(global_setting(AppSettings::DeriveDisplayOrder))]
@@ -56,11 +64,31 @@ pub(super) struct Opts {
     #[merge(strategy = merge::bool::overwrite_false)]
     ignore_inode: bool,
 
+    /// Number of files to read concurrently. Increase this for fast storage like SSDs [default: 2]
+    #[clap(long, value_name = "N")]
+    read_concurrency: Option<usize>,
+
     /// Set filename to be used when backing up from stdin
     #[clap(long, value_name = "FILENAME", default_value = "stdin")]
     #[merge(skip)]
     stdin_filename: String,
 
+    /// Backup the output of the given command. The backup source is used as filename within the
+    /// snapshot; if the source is -, the stdin-filename is used
+    #[clap(long, value_name = "COMMAND")]
+    stdin_command: Option<String>,
+
+    /// Backup the contents of the tar archive given as source; if the source is -, the archive
+    /// is read from stdin
+    #[clap(long, conflicts_with = "stdin-command")]
+    #[merge(strategy = merge::bool::overwrite_false)]
+    from_tar: bool,
+
+    /// Use the latest modification time of all entries in the tar archive as snapshot time
+    #[clap(long, requires = "from-tar")]
+    #[merge(strategy = merge::bool::overwrite_false)]
+    time_from_tar: bool,
+
     /// Manually set backup path in snapshot
     #[clap(long, value_name = "PATH")]
     as_path: Option<PathBuf>,
@@ -94,6 +122,22 @@ pub(super) struct Opts {
     #[clap(skip)]
     #[merge(skip)]
     source: String,
+
+    /// Command to run before the backup of a source; if it fails, the source is not backed up
+    #[clap(skip)]
+    run_before: Option<String>,
+
+    /// Command to run after a successful backup of a source
+    #[clap(skip)]
+    run_after: Option<String>,
+
+    /// Command to run after the backup of a source failed
+    #[clap(skip)]
+    run_failed: Option<String>,
+
+    /// Command to run after the backup of a source, regardless of whether it was successful
+    #[clap(skip)]
+    run_finally: Option<String>,
 }
 
 pub(super) fn execute(
@@ -132,17 +176,27 @@ pub(super) fn execute(
         }
     };
 
-    let index = IndexBackend::only_full_trees(&repo.dbe, progress_counter(""))?;
-
+    // a write-only key cannot read the repository, so index, snapshots and trees are read from its cache
+    let write_only_cache = match &repo.write_only_cache {
+        Some(cache) => Some(cache.open(&repo.be)?),
+        None if repo.key.is_write_only() => {
+            bail!("a write-only key needs a cache for the backup. Please don't use --no-cache.")
+        }
+        None => None,
+    };
+    let index = IndexBackend::only_full_trees(
+        write_only_cache.as_ref().unwrap_or(&repo.dbe),
+        progress_counter(""),
+    )?;
+
+    let total = sources.len();
+    // sources which failed; the remaining sources are still backed up
+    let mut failed = Vec::new();
+    // the key file of a write-only key is replaced before data is saved for the first time
+    let mut key_replaced = false;
     for source in sources {
         let mut opts = opts.clone();
         let index = index.clone();
-        let backup_stdin = source == PathList::from_string("-", false)?;
-        let backup_path = if backup_stdin {
-            vec![PathBuf::from(&opts.stdin_filename)]
-        } else {
-            source.paths()
-        };
 
         // merge Options from config file, if given
         if let Some(idx) = config_sources.iter().position(|s| s == &source) {
@@ -152,7 +206,9 @@ pub(super) fn execute(
         if let Some(path) = &opts.as_path {
             // as_path only works in combination with a single target
             if source.len() > 1 {
-                bail!("as-path only works with a single target!");
+                error!("backup of {source} failed: as-path only works with a single target!");
+                failed.push(source);
+                continue;
             }
             // merge Options from config file using as_path, if given
             if let Some(path) = path.as_os_str().to_str() {
@@ -165,58 +221,49 @@ pub(super) fn execute(
         // merge "backup" section from config file, if given
         config_file.merge_into("backup", &mut opts)?;
 
-        let be = DryRunBackend::new(repo.dbe.clone(), opts.dry_run);
-        info!("starting to backup {source}...");
-        let as_path = match opts.as_path {
-            None => None,
-            Some(p) => Some(p.parse_dot()?.to_path_buf()),
-        };
-
-        let mut snap = SnapshotFile::new_from_options(opts.snap_opts, time, command.clone())?;
-        match &as_path {
-            Some(p) => snap.paths.set_paths(&[p.to_path_buf()])?,
-            None => snap.paths.set_paths(&backup_path)?,
-        };
-
-        // get suitable snapshot group from snapshot and opts.group_by. This is used to filter snapshots for the parent detection
-        let group = SnapshotGroup::from_sn(
-            &snap,
-            &opts
-                .group_by
-                .unwrap_or_else(|| SnapshotGroupCriterion::from_str("host,label,paths").unwrap()),
-        );
-
-        let parent = match (backup_stdin, opts.force, opts.parent.clone()) {
-            (true, _, _) | (false, true, _) => None,
-            (false, false, None) => {
-                SnapshotFile::latest(&be, |snap| snap.has_group(&group), progress_counter("")).ok()
-            }
-            (false, false, Some(parent)) => SnapshotFile::from_id(&be, &parent).ok(),
-        };
-
-        let parent_tree = match &parent {
-            Some(parent) => {
-                info!("using parent {}", parent.id);
-                snap.parent = Some(parent.id);
-                Some(parent.tree)
+        let source_paths = source.paths().iter().map(|p| p.to_string_lossy()).join(",");
+        let mut env = vec![("RUSTIC_BACKUP_SOURCE".to_string(), source_paths)];
+        let result = run_hook(&opts.run_before, "run-before", &env).and_then(|()| {
+            backup_source(
+                &repo,
+                index,
+                &opts,
+                &source,
+                time,
+                &command,
+                &mut key_replaced,
+            )
+        });
+        let hook_result = match &result {
+            Ok(snap) => {
+                env.push((
+                    "RUSTIC_SNAPSHOT_ID".to_string(),
+                    snap.id.to_hex().as_str().to_string(),
+                ));
+                env.extend(summary_env(snap)?);
+                run_hook(&opts.run_after, "run-after", &env)
             }
-            None => {
-                info!("using no parent");
-                None
+            Err(err) => {
+                env.push(("RUSTIC_ERROR".to_string(), err.to_string()));
+                if let Err(err) = run_hook(&opts.run_failed, "run-failed", &env) {
+                    warn!("{err}");
+                }
+                Ok(())
             }
         };
-
-        let parent = Parent::new(&index, parent_tree, opts.ignore_ctime, opts.ignore_inode);
-
-        let archiver = Archiver::new(be, index, &repo.config, parent, snap)?;
-        let p = progress_bytes("determining size...");
-
-        let snap = if backup_stdin {
-            let src = StdinSource::new()?;
-            archiver.archive(src, &backup_path[0], as_path.as_ref(), &p)?
-        } else {
-            let src = LocalSource::new(opts.ignore_opts.clone(), &backup_path)?;
-            archiver.archive(src, &backup_path[0], as_path.as_ref(), &p)?
+        let finally_result = run_hook(&opts.run_finally, "run-finally", &env);
+        let snap = match (result, hook_result, finally_result) {
+            (Ok(snap), Ok(()), Ok(())) => snap,
+            (result, hook_result, finally_result) => {
+                for err in [result.err(), hook_result.err(), finally_result.err()]
+                    .into_iter()
+                    .flatten()
+                {
+                    error!("backup of {source} failed: {err}");
+                }
+                failed.push(source);
+                continue;
+            }
         };
 
         if opts.json {
@@ -251,5 +298,232 @@ pub(super) fn execute(
         info!("backup of {source} done.");
     }
 
+    if !failed.is_empty() {
+        bail!(
+            "backup of {} of {} source(s) failed: {}",
+            failed.len(),
+            total,
+            failed.iter().join(", ")
+        );
+    }
+
+    Ok(())
+}
+
+// backup a single source and return the saved snapshot
+fn backup_source(
+    repo: &OpenRepository,
+    index: IndexBackend<impl DecryptFullBackend>,
+    opts: &Opts,
+    source: &PathList,
+    time: DateTime<Local>,
+    command: &str,
+    key_replaced: &mut bool,
+) -> Result<SnapshotFile> {
+    let backup_stdin = source == &PathList::from_string("-", false)?;
+    let backup_path = if backup_stdin {
+        vec![PathBuf::from(&opts.stdin_filename)]
+    } else {
+        source.paths()
+    };
+    if opts.stdin_command.is_some() && backup_path.len() > 1 {
+        bail!("stdin-command only works with a single target!");
+    }
+    if opts.from_tar && backup_path.len() > 1 {
+        bail!("from-tar only works with a single target!");
+    }
+    // when backing up the output of a command, save this command in the snapshot
+    let command = opts.stdin_command.as_deref().unwrap_or(command);
+
+    // with a write-only key, the index has been read from the cache which also needs all new files
+    let write_only_cache = repo.key.is_write_only().then(|| index.be().clone());
+    let be = DryRunBackend::new(
+        MirrorBackend::new(repo.dbe.clone(), write_only_cache),
+        opts.dry_run,
+    );
+    info!("starting to backup {source}...");
+    let as_path = match &opts.as_path {
+        None => None,
+        Some(p) => Some(p.parse_dot()?.to_path_buf()),
+    };
+
+    let mut snap =
+        SnapshotFile::new_from_options(opts.snap_opts.clone(), time, command.to_string())?;
+    match &as_path {
+        Some(p) => snap.paths.set_paths(&[p.to_path_buf()])?,
+        None => snap.paths.set_paths(&backup_path)?,
+    };
+
+    // get suitable snapshot group from snapshot and opts.group_by. This is used to filter snapshots for the parent detection
+    let group = SnapshotGroup::from_sn(
+        &snap,
+        &opts
+            .group_by
+            .clone()
+            .unwrap_or_else(|| SnapshotGroupCriterion::from_str("host,label,paths").unwrap()),
+    );
+
+    let no_parent = backup_stdin || opts.stdin_command.is_some() || opts.from_tar;
+    let parent = match (no_parent, opts.force, opts.parent.clone()) {
+        (true, _, _) | (false, true, _) => None,
+        (false, false, None) => SnapshotFile::latest(
+            index.be(),
+            |snap| snap.has_group(&group),
+            progress_counter(""),
+        )
+        .ok(),
+        (false, false, Some(parent)) => SnapshotFile::from_id(index.be(), &parent).ok(),
+    };
+
+    let parent_tree = match &parent {
+        Some(parent) => {
+            info!("using parent {}", parent.id);
+            snap.parent = Some(parent.id);
+            Some(parent.tree)
+        }
+        None => {
+            info!("using no parent");
+            None
+        }
+    };
+
+    let parent = Parent::new(&index, parent_tree, opts.ignore_ctime, opts.ignore_inode);
+
+    if !opts.dry_run && !*key_replaced {
+        replace_write_only_key(repo)?;
+        *key_replaced = true;
+    }
+
+    let mut archiver = Archiver::new(
+        be,
+        index,
+        &repo.config,
+        parent,
+        snap,
+        opts.read_concurrency.unwrap_or(DEFAULT_READ_CONCURRENCY),
+    )?;
+    let p = progress_bytes("determining size...");
+
+    let snap = match (&opts.stdin_command, backup_stdin) {
+        _ if opts.from_tar => {
+            let path = if backup_stdin {
+                Path::new("-")
+            } else {
+                &backup_path[0]
+            };
+            let src = TarSource::new(path)?;
+            if opts.time_from_tar {
+                match src.latest_mtime() {
+                    Some(time) => archiver.set_snapshot_time(time),
+                    None => warn!("tar archive contains no modification times, using current time"),
+                }
+            }
+            // the paths within the tar archive are relative to the archive root
+            archiver.archive(src, Path::new(""), as_path.as_ref(), &p)?
+        }
+        (Some(command), _) => {
+            let commands = parse_command::<()>(command)?.1;
+            if commands.is_empty() {
+                bail!("stdin-command is empty");
+            }
+            debug!("calling {commands:?}...");
+            let mut child = Command::new(commands[0])
+                .args(&commands[1..])
+                .stdout(Stdio::piped())
+                .spawn()
+                .with_context(|| format!("failed to call stdin-command {commands:?}"))?;
+            let stdout = child.stdout.take().expect("stdout should be piped");
+            let src = StdinSource::from_reader(stdout, backup_path[0].clone());
+            if let Err(err) = archiver.archive_entries(src, &backup_path[0], as_path.as_ref(), &p) {
+                // don't leave the command running or as zombie
+                if let Err(err) = child.kill() {
+                    warn!("failed to kill stdin-command {commands:?}: {err}");
+                }
+                let _ = child.wait();
+                return Err(err);
+            }
+
+            // only save the snapshot if the command was successful
+            let status = child.wait()?;
+            if !status.success() {
+                bail!("stdin-command {commands:?} was not successful. {status}");
+            }
+            let snap = archiver.finalize_snapshot()?;
+            p.finish_with_message("done");
+            snap
+        }
+        (None, true) => {
+            let src = StdinSource::new(backup_path[0].clone())?;
+            archiver.archive(src, &backup_path[0], as_path.as_ref(), &p)?
+        }
+        (None, false) => {
+            let src = LocalSource::new(opts.ignore_opts.clone(), &backup_path)?;
+            archiver.archive(src, &backup_path[0], as_path.as_ref(), &p)?
+        }
+    };
+
+    json_summary("backup", &snap);
+    if !opts.dry_run {
+        backup_metrics(&snap, &group)?;
+    }
+    Ok(snap)
+}
+
+// replace the key file of a write-only key by a key file containing the next key, so data saved in
+// this run cannot be read using the key file anymore
+fn replace_write_only_key(repo: &OpenRepository) -> Result<()> {
+    let Some(file) = &repo.write_only_key else {
+        return Ok(());
+    };
+    let keyfile = file.next()?;
+    let data = serde_json::to_vec(&keyfile)?;
+    let id = hash(&data);
+    repo.be
+        .write_bytes(FileType::Key, &id, false, data.into())?;
+    // only remove the old key file once the new one has been verified
+    file.verify_next(&KeyFile::from_backend(&repo.be, &id)?)
+        .with_context(|| format!("error verifying the new key file {id}"))?;
+    if let Err(err) = repo.be.remove(FileType::Key, &repo.key_id, false) {
+        // a concurrent run may already have replaced the key file
+        if repo.be.list(FileType::Key)?.contains(&repo.key_id) {
+            return Err(err);
+        }
+        warn!("key file {} has already been removed", repo.key_id);
+    }
+    info!("write-only key {} replaced by key {id}", repo.key_id);
     Ok(())
 }
+
+// run the hook command, if given, with the given additional environment variables
+fn run_hook(command: &Option<String>, name: &str, env: &[(String, String)]) -> Result<()> {
+    let Some(command) = command else {
+        return Ok(());
+    };
+    debug!("calling {name} command {command}...");
+    let commands = parse_command::<()>(command)?.1;
+    if commands.is_empty() {
+        bail!("{name} command is empty");
+    }
+    let status = Command::new(commands[0])
+        .args(&commands[1..])
+        .envs(env.iter().map(|(key, value)| (key, value)))
+        .status()
+        .with_context(|| format!("failed to call {name} command {commands:?}"))?;
+    if !status.success() {
+        bail!("{name} command was not successful. {status}");
+    }
+    Ok(())
+}
+
+// environment variables containing the summary of the snapshot, e.g. RUSTIC_FILES_NEW
+fn summary_env(snap: &SnapshotFile) -> Result<Vec<(String, String)>> {
+    let summary = match serde_json::to_value(&snap.summary)? {
+        serde_json::Value::Object(map) => map,
+        _ => return Ok(Vec::new()),
+    };
+    Ok(summary
+        .into_iter()
+        .filter(|(_, value)| value.is_number())
+        .map(|(key, value)| (format!("RUSTIC_{}", key.to_uppercase()), value.to_string()))
+        .collect())
+}
@@ -2,7 +2,6 @@ use std::path::Path;
 
 use anyhow::{anyhow, Result};
 use clap::{Parser, Subcommand};
-use indicatif::ProgressBar;
 
 use super::progress_counter;
 use super::rustic_config::RusticConfig;
@@ -10,7 +9,8 @@ use crate::backend::{DecryptReadBackend, FileType};
 use crate::blob::{BlobType, Tree};
 use crate::id::Id;
 use crate::index::{IndexBackend, IndexedBackend};
-use crate::repofile::{SnapshotFile, SnapshotFilter};
+use crate::progress::Progress;
+use crate::repofile::{split_snapshot_path, SnapshotFile, SnapshotFilter, SnapshotRefGroup};
 use crate::repository::OpenRepository;
 
 #[derive(Parser)]
@@ -32,7 +32,7 @@ enum Command {
     /// Display a snapshot file
     Snapshot(IdOpt),
     /// Display a tree within a snapshot
-    Tree(TreeOpts),
+    Tree(Box<TreeOpts>),
 }
 
 #[derive(Default, Parser)]
@@ -46,6 +46,9 @@ struct TreeOpts {
     #[clap(flatten, help_heading = "SNAPSHOT FILTER OPTIONS (when using latest)")]
     filter: SnapshotFilter,
 
+    #[clap(flatten)]
+    group: SnapshotRefGroup,
+
     /// Snapshot/path of the tree to display
     #[clap(value_name = "SNAPSHOT[:PATH]")]
     snap: String,
@@ -61,7 +64,7 @@ pub(super) fn execute(repo: OpenRepository, opts: Opts, config_file: RusticConfi
         Command::TreeBlob(opt) => cat_blob(be, BlobType::Tree, opt),
         Command::DataBlob(opt) => cat_blob(be, BlobType::Data, opt),
         // special treatment for cating a tree within a snapshot
-        Command::Tree(opts) => cat_tree(be, opts, config_file),
+        Command::Tree(opts) => cat_tree(be, *opts, config_file),
     }
 }
 
@@ -75,7 +78,7 @@ fn cat_file(be: &impl DecryptReadBackend, tpe: FileType, opt: IdOpt) -> Result<(
 
 fn cat_blob(be: &impl DecryptReadBackend, tpe: BlobType, opt: IdOpt) -> Result<()> {
     let id = Id::from_hex(&opt.id)?;
-    let data = IndexBackend::new(be, ProgressBar::hidden())?.blob_from_backend(tpe, &id)?;
+    let data = IndexBackend::new(be, Progress::hidden())?.blob_from_backend(tpe, &id)?;
     print!("{}", String::from_utf8(data.to_vec())?);
 
     Ok(())
@@ -88,8 +91,14 @@ fn cat_tree(
 ) -> Result<()> {
     config_file.merge_into("snapshot-filter", &mut opts.filter)?;
 
-    let (id, path) = opts.snap.split_once(':').unwrap_or((&opts.snap, ""));
-    let snap = SnapshotFile::from_str(be, id, |sn| sn.matches(&opts.filter), progress_counter(""))?;
+    let (id, path) = split_snapshot_path(&opts.snap).unwrap_or((&opts.snap, ""));
+    let snap = SnapshotFile::from_str(
+        be,
+        id,
+        |sn| sn.matches(&opts.filter),
+        opts.group.criterion(),
+        progress_counter(""),
+    )?;
     let index = IndexBackend::new(be, progress_counter(""))?;
     let node = Tree::node_from_path(&index, snap.tree, Path::new(path))?;
     let id = node.subtree.ok_or_else(|| anyhow!("{path} is no dir"))?;
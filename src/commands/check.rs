@@ -3,10 +3,10 @@ use std::collections::HashMap;
 use anyhow::Result;
 use bytes::Bytes;
 use clap::Parser;
-use indicatif::ProgressBar;
 use itertools::Itertools;
 use log::*;
 use rayon::prelude::*;
+use serde_json::json;
 use zstd::stream::decode_all;
 
 use super::{progress_bytes, progress_counter};
@@ -16,6 +16,8 @@ use crate::commands::helpers::progress_spinner;
 use crate::crypto::hash;
 use crate::id::Id;
 use crate::index::{IndexBackend, IndexCollector, IndexType, IndexedBackend};
+use crate::metrics::check_metrics;
+use crate::progress::{error_count, json_summary, warning_count, Progress};
 use crate::repofile::{
     IndexFile, IndexPack, PackHeader, PackHeaderLength, PackHeaderRef, SnapshotFile,
 };
@@ -107,6 +109,8 @@ pub(super) fn execute(repo: OpenRepository, opts: Opts) -> Result<()> {
         p.finish();
     }
 
+    json_summary("check", json!({ "read_data": opts.read_data }));
+    check_metrics(error_count(), warning_count())?;
     Ok(())
 }
 
@@ -146,7 +150,7 @@ fn check_cache_files(
     cache: &Cache,
     be: &impl ReadBackend,
     file_type: FileType,
-    p: ProgressBar,
+    p: Progress,
 ) -> Result<()> {
     let files = cache.list_with_size(file_type)?;
 
@@ -336,7 +340,7 @@ fn check_pack(
     be: &impl DecryptReadBackend,
     index_pack: IndexPack,
     mut data: Bytes,
-    p: &mut ProgressBar,
+    p: &mut Progress,
 ) -> Result<()> {
     let id = index_pack.id;
     let size = index_pack.pack_size();
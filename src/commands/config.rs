@@ -2,7 +2,8 @@ use anyhow::{bail, Result};
 use bytesize::ByteSize;
 use clap::{AppSettings, Parser};
 
-use crate::backend::{DecryptBackend, DecryptWriteBackend};
+use crate::backend::{DecryptBackend, DecryptWriteBackend, WriteBackend};
+use crate::crypto::CryptoKey;
 use crate::repofile::ConfigFile;
 use crate::repository::OpenRepository;
 
@@ -16,6 +17,10 @@ pub(super) fn execute(mut repo: OpenRepository, opts: Opts) -> Result<()> {
     let mut new_config = repo.config.clone();
     opts.config_opts.apply(&mut new_config)?;
     if new_config != repo.config {
+        if new_config.use_locking == Some(true) && repo.config.use_locking != Some(true) {
+            // repositories created by older versions don't have the locks/ directory
+            repo.be.create()?;
+        }
         new_config.is_hot = None;
         // don't compress the config file
         repo.dbe.set_zstd(None);
@@ -39,6 +44,27 @@ pub(super) fn execute(mut repo: OpenRepository, opts: Opts) -> Result<()> {
     Ok(())
 }
 
+// save the config encrypted with the given key; also saves the config of the hot repo, if present
+pub(super) fn save_config(
+    repo: &OpenRepository,
+    config: &ConfigFile,
+    key: &impl CryptoKey,
+) -> Result<()> {
+    let mut config = config.clone();
+    // don't compress the config file
+    let dbe = DecryptBackend::new(&repo.be, key.clone());
+    config.is_hot = None;
+    // for hot/cold backend, this only saves the config to the cold repo.
+    dbe.save_file(&config)?;
+
+    if let Some(hot_be) = &repo.be_hot {
+        let dbe = DecryptBackend::new(hot_be, key.clone());
+        config.is_hot = Some(true);
+        dbe.save_file(&config)?;
+    }
+    Ok(())
+}
+
 #[derive(Parser)]
 #[clap(global_setting(AppSettings::DeriveDisplayOrder))]
 pub(super) struct ConfigOpts {
@@ -99,6 +125,11 @@ pub(super) struct ConfigOpts {
     /// tolerated. Default if not set: larger packfiles are always tolerated.
     #[clap(long, value_name = "PERCENT")]
     pub set_max_packsize_tolerate_percent: Option<u32>,
+
+    /// Use restic-compatible lock files in the repository.
+    /// If enabled, backup creates a non-exclusive lock while forget, prune and repair create an exclusive lock.
+    #[clap(long, value_name = "TRUE/FALSE")]
+    pub set_locking: Option<bool>,
 }
 
 impl ConfigOpts {
@@ -168,6 +199,10 @@ impl ConfigOpts {
             config.max_packsize_tolerate_percent = Some(percent);
         }
 
+        if let Some(locking) = self.set_locking {
+            config.use_locking = Some(locking);
+        }
+
         Ok(())
     }
 }
@@ -4,11 +4,13 @@ use anyhow::{bail, Result};
 use clap::{AppSettings, Parser};
 use log::*;
 use rayon::prelude::*;
+use serde_json::json;
 
 use super::{progress_counter, table_with_titles, RusticConfig};
 use crate::backend::DecryptWriteBackend;
 use crate::blob::{BlobType, NodeType, Packer, TreeStreamerOnce};
 use crate::index::{IndexBackend, IndexedBackend, Indexer, ReadIndex};
+use crate::progress::json_summary;
 use crate::repofile::{Id, SnapshotFile, SnapshotFilter};
 use crate::repository::{OpenRepository, Repository, RepositoryOptions};
 
@@ -62,7 +64,12 @@ pub(super) fn execute(
         if poly != repo_dest.config.poly()? {
             bail!("cannot copy to repository with different chunker parameter (re-chunking not implemented)!");
         }
-        copy(&snapshots, index.clone(), repo_dest, &opts)?;
+        let target = repo_dest.name.clone();
+        let count = copy(&snapshots, index.clone(), repo_dest, &opts)?;
+        json_summary(
+            "copy",
+            json!({ "dry_run": opts.dry_run, "target": target, "snapshots": count }),
+        );
     }
     Ok(())
 }
@@ -72,18 +79,18 @@ fn copy(
     index: impl IndexedBackend,
     repo_dest: OpenRepository,
     opts: &Opts,
-) -> Result<()> {
+) -> Result<usize> {
     let be_dest = &repo_dest.dbe;
 
     let snapshots = relevant_snapshots(snapshots, &repo_dest, &opts.filter)?;
     match (snapshots.len(), opts.dry_run) {
         (count, true) => {
             info!("would have copied {count} snapshots");
-            return Ok(());
+            return Ok(count);
         }
         (0, false) => {
             info!("no snapshot to copy.");
-            return Ok(());
+            return Ok(0);
         }
         _ => {} // continue
     }
@@ -161,7 +168,7 @@ fn copy(
 
     let p = progress_counter("saving snapshots...");
     be_dest.save_list(snapshots.iter(), p)?;
-    Ok(())
+    Ok(snapshots.len())
 }
 
 fn relevant_snapshots(
@@ -7,10 +7,9 @@ use clap::Parser;
 use super::{progress_counter, RusticConfig};
 use crate::backend::{LocalDestination, LocalSource, LocalSourceOptions, ReadSourceEntry};
 use crate::blob::{Node, NodeStreamer, NodeType, Tree};
-use crate::commands::helpers::progress_spinner;
 use crate::crypto::hash;
 use crate::index::{IndexBackend, ReadIndex};
-use crate::repofile::{SnapshotFile, SnapshotFilter};
+use crate::repofile::{split_snapshot_path, SnapshotFile, SnapshotFilter, SnapshotRefGroup};
 use crate::repository::OpenRepository;
 
 #[derive(Parser)]
@@ -21,6 +20,9 @@ pub(super) struct Opts {
     #[clap(flatten, help_heading = "SNAPSHOT FILTER OPTIONS (when using latest)")]
     filter: SnapshotFilter,
 
+    #[clap(flatten)]
+    group: SnapshotRefGroup,
+
     /// Reference snapshot/path
     #[clap(value_name = "SNAPSHOT1[:PATH1]")]
     snap1: String,
@@ -43,19 +45,26 @@ pub(super) fn execute(
     mut opts: Opts,
     config_file: RusticConfig,
 ) -> Result<()> {
+    config_file.merge_into("snapshot-filter", &mut opts.filter)?;
+
     let be = &repo.dbe;
     let (id1, path1) = arg_to_snap_path(&opts.snap1, "");
     let (id2, path2) = arg_to_snap_path(&opts.snap2, path1);
+    let get_snap = |id| {
+        SnapshotFile::from_str(
+            be,
+            id,
+            |sn| sn.matches(&opts.filter),
+            opts.group.criterion(),
+            progress_counter(""),
+        )
+    };
 
     match (id1, id2) {
         (Some(id1), Some(id2)) => {
             // diff between two snapshots
-            let p = progress_spinner("getting snapshots...");
-            let snaps = SnapshotFile::from_ids(be, &[id1.to_string(), id2.to_string()])?;
-            p.finish();
-
-            let snap1 = &snaps[0];
-            let snap2 = &snaps[1];
+            let snap1 = get_snap(id1)?;
+            let snap2 = get_snap(id2)?;
 
             let index = IndexBackend::new(be, progress_counter(""))?;
             let node1 = Tree::node_from_path(&index, snap1.tree, Path::new(path1))?;
@@ -71,11 +80,7 @@ pub(super) fn execute(
         }
         (Some(id1), None) => {
             // diff between snapshot and local path
-            config_file.merge_into("snapshot-filter", &mut opts.filter)?;
-
-            let p = progress_spinner("getting snapshot...");
-            let snap1 = SnapshotFile::from_str(be, id1, |sn| sn.matches(&opts.filter), p.clone())?;
-            p.finish();
+            let snap1 = get_snap(id1)?;
 
             let index = IndexBackend::new(be, progress_counter(""))?;
             let node1 = Tree::node_from_path(&index, snap1.tree, Path::new(path1))?;
@@ -110,7 +115,7 @@ pub(super) fn execute(
 }
 
 fn arg_to_snap_path<'a>(arg: &'a str, default_path: &'a str) -> (Option<&'a str>, &'a str) {
-    match arg.split_once(':') {
+    match split_snapshot_path(arg) {
         Some((id, path)) => (Some(id), path),
         None => {
             if arg.contains('/') {
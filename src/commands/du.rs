@@ -0,0 +1,157 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use clap::{AppSettings, Parser};
+use log::*;
+
+use super::{bytes, progress_counter, table_right_from, RusticConfig};
+use crate::blob::{NodeStreamer, NodeType, Tree, TreeStreamerOnce};
+use crate::id::Id;
+use crate::index::{IndexBackend, ReadIndex};
+use crate::repofile::{split_snapshot_path, SnapshotFile, SnapshotFilter, SnapshotRefGroup};
+use crate::repository::OpenRepository;
+
+#[derive(Parser)]
+#[clap(global_setting(AppSettings::DeriveDisplayOrder))]
+pub(super) struct Opts {
+    #[clap(flatten, help_heading = "SNAPSHOT FILTER OPTIONS (when using latest)")]
+    filter: SnapshotFilter,
+
+    #[clap(flatten)]
+    group: SnapshotRefGroup,
+
+    /// Only show directories up to the given depth below PATH
+    #[clap(long, short = 'd', value_name = "DEPTH")]
+    max_depth: Option<usize>,
+
+    /// Sort directories by name or by size (largest first)
+    #[clap(
+        long,
+        value_name = "FIELD",
+        possible_values = ["name", "size", "stored", "new"],
+        default_value = "name"
+    )]
+    sort_by: String,
+
+    /// Snapshot/path to show the directory sizes for
+    #[clap(value_name = "SNAPSHOT[:PATH]")]
+    snap: String,
+}
+
+#[derive(Default)]
+struct DirSize {
+    // size of all files
+    size: u64,
+    // size of all data blobs in the pack files; each blob is only counted once
+    stored: u64,
+    // stored size of all data blobs which are not contained in the parent snapshot
+    new: u64,
+}
+
+pub(super) fn execute(
+    repo: OpenRepository,
+    mut opts: Opts,
+    config_file: RusticConfig,
+) -> Result<()> {
+    config_file.merge_into("snapshot-filter", &mut opts.filter)?;
+    let be = &repo.dbe;
+
+    let (id, path) = split_snapshot_path(&opts.snap).unwrap_or((&opts.snap, ""));
+    let snap = SnapshotFile::from_str(
+        be,
+        id,
+        |sn| sn.matches(&opts.filter),
+        opts.group.criterion(),
+        progress_counter(""),
+    )?;
+    let index = IndexBackend::new(be, progress_counter(""))?;
+    let node = Tree::node_from_path(&index, snap.tree, Path::new(path))?;
+
+    // all data blobs of the parent snapshot
+    let mut parent_blobs: HashSet<Id> = HashSet::new();
+    match snap.parent.map(|id| SnapshotFile::from_backend(be, &id)) {
+        Some(Ok(parent)) => {
+            let p = progress_counter("reading parent snapshot...");
+            for item in TreeStreamerOnce::new(index.clone(), vec![parent.tree], p)? {
+                let (_, tree) = item?;
+                for node in tree.nodes() {
+                    parent_blobs.extend(node.content.iter().flatten());
+                }
+            }
+        }
+        Some(Err(err)) => warn!("error reading parent snapshot, all data is shown as new: {err}"),
+        None => info!("snapshot has no parent, all data is shown as new"),
+    }
+
+    let max_depth = opts.max_depth.unwrap_or(usize::MAX);
+    let mut dirs: BTreeMap<PathBuf, DirSize> = BTreeMap::new();
+    dirs.insert(PathBuf::new(), DirSize::default());
+    // the directories to show containing the current node together with the blobs counted so far.
+    // The nodes are streamed depth-first, so the blobs of a directory are dropped once it is left.
+    let mut open_dirs: Vec<(PathBuf, HashSet<Id>)> = vec![(PathBuf::new(), HashSet::new())];
+
+    let p = progress_counter("scanning trees...");
+    for item in NodeStreamer::new(index.clone(), &node)? {
+        let (path, node) = item?;
+        while let Some((dir, _)) = open_dirs.last() {
+            if path.starts_with(dir) {
+                break;
+            }
+            open_dirs.pop();
+        }
+        if node.is_dir() {
+            if path.components().count() <= max_depth {
+                dirs.insert(path.clone(), DirSize::default());
+                open_dirs.push((path, HashSet::new()));
+            }
+            continue;
+        }
+        if node.node_type != NodeType::File {
+            continue;
+        }
+        p.inc(1);
+
+        for (dir, blobs) in &mut open_dirs {
+            let entry = dirs.get_mut(dir).unwrap();
+            entry.size += node.meta.size;
+            for id in node.content.iter().flatten() {
+                if !blobs.insert(*id) {
+                    continue;
+                }
+                let size = index.get_data(id).map_or(0, |ie| u64::from(*ie.length()));
+                entry.stored += size;
+                if !parent_blobs.contains(id) {
+                    entry.new += size;
+                }
+            }
+        }
+    }
+    p.finish();
+
+    let mut dirs: Vec<_> = dirs.into_iter().collect();
+    match opts.sort_by.as_str() {
+        "size" => dirs.sort_by_key(|(_, dir)| Reverse(dir.size)),
+        "stored" => dirs.sort_by_key(|(_, dir)| Reverse(dir.stored)),
+        "new" => dirs.sort_by_key(|(_, dir)| Reverse(dir.new)),
+        _ => {}
+    }
+
+    let mut table = table_right_from(1, ["Path", "Size", "Stored Size", "New Size"]);
+    for (dir, size) in dirs {
+        let mut path = Path::new("/").join(path);
+        if dir.components().count() > 0 {
+            path.push(dir);
+        }
+        table.add_row([
+            path.display().to_string(),
+            bytes(size.size),
+            bytes(size.stored),
+            bytes(size.new),
+        ]);
+    }
+    println!("{table}");
+
+    Ok(())
+}
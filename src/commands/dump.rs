@@ -1,21 +1,40 @@
-use anyhow::{bail, Result};
-use clap::Parser;
-use std::io::Write;
-use std::path::Path;
+use anyhow::{anyhow, bail, Result};
+use bytes::{Buf, Bytes};
+use chrono::{Datelike, Timelike};
+use clap::{AppSettings, Parser};
+use log::*;
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Seek, Write};
+use std::path::{Path, PathBuf};
 
-use crate::blob::{BlobType, NodeType, Tree};
+#[cfg(not(windows))]
+use crate::backend::mapper::map_mode_from_go;
+use crate::blob::{BlobType, HardLinks, Node, NodeStreamer, NodeType, Tree};
+use crate::id::Id;
 use crate::index::{IndexBackend, IndexedBackend};
-use crate::repofile::{SnapshotFile, SnapshotFilter};
+use crate::repofile::{split_snapshot_path, SnapshotFile, SnapshotFilter, SnapshotRefGroup};
 use crate::repository::OpenRepository;
 
 use super::{progress_counter, RusticConfig};
 
 #[derive(Parser)]
+#[clap(global_setting(AppSettings::DeriveDisplayOrder))]
 pub(super) struct Opts {
     #[clap(flatten, help_heading = "SNAPSHOT FILTER OPTIONS (when using latest)")]
     filter: SnapshotFilter,
 
-    /// file from snapshot to dump
+    #[clap(flatten)]
+    group: SnapshotRefGroup,
+
+    /// Dump as archive of the given format [default: tar for directories, no archive for files]
+    #[clap(long, value_name = "FORMAT", possible_values = ["tar", "zip"])]
+    archive: Option<String>,
+
+    /// Write to the given file instead of stdout
+    #[clap(long, short = 'o', value_name = "FILE")]
+    output: Option<PathBuf>,
+
+    /// file or dir from snapshot to dump
     #[clap(value_name = "SNAPSHOT[:PATH]")]
     snap: String,
 }
@@ -28,21 +47,416 @@ pub(super) fn execute(
     config_file.merge_into("snapshot-filter", &mut opts.filter)?;
     let be = &repo.dbe;
 
-    let (id, path) = opts.snap.split_once(':').unwrap_or((&opts.snap, ""));
-    let snap = SnapshotFile::from_str(be, id, |sn| sn.matches(&opts.filter), progress_counter(""))?;
+    let (id, path) = split_snapshot_path(&opts.snap).unwrap_or((&opts.snap, ""));
+    let snap = SnapshotFile::from_str(
+        be,
+        id,
+        |sn| sn.matches(&opts.filter),
+        opts.group.criterion(),
+        progress_counter(""),
+    )?;
     let index = IndexBackend::new(be, progress_counter(""))?;
     let node = Tree::node_from_path(&index, snap.tree, Path::new(path))?;
 
-    if node.node_type != NodeType::File {
-        bail!("dump only supports regular files!");
+    let output = || -> Result<Box<dyn Write>> {
+        Ok(match &opts.output {
+            Some(file) => Box::new(BufWriter::new(File::create(file)?)),
+            None => Box::new(BufWriter::new(io::stdout())),
+        })
+    };
+
+    let archive = opts.archive.as_deref().or(node.is_dir().then_some("tar"));
+    match (archive, &opts.output) {
+        (None, _) => {
+            if node.node_type != NodeType::File {
+                bail!("dump without archive format only supports regular files!");
+            }
+            let mut output = output()?;
+            io::copy(&mut ContentReader::new(&index, &node), &mut output)?;
+            output.flush()?;
+        }
+        (Some("tar"), _) => dump_tar(&index, &node, output()?)?,
+        (_, Some(file)) => dump_zip(&index, &node, File::create(file)?)?,
+        // zip needs a seekable output, so write to a temporary file first
+        (_, None) => {
+            let mut file = tempfile::tempfile()?;
+            dump_zip(&index, &node, &mut file)?;
+            file.rewind()?;
+            let mut output = output()?;
+            io::copy(&mut file, &mut output)?;
+            output.flush()?;
+        }
+    }
+
+    Ok(())
+}
+
+/// [`ContentReader`] reads the contents of a file node blob by blob
+struct ContentReader<'a, BE: IndexedBackend> {
+    index: &'a BE,
+    blobs: std::vec::IntoIter<Id>,
+    data: Bytes,
+}
+
+impl<'a, BE: IndexedBackend> ContentReader<'a, BE> {
+    fn new(index: &'a BE, node: &Node) -> Self {
+        Self {
+            index,
+            blobs: node.content.clone().unwrap_or_default().into_iter(),
+            data: Bytes::new(),
+        }
+    }
+}
+
+impl<BE: IndexedBackend> Read for ContentReader<'_, BE> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.data.is_empty() {
+            match self.blobs.next() {
+                None => return Ok(0),
+                Some(id) => {
+                    self.data = self
+                        .index
+                        .blob_from_backend(BlobType::Data, &id)
+                        .map_err(io::Error::other)?;
+                }
+            }
+        }
+        let len = buf.len().min(self.data.len());
+        self.data.copy_to_slice(&mut buf[..len]);
+        Ok(len)
     }
+}
 
-    let mut stdout = std::io::stdout();
-    for id in node.content.unwrap() {
-        // TODO: cache blobs which are needed later
-        let data = index.blob_from_backend(BlobType::Data, &id)?;
-        stdout.write_all(&data)?;
+// the size of the file contents as saved in the repository
+fn content_size(index: &impl IndexedBackend, node: &Node) -> Result<u64> {
+    let mut size = 0;
+    for id in node.content.iter().flatten() {
+        let ie = index
+            .get_data(id)
+            .ok_or_else(|| anyhow!("blob {id} not found in index"))?;
+        size += u64::from(ie.data_length());
     }
+    Ok(size)
+}
 
+// the permission bits (including setuid, setgid and sticky bit) of the node
+fn unix_mode(node: &Node) -> u32 {
+    let default = if node.is_dir() { 0o755 } else { 0o644 };
+    #[cfg(not(windows))]
+    let mode = node.meta.mode.map(|mode| map_mode_from_go(mode) & 0o7777);
+    #[cfg(windows)]
+    let mode = node.meta.mode.map(|mode| mode & 0o777);
+    mode.unwrap_or(default)
+}
+
+// split a linux device id into major and minor number
+fn device_numbers(device: u64) -> (u32, u32) {
+    let major = ((device >> 8) & 0xfff) | ((device >> 32) & !0xfff);
+    let minor = (device & 0xff) | ((device >> 12) & !0xff);
+    (major as u32, minor as u32)
+}
+
+fn dump_tar(index: &impl IndexedBackend, node: &Node, w: impl Write) -> Result<()> {
+    let mut ar = tar::Builder::new(w);
+    let mut hardlinks = HardLinks::default();
+    for item in NodeStreamer::new(index.clone(), node)? {
+        let (path, node) = item?;
+        let meta = &node.meta;
+
+        // skip sockets before anything is written for them
+        if node.node_type == NodeType::Socket {
+            warn!(
+                "{}: sockets cannot be saved in tar, skipping",
+                path.display()
+            );
+            continue;
+        }
+
+        let mut header = tar::Header::new_gnu();
+        header.set_mode(unix_mode(&node));
+        header.set_uid(meta.uid.unwrap_or_default().into());
+        header.set_gid(meta.gid.unwrap_or_default().into());
+        // user and group names are only informational, so ignore too long names
+        if let Some(user) = &meta.user {
+            _ = header.set_username(user);
+        }
+        if let Some(group) = &meta.group {
+            _ = header.set_groupname(group);
+        }
+        if let Some(mtime) = meta.mtime {
+            header.set_mtime(u64::try_from(mtime.timestamp()).unwrap_or_default());
+        }
+        header.set_size(0);
+
+        // extended attributes are saved as PAX headers in the format used by GNU tar; the PAX header
+        // applies to the entry appended next
+        let xattrs: Vec<_> = meta
+            .extended_attributes
+            .iter()
+            .map(|attr| (format!("SCHILY.xattr.{}", attr.name), &attr.value))
+            .collect();
+        ar.append_pax_extensions(
+            xattrs
+                .iter()
+                .map(|(name, value)| (name.as_str(), value.as_slice())),
+        )?;
+
+        match &node.node_type {
+            NodeType::File => match hardlinks.link_target(&path, &node) {
+                Some(target) => {
+                    header.set_entry_type(tar::EntryType::Link);
+                    ar.append_link(&mut header, &path, target)?;
+                }
+                None => {
+                    header.set_entry_type(tar::EntryType::Regular);
+                    header.set_size(content_size(index, &node)?);
+                    ar.append_data(&mut header, &path, ContentReader::new(index, &node))?;
+                }
+            },
+            NodeType::Dir => {
+                header.set_entry_type(tar::EntryType::Directory);
+                ar.append_data(&mut header, &path, io::empty())?;
+            }
+            NodeType::Symlink { linktarget } => {
+                header.set_entry_type(tar::EntryType::Symlink);
+                ar.append_link(&mut header, &path, linktarget)?;
+            }
+            NodeType::Dev { device } | NodeType::Chardev { device } => {
+                header.set_entry_type(match node.node_type {
+                    NodeType::Dev { .. } => tar::EntryType::Block,
+                    _ => tar::EntryType::Char,
+                });
+                let (major, minor) = device_numbers(*device);
+                header.set_device_major(major)?;
+                header.set_device_minor(minor)?;
+                ar.append_data(&mut header, &path, io::empty())?;
+            }
+            NodeType::Fifo => {
+                header.set_entry_type(tar::EntryType::Fifo);
+                ar.append_data(&mut header, &path, io::empty())?;
+            }
+            NodeType::Socket => unreachable!("sockets are skipped above"),
+        }
+    }
+    ar.into_inner()?.flush()?;
+    Ok(())
+}
+
+fn dump_zip(index: &impl IndexedBackend, node: &Node, w: impl Write + Seek) -> Result<()> {
+    let mut zip = zip::ZipWriter::new(w);
+    for item in NodeStreamer::new(index.clone(), node)? {
+        let (path, node) = item?;
+        let name = path.to_string_lossy();
+
+        let mut options = zip::write::FileOptions::default().unix_permissions(unix_mode(&node));
+        let mtime = node.meta.mtime.and_then(|t| {
+            zip::DateTime::from_date_and_time(
+                u16::try_from(t.year()).ok()?,
+                t.month() as u8,
+                t.day() as u8,
+                t.hour() as u8,
+                t.minute() as u8,
+                t.second() as u8,
+            )
+            .ok()
+        });
+        if let Some(mtime) = mtime {
+            options = options.last_modified_time(mtime);
+        }
+
+        match &node.node_type {
+            NodeType::File => {
+                let size = content_size(index, &node)?;
+                options = options.large_file(size >= u64::from(u32::MAX));
+                zip.start_file(name, options)?;
+                io::copy(&mut ContentReader::new(index, &node), &mut zip)?;
+            }
+            NodeType::Dir => zip.add_directory(name, options)?,
+            NodeType::Symlink { linktarget } => zip.add_symlink(name, linktarget, options)?,
+            _ => warn!(
+                "{}: special files cannot be saved in zip, skipping",
+                path.display()
+            ),
+        }
+    }
+    zip.finish()?.flush()?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::ffi::OsStr;
+
+    use crate::backend::{
+        DecryptBackend, DecryptWriteBackend, FileType, LocalBackend, WriteBackend,
+    };
+    use crate::blob::{ExtendedAttribute, Metadata};
+    use crate::crypto::{hash, Key};
+    use crate::index::{IndexCollector, IndexType};
+    use crate::repofile::{IndexBlob, IndexPack};
+
+    type TestIndex = IndexBackend<DecryptBackend<LocalBackend, Key>>;
+
+    // save each blob in its own pack and return the index of all blobs
+    fn test_index(dir: &Path, blobs: &[(BlobType, Vec<u8>)]) -> Result<TestIndex> {
+        let be = LocalBackend::new(dir.to_str().unwrap())?;
+        be.create()?;
+        let be = DecryptBackend::new(&be, Key::new());
+        let mut collector = IndexCollector::new(IndexType::Full);
+        for (tpe, data) in blobs {
+            let id = be.hash_write_full(FileType::Pack, data)?;
+            let blob = IndexBlob {
+                id: hash(data),
+                tpe: *tpe,
+                offset: 0,
+                length: u32::try_from(data.len())? + 32,
+                uncompressed_length: None,
+            };
+            collector.extend([IndexPack {
+                id,
+                blobs: vec![blob],
+                time: None,
+                size: None,
+            }]);
+        }
+        Ok(IndexBackend::new_from_index(&be, collector.into_index()))
+    }
+
+    fn node(name: &str, node_type: NodeType, meta: Metadata) -> Node {
+        Node::new_node(OsStr::new(name), node_type, meta)
+    }
+
+    // a directory containing all node types; "file" and "link" are hard links
+    fn test_tree(dir: &Path) -> Result<(TestIndex, Node)> {
+        let data = b"hello".to_vec();
+        let linked = Metadata {
+            mode: Some(0o640),
+            inode: 42,
+            device_id: 1,
+            size: 5,
+            links: 2,
+            ..Default::default()
+        };
+        let mut file = node("file", NodeType::File, linked.clone());
+        file.set_content(vec![hash(&data)]);
+        let mut link = node("link", NodeType::File, linked);
+        link.set_content(vec![hash(&data)]);
+        let xattr = Metadata {
+            extended_attributes: vec![ExtendedAttribute {
+                name: "user.test".to_string(),
+                value: b"value".to_vec(),
+            }],
+            ..Default::default()
+        };
+
+        let mut tree = Tree::new();
+        tree.add(node(
+            "chardev",
+            NodeType::Chardev { device: 0x103 },
+            Metadata::default(),
+        ));
+        tree.add(file);
+        tree.add(link);
+        tree.add(node("socket", NodeType::Socket, xattr));
+        tree.add(node(
+            "symlink",
+            NodeType::Symlink {
+                linktarget: "file".to_string(),
+            },
+            Metadata::default(),
+        ));
+        tree.add(node("z-fifo", NodeType::Fifo, Metadata::default()));
+        let (tree, tree_id) = tree.serialize()?;
+
+        let index = test_index(dir, &[(BlobType::Data, data), (BlobType::Tree, tree)])?;
+        let mut root = node("", NodeType::Dir, Metadata::default());
+        root.set_subtree(tree_id);
+        Ok((index, root))
+    }
+
+    #[test]
+    fn tar() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let (index, root) = test_tree(dir.path())?;
+        let mut buffer = Vec::new();
+        dump_tar(&index, &root, &mut buffer)?;
+
+        let mut ar = tar::Archive::new(buffer.as_slice());
+        let mut entries = Vec::new();
+        for entry in ar.entries()? {
+            let mut entry = entry?;
+            let header = entry.header().clone();
+            let xattrs = entry.pax_extensions()?.map_or(0, Iterator::count);
+            let mut content = String::new();
+            _ = entry.read_to_string(&mut content)?;
+            entries.push((entry.path()?.into_owned(), header, xattrs, content));
+        }
+        let names: Vec<_> = entries.iter().map(|e| e.0.to_str().unwrap()).collect();
+        assert_eq!(names, ["chardev", "file", "link", "symlink", "z-fifo"]);
+
+        let (_, chardev, _, _) = &entries[0];
+        assert_eq!(chardev.entry_type(), tar::EntryType::Char);
+        assert_eq!(chardev.device_major()?, Some(1));
+        assert_eq!(chardev.device_minor()?, Some(3));
+
+        let (_, file, _, content) = &entries[1];
+        assert_eq!(file.entry_type(), tar::EntryType::Regular);
+        assert_eq!(file.mode()?, 0o640);
+        assert_eq!(content, "hello");
+
+        let (_, link, _, _) = &entries[2];
+        assert_eq!(link.entry_type(), tar::EntryType::Link);
+        assert_eq!(link.link_name()?.unwrap(), Path::new("file"));
+
+        let (_, symlink, _, _) = &entries[3];
+        assert_eq!(symlink.entry_type(), tar::EntryType::Symlink);
+        assert_eq!(symlink.link_name()?.unwrap(), Path::new("file"));
+
+        let (_, fifo, _, _) = &entries[4];
+        assert_eq!(fifo.entry_type(), tar::EntryType::Fifo);
+
+        // the extended attributes of the skipped socket must not end up at the next entry
+        assert!(entries.iter().all(|(_, _, xattrs, _)| *xattrs == 0));
+        Ok(())
+    }
+
+    #[test]
+    fn zip() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let (index, root) = test_tree(dir.path())?;
+        let mut buffer = io::Cursor::new(Vec::new());
+        dump_zip(&index, &root, &mut buffer)?;
+
+        let mut zip = zip::ZipArchive::new(buffer)?;
+        let names: Vec<_> = zip.file_names().collect();
+        assert_eq!(names.len(), 3);
+
+        let mut file = zip.by_name("file")?;
+        assert_eq!(file.unix_mode().map(|mode| mode & 0o7777), Some(0o640));
+        let mut content = String::new();
+        _ = file.read_to_string(&mut content)?;
+        assert_eq!(content, "hello");
+        drop(file);
+
+        // zip has no hard links, so the link is saved as regular file
+        assert_eq!(zip.by_name("link")?.size(), 5);
+
+        let mut symlink = zip.by_name("symlink")?;
+        assert_eq!(
+            symlink.unix_mode().map(|mode| mode & 0o170_000),
+            Some(0o120_000)
+        );
+        let mut target = String::new();
+        _ = symlink.read_to_string(&mut target)?;
+        assert_eq!(target, "file");
+        Ok(())
+    }
+
+    #[test]
+    fn devices() {
+        assert_eq!(device_numbers(0x103), (1, 3));
+        assert_eq!(device_numbers(0x1234_5678), (0x456, 0x1_2378));
+    }
+}
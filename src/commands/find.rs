@@ -0,0 +1,404 @@
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Local};
+use clap::{AppSettings, Parser};
+use ignore::overrides::{Override, OverrideBuilder};
+use log::*;
+use regex::Regex;
+use serde::Serialize;
+
+use super::{bytes, progress_counter, table_with_titles, RusticConfig};
+use crate::blob::{Node, NodeType, Tree, TreeStreamerOnce};
+use crate::id::Id;
+use crate::index::{IndexBackend, IndexedBackend};
+use crate::repofile::{SnapshotFile, SnapshotFilter};
+use crate::repository::OpenRepository;
+
+#[derive(Parser)]
+#[clap(global_setting(AppSettings::DeriveDisplayOrder))]
+pub(super) struct Opts {
+    #[clap(
+        flatten,
+        help_heading = "SNAPSHOT FILTER OPTIONS (if no snapshot is given)"
+    )]
+    filter: SnapshotFilter,
+
+    /// Glob pattern to search for (can be specified multiple times)
+    #[clap(
+        long,
+        help_heading = "PATTERN OPTIONS",
+        required_unless_present_any = &["iglob", "regex"]
+    )]
+    glob: Vec<String>,
+
+    /// Same as --glob pattern but ignores the casing of filenames
+    #[clap(long, value_name = "GLOB", help_heading = "PATTERN OPTIONS")]
+    iglob: Vec<String>,
+
+    /// Regular expression the full path is matched against (can be specified multiple times)
+    #[clap(long, value_name = "REGEX", help_heading = "PATTERN OPTIONS")]
+    regex: Vec<String>,
+
+    /// Show matching entries in json format
+    #[clap(long)]
+    json: bool,
+
+    /// Snapshots to search in. If none is given, use filter to filter from all snapshots.
+    #[clap(value_name = "ID")]
+    ids: Vec<String>,
+}
+
+pub(super) fn execute(
+    repo: OpenRepository,
+    mut opts: Opts,
+    config_file: RusticConfig,
+) -> Result<()> {
+    config_file.merge_into("snapshot-filter", &mut opts.filter)?;
+    let be = &repo.dbe;
+
+    let matcher = Matcher::new(&opts)?;
+
+    let mut snapshots = match opts.ids.is_empty() {
+        true => SnapshotFile::all_from_backend(be, &opts.filter)?,
+        false => SnapshotFile::from_ids(be, &opts.ids)?,
+    };
+    snapshots.sort_unstable();
+
+    let index = IndexBackend::only_full_trees(be, progress_counter(""))?;
+    let results = find_in_snapshots(index, matcher, &snapshots)?;
+
+    if opts.json {
+        let mut stdout = std::io::stdout();
+        serde_json::to_writer_pretty(&mut stdout, &results)?;
+        return Ok(());
+    }
+
+    if results.is_empty() {
+        info!("no matching entries found.");
+    }
+    for result in results {
+        println!(
+            "found {} matching entries in snapshot {} from {}",
+            result.matches.len(),
+            result.snapshot,
+            result.time.format("%Y-%m-%d %H:%M:%S")
+        );
+        let mut table = table_with_titles(["Path", "Size", "Modified"]);
+        for m in result.matches {
+            let size = match m.node_type {
+                NodeType::File => bytes(m.size),
+                _ => String::new(),
+            };
+            let mtime = m
+                .mtime
+                .map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+                .unwrap_or_default();
+            table.add_row([m.path.display().to_string(), size, mtime]);
+        }
+        println!("{table}");
+        println!();
+    }
+
+    Ok(())
+}
+
+/// Search all given snapshots and return the matches of the snapshots with matching entries
+fn find_in_snapshots(
+    index: impl IndexedBackend,
+    matcher: Matcher,
+    snapshots: &[SnapshotFile],
+) -> Result<Vec<FindResult>> {
+    // visit each tree only once and save the matches together with the path the tree was found
+    let p = progress_counter("searching trees...");
+    let snap_trees = snapshots.iter().map(|sn| sn.tree).collect();
+    let mut trees = HashMap::new();
+    let mut tree_streamer = TreeStreamerOnce::new(index.clone(), snap_trees, p)?;
+    while let Some(item) = tree_streamer.next_with_id().transpose()? {
+        let (path, id, tree) = item;
+        let matches = matcher.matches_in(&tree, &path);
+        let subtrees = subtrees(&tree);
+        trees.insert(
+            id,
+            TreeInfo {
+                path,
+                subtrees,
+                matches,
+            },
+        );
+    }
+
+    let mut finder = Finder {
+        index,
+        matcher,
+        trees,
+        found: HashMap::new(),
+    };
+
+    let mut results = Vec::new();
+    for sn in snapshots {
+        let matches = finder.find(sn.tree, Path::new(""))?;
+        if !matches.is_empty() {
+            let root = Path::new("/");
+            results.push(FindResult {
+                snapshot: sn.id,
+                time: sn.time,
+                matches: matches.iter().map(|m| m.prefixed(root)).collect(),
+            });
+        }
+    }
+    Ok(results)
+}
+
+fn subtrees(tree: &Tree) -> Vec<(OsString, Id)> {
+    tree.nodes()
+        .iter()
+        .filter_map(|node| node.subtree.map(|id| (node.name(), id)))
+        .collect()
+}
+
+struct Matcher {
+    globs: Override,
+    regexes: Vec<Regex>,
+    // whether matching depends on the path of a tree and not only on the names within it
+    path_dependent: bool,
+}
+
+impl Matcher {
+    fn new(opts: &Opts) -> Result<Self> {
+        let mut override_builder = OverrideBuilder::new("/");
+        for g in &opts.glob {
+            override_builder.add(g)?;
+        }
+        override_builder.case_insensitive(true)?;
+        for g in &opts.iglob {
+            override_builder.add(g)?;
+        }
+
+        let regexes = opts
+            .regex
+            .iter()
+            .map(|r| Regex::new(r))
+            .collect::<Result<_, _>>()?;
+
+        // globs without a slash only match the file name
+        let path_dependent =
+            !opts.regex.is_empty() || opts.glob.iter().chain(&opts.iglob).any(|g| g.contains('/'));
+
+        Ok(Self {
+            globs: override_builder.build()?,
+            regexes,
+            path_dependent,
+        })
+    }
+
+    fn is_match(&self, path: &Path, node: &Node) -> bool {
+        self.globs.matched(path, node.is_dir()).is_whitelist()
+            || self
+                .regexes
+                .iter()
+                .any(|r| r.is_match(&path.to_string_lossy()))
+    }
+
+    // find all matching nodes of a tree which is located at the given path; the paths of the
+    // matches are relative to the tree
+    fn matches_in(&self, tree: &Tree, path: &Path) -> Vec<Match> {
+        tree.nodes()
+            .iter()
+            .filter_map(|node| {
+                let name = PathBuf::from(node.name());
+                self.is_match(&Path::new("/").join(path).join(&name), node)
+                    .then(|| Match {
+                        path: name,
+                        node_type: node.node_type.clone(),
+                        size: node.meta.size,
+                        mtime: node.meta.mtime,
+                    })
+            })
+            .collect()
+    }
+}
+
+#[derive(Clone, Serialize)]
+struct Match {
+    path: PathBuf,
+    #[serde(flatten)]
+    node_type: NodeType,
+    size: u64,
+    mtime: Option<DateTime<Local>>,
+}
+
+impl Match {
+    fn prefixed(&self, path: &Path) -> Self {
+        Self {
+            path: path.join(&self.path),
+            ..self.clone()
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct FindResult {
+    snapshot: Id,
+    time: DateTime<Local>,
+    matches: Vec<Match>,
+}
+
+struct TreeInfo {
+    // the path where the tree has been found by the tree streamer
+    path: PathBuf,
+    subtrees: Vec<(OsString, Id)>,
+    matches: Vec<Match>,
+}
+
+struct Finder<BE: IndexedBackend> {
+    index: BE,
+    matcher: Matcher,
+    trees: HashMap<Id, TreeInfo>,
+    // all matches within a tree relative to the tree; if the matcher is path dependent, the matches
+    // are only valid at the path of the tree
+    found: HashMap<(Id, Option<PathBuf>), Rc<Vec<Match>>>,
+}
+
+impl<BE: IndexedBackend> Finder<BE> {
+    // find all matches within the tree located at path
+    fn find(&mut self, id: Id, path: &Path) -> Result<Rc<Vec<Match>>> {
+        let key = (id, self.matcher.path_dependent.then(|| path.to_path_buf()));
+        if let Some(found) = self.found.get(&key) {
+            return Ok(found.clone());
+        }
+
+        let info = self
+            .trees
+            .get(&id)
+            .ok_or_else(|| anyhow!("tree {id} has not been visited"))?;
+        let (mut found, subtrees) = if info.path == path || !self.matcher.path_dependent {
+            (info.matches.clone(), info.subtrees.clone())
+        } else {
+            // the tree has been visited at another path, so we need to match again
+            let tree = Tree::from_backend(&self.index, id)?;
+            (self.matcher.matches_in(&tree, path), subtrees(&tree))
+        };
+
+        for (name, subtree) in subtrees {
+            let matches = self.find(subtree, &path.join(&name))?;
+            found.extend(matches.iter().map(|m| m.prefixed(Path::new(&name))));
+        }
+
+        let found = Rc::new(found);
+        _ = self.found.insert(key, found.clone());
+        Ok(found)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::backend::{DecryptBackend, LocalBackend, WriteBackend};
+    use crate::blob::{BlobType, Metadata, Packer};
+    use crate::crypto::Key;
+    use crate::index::Indexer;
+    use crate::progress::Progress;
+    use crate::repofile::ConfigFile;
+
+    fn node(name: &str, node_type: NodeType) -> Node {
+        Node::new_node(name.as_ref(), node_type, Metadata::default())
+    }
+
+    fn dir(name: &str, id: Id) -> Node {
+        let mut node = node(name, NodeType::Dir);
+        node.set_subtree(id);
+        node
+    }
+
+    fn find(
+        index: &impl IndexedBackend,
+        snaps: &[SnapshotFile],
+        args: &[&str],
+    ) -> Vec<Vec<String>> {
+        let opts = Opts::try_parse_from(["find"].iter().chain(args)).unwrap();
+        let matcher = Matcher::new(&opts).unwrap();
+        find_in_snapshots(index.clone(), matcher, snaps)
+            .unwrap()
+            .into_iter()
+            .map(|result| {
+                result
+                    .matches
+                    .into_iter()
+                    .map(|m| m.path.to_str().unwrap().to_string())
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn shared_subtree() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let be = LocalBackend::new(tmp.path().to_str().unwrap())?;
+        be.create()?;
+        let be = DecryptBackend::new(&be, Key::new());
+        let config = ConfigFile::new(2, Id::random(), 0);
+        let indexer = Indexer::new(be.clone()).into_shared();
+        let packer = Packer::new(be.clone(), BlobType::Tree, indexer.clone(), &config, 0)?;
+        let save = |nodes: Vec<Node>| -> Result<Id> {
+            let mut tree = Tree::new();
+            for node in nodes {
+                tree.add(node);
+            }
+            let (chunk, id) = tree.serialize()?;
+            packer.add(&chunk, &id)?;
+            Ok(id)
+        };
+
+        // the tree "shared" is contained at /a/shared and /b/shared in the first snapshot and
+        // at /shared in the second snapshot
+        let shared = save(vec![
+            node("x.txt", NodeType::File),
+            node("y.log", NodeType::File),
+        ])?;
+        let a = save(vec![dir("shared", shared)])?;
+        let b = save(vec![dir("shared", shared), node("z.txt", NodeType::File)])?;
+        let root = save(vec![dir("a", a), dir("b", b)])?;
+        packer.finalize()?;
+        indexer.write().unwrap().finalize()?;
+        let snaps = [
+            SnapshotFile {
+                tree: root,
+                ..Default::default()
+            },
+            SnapshotFile {
+                tree: a,
+                ..Default::default()
+            },
+        ];
+        let index = IndexBackend::new(&be, Progress::hidden())?;
+
+        // matches of the shared tree are found at all paths
+        assert_eq!(
+            find(&index, &snaps, &["--glob", "*.txt"]),
+            [
+                vec!["/a/shared/x.txt", "/b/z.txt", "/b/shared/x.txt"],
+                vec!["/shared/x.txt"]
+            ]
+        );
+        assert_eq!(
+            find(&index, &snaps, &["--glob", "shared"]),
+            [vec!["/a/shared", "/b/shared"], vec!["/shared"]]
+        );
+
+        // path dependent patterns only match at the respective paths
+        assert_eq!(
+            find(&index, &snaps, &["--glob", "/b/**/*.txt"]),
+            [vec!["/b/z.txt", "/b/shared/x.txt"]]
+        );
+        assert_eq!(
+            find(&index, &snaps, &["--regex", "^/(a/)?shared/.*log$"]),
+            [vec!["/a/shared/y.log"], vec!["/shared/y.log"]]
+        );
+        Ok(())
+    }
+}
@@ -16,6 +16,7 @@ use log::*;
 use rayon::ThreadPoolBuilder;
 
 use crate::backend::{FileType, ReadBackend};
+use crate::progress::{is_json_progress, Progress, ProgressKind};
 use crate::repofile::Id;
 use crate::repository::{parse_command, OpenRepository};
 
@@ -36,10 +37,13 @@ fn is_no_progress() -> bool {
     *NO_PROGRESS.lock().unwrap()
 }
 
-pub fn progress_spinner(prefix: impl Into<Cow<'static, str>>) -> ProgressBar {
+pub fn progress_spinner(prefix: impl Into<Cow<'static, str>>) -> Progress {
     if is_no_progress() {
         return no_progress();
     }
+    if is_json_progress() {
+        return Progress::json(ProgressKind::Spinner, prefix, progress_intervall());
+    }
     let p = ProgressBar::new(0).with_style(
         ProgressStyle::default_bar()
             .template("[{elapsed_precise}] {prefix:30} {spinner}")
@@ -47,13 +51,16 @@ pub fn progress_spinner(prefix: impl Into<Cow<'static, str>>) -> ProgressBar {
     );
     p.set_prefix(prefix);
     p.enable_steady_tick(progress_intervall());
-    p
+    Progress::Bar(p)
 }
 
-pub fn progress_counter(prefix: impl Into<Cow<'static, str>>) -> ProgressBar {
+pub fn progress_counter(prefix: impl Into<Cow<'static, str>>) -> Progress {
     if is_no_progress() {
         return no_progress();
     }
+    if is_json_progress() {
+        return Progress::json(ProgressKind::Counter, prefix, progress_intervall());
+    }
     let p = ProgressBar::new(0).with_style(
         ProgressStyle::default_bar()
             .template("[{elapsed_precise}] {prefix:30} {bar:40.cyan/blue} {pos:>10}/{len:10}")
@@ -61,17 +68,20 @@ pub fn progress_counter(prefix: impl Into<Cow<'static, str>>) -> ProgressBar {
     );
     p.set_prefix(prefix);
     p.enable_steady_tick(progress_intervall());
-    p
+    Progress::Bar(p)
 }
 
-pub fn no_progress() -> ProgressBar {
-    ProgressBar::hidden()
+pub fn no_progress() -> Progress {
+    Progress::hidden()
 }
 
-pub fn progress_bytes(prefix: impl Into<Cow<'static, str>>) -> ProgressBar {
+pub fn progress_bytes(prefix: impl Into<Cow<'static, str>>) -> Progress {
     if is_no_progress() {
         return no_progress();
     }
+    if is_json_progress() {
+        return Progress::json(ProgressKind::Bytes, prefix, progress_intervall());
+    }
     let p = ProgressBar::new(0).with_style(
             ProgressStyle::default_bar()
             .with_key("my_eta", |s: &ProgressState, w: &mut dyn Write| 
@@ -84,7 +94,7 @@ pub fn progress_bytes(prefix: impl Into<Cow<'static, str>>) -> ProgressBar {
             );
     p.set_prefix(prefix);
     p.enable_steady_tick(progress_intervall());
-    p
+    Progress::Bar(p)
 }
 
 pub fn warm_up_wait(
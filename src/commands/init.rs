@@ -9,7 +9,7 @@ use crate::backend::{DecryptBackend, DecryptWriteBackend, FileType, WriteBackend
 use crate::chunker;
 use crate::crypto::{hash, Key};
 use crate::id::Id;
-use crate::repofile::{ConfigFile, KeyFile};
+use crate::repofile::{ConfigFile, KdfParams, KeyFile};
 
 #[derive(Parser)]
 pub(super) struct Opts {
@@ -50,9 +50,11 @@ pub(super) fn execute(
     };
 
     let key_opts = opts.key_opts;
+    let kdf = key_opts.kdf_params(KdfParams::default())?;
     let keyfile = KeyFile::generate(
         key.clone(),
         &pass,
+        kdf,
         key_opts.hostname,
         key_opts.username,
         key_opts.with_created,
@@ -1,14 +1,22 @@
-use std::fs::File;
-use std::io::BufReader;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufReader, Read, Write};
+use std::path::{Path, PathBuf};
 
-use anyhow::Result;
+use anyhow::{anyhow, bail, Context, Result};
+use bytesize::ByteSize;
 use clap::{AppSettings, Parser, Subcommand};
+use data_encoding::BASE32_NOPAD;
 use rpassword::{prompt_password, read_password_from_bufread};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
 
-use crate::backend::{FileType, WriteBackend};
-use crate::crypto::{hash, Key};
-use crate::repofile::KeyFile;
-use crate::repository::OpenRepository;
+use super::config::save_config;
+use super::table_with_titles;
+use crate::backend::{FileType, ReadBackend, WriteBackend};
+use crate::crypto::{hash, CryptoKey, Key, PrivateKey, PublicKey, WriteOnlyKey};
+use crate::id::Id;
+use crate::repofile::{KdfParams, KeyFile};
+use crate::repository::{OpenRepository, Repository};
 
 #[derive(Parser)]
 pub(super) struct Opts {
@@ -18,10 +26,88 @@ pub(super) struct Opts {
 
 #[derive(Subcommand)]
 enum Command {
-    Add(AddOpts),
+    /// Add a new key to the repository
+    Add(AddKeyOpts),
+
+    /// List all keys of the repository
+    List(ListOpts),
+
+    /// Remove a key from the repository
+    Remove(RemoveOpts),
+
+    /// Change the password of the key currently used
+    Passwd(AddOpts),
+
+    /// Generate the key pair needed for write-only keys
+    GenerateKeypair(KeypairOpts),
+
+    /// Export the master key for an offline recovery
+    ExportMaster(ExportMasterOpts),
+
+    /// Add a new key using an exported master key. This doesn't need the password of the repository
+    ImportMaster(ImportMasterOpts),
+}
+
+impl Opts {
+    /// Options for `key import-master` which is run without opening the repository
+    pub(super) fn import_master_opts(&self) -> Option<&ImportMasterOpts> {
+        match &self.command {
+            Command::ImportMaster(opts) => Some(opts),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Parser)]
+pub(crate) struct ExportMasterOpts {
+    /// File to save the exported master key to [default: print to stdout]
+    #[clap(long, short, value_name = "FILE", parse(from_os_str))]
+    output: Option<PathBuf>,
+}
+
+#[derive(Parser)]
+pub(crate) struct ImportMasterOpts {
+    /// File containing the exported master key ("-" to read from stdin)
+    #[clap(value_name = "FILE", parse(from_os_str))]
+    file: PathBuf,
+
+    #[clap(flatten)]
+    add_opts: AddOpts,
+}
+
+#[derive(Parser)]
+pub(crate) struct AddKeyOpts {
+    #[clap(flatten)]
+    add_opts: AddOpts,
+
+    /// Add a write-only key which can only be used to backup. Reading the saved data needs the
+    /// private key of the repository, see `rustic key generate-keypair`
+    #[clap(long)]
+    write_only: bool,
+}
+
+#[derive(Parser)]
+pub(crate) struct KeypairOpts {
+    /// File to save the private key to. This file should be kept offline
+    #[clap(value_name = "FILE", parse(from_os_str))]
+    private_key_file: PathBuf,
 }
 
 #[derive(Parser)]
+pub(crate) struct ListOpts {
+    /// Show keys in json format
+    #[clap(long)]
+    json: bool,
+}
+
+#[derive(Parser)]
+pub(crate) struct RemoveOpts {
+    /// Key to remove
+    #[clap(value_name = "ID")]
+    id: String,
+}
+
+#[derive(Clone, Parser)]
 #[clap(global_setting(AppSettings::DeriveDisplayOrder))]
 pub(crate) struct AddOpts {
     /// File from which to read the new password
@@ -32,7 +118,7 @@ pub(crate) struct AddOpts {
     pub key_opts: KeyOpts,
 }
 
-#[derive(Parser)]
+#[derive(Clone, Parser)]
 #[clap(global_setting(AppSettings::DeriveDisplayOrder))]
 pub(crate) struct KeyOpts {
     /// Set 'hostname' in public key information
@@ -46,28 +132,365 @@ pub(crate) struct KeyOpts {
     /// Add 'created' date in public key information
     #[clap(long)]
     pub(crate) with_created: bool,
+
+    /// Key derivation function to use for the new key [default: scrypt]
+    #[clap(long, value_name = "KDF", possible_values = ["scrypt", "argon2id"])]
+    pub(crate) kdf: Option<String>,
+
+    /// Memory to use for the key derivation (e.g. 64MiB) [default: 128MiB for scrypt, 19MiB for argon2id]
+    #[clap(long, value_name = "SIZE")]
+    pub(crate) kdf_memory: Option<ByteSize>,
+
+    /// Number of iterations for the key derivation (for scrypt: the parallelization parameter p) [default: 1 for scrypt, 2 for argon2id]
+    #[clap(long, value_name = "N")]
+    pub(crate) kdf_iterations: Option<u32>,
+}
+
+impl KeyOpts {
+    /// Parameters of the key derivation function; values which are not given are taken from `params`
+    pub(crate) fn kdf_params(&self, params: KdfParams) -> Result<KdfParams> {
+        params.with(
+            self.kdf.as_deref(),
+            self.kdf_memory.map(|size| size.as_u64()),
+            self.kdf_iterations,
+        )
+    }
 }
 
 pub(super) fn execute(repo: OpenRepository, opts: Opts) -> Result<()> {
     match opts.command {
-        Command::Add(opt) => add_key(&repo.dbe, repo.key, opt),
+        Command::Add(opt) => {
+            let id = match opt.write_only {
+                true => add_write_only_key(&repo, opt.add_opts)?,
+                false => add_key(
+                    &repo.dbe,
+                    master_key(&repo)?.clone(),
+                    KdfParams::default(),
+                    opt.add_opts,
+                )?,
+            };
+            println!("key {id} successfully added.");
+            Ok(())
+        }
+        Command::List(opt) => list_keys(&repo, opt),
+        Command::Remove(opt) => remove_key(&repo, opt),
+        Command::Passwd(opt) => change_password(&repo, opt),
+        Command::GenerateKeypair(opt) => generate_keypair(&repo, opt),
+        Command::ExportMaster(opt) => export_master(&repo, opt),
+        Command::ImportMaster(_) => Ok(()), // already handled before opening the repository
     }
 }
 
-fn add_key(be: &impl WriteBackend, key: Key, opts: AddOpts) -> Result<()> {
-    let pass = match opts.new_password_file {
+fn master_key(repo: &OpenRepository) -> Result<&Key> {
+    repo.key
+        .master_key()
+        .ok_or_else(|| anyhow!("not possible using a write-only key"))
+}
+
+/// Read the password for a new key from the given file or prompt for it
+pub(super) fn new_password(file: Option<String>) -> Result<String> {
+    Ok(match file {
         Some(file) => {
             let mut file = BufReader::new(File::open(file)?);
             read_password_from_bufread(&mut file)?
         }
         None => prompt_password("enter password for new key: ")?,
-    };
+    })
+}
+
+/// Add a key file for the key. Parameters of the key derivation which are not given in the options
+/// are taken from `kdf`.
+pub(super) fn add_key(
+    be: &impl WriteBackend,
+    key: Key,
+    kdf: KdfParams,
+    opts: AddOpts,
+) -> Result<Id> {
+    let pass = new_password(opts.new_password_file)?;
     let ko = opts.key_opts;
-    let keyfile = KeyFile::generate(key, &pass, ko.hostname, ko.username, ko.with_created)?;
-    let data = serde_json::to_vec(&keyfile)?;
+    let kdf = ko.kdf_params(kdf)?;
+    let keyfile = KeyFile::generate(key, &pass, kdf, ko.hostname, ko.username, ko.with_created)?;
+    save_keyfile(be, &keyfile)
+}
+
+fn save_keyfile(be: &impl WriteBackend, keyfile: &KeyFile) -> Result<Id> {
+    let data = serde_json::to_vec(keyfile)?;
     let id = hash(&data);
     be.write_bytes(FileType::Key, &id, false, data.into())?;
+    Ok(id)
+}
+
+fn add_write_only_key(repo: &OpenRepository, opts: AddOpts) -> Result<Id> {
+    let Some(public_key) = &repo.config.public_key else {
+        bail!("repository has no public key. Please generate one using `rustic key generate-keypair`.");
+    };
+    let public_key = PublicKey::from_hex(public_key)?;
+    let pass = new_password(opts.new_password_file)?;
+
+    // only the public key can read the initial key of the write-only key
+    let key = WriteOnlyKey::new();
+    let mut config = repo.config.clone();
+    config.write_only_keys.push(key.seal(&public_key)?);
+    save_config(repo, &config, master_key(repo)?)?;
+
+    // the write-only key cannot read the repository config, so it gets its own copy
+    config.write_only_keys.clear();
+    config.is_hot = None;
+    let ko = opts.key_opts;
+    let kdf = ko.kdf_params(KdfParams::default())?;
+    let keyfile = KeyFile::generate_write_only(
+        &key,
+        config,
+        &pass,
+        kdf,
+        ko.hostname,
+        ko.username,
+        ko.with_created,
+    )?;
+    save_keyfile(&repo.be, &keyfile)
+}
+
+fn generate_keypair(repo: &OpenRepository, opts: KeypairOpts) -> Result<()> {
+    if repo.config.public_key.is_some() {
+        bail!("repository already has a public key.");
+    }
+    let private_key = PrivateKey::generate();
+    let file = &opts.private_key_file;
+    let mut f = create_secret_file(file)?;
+    writeln!(f, "{}", private_key.to_hex())?;
+    f.sync_all()?;
+
+    let mut config = repo.config.clone();
+    config.public_key = Some(private_key.public_key().to_hex());
+    save_config(repo, &config, master_key(repo)?)?;
+    println!("saved private key to {file:?}.");
+    println!("Keep it offline; it is only needed to read data saved using write-only keys.");
+    Ok(())
+}
+
+// create a new file which is only readable by the current user
+fn create_secret_file(file: &Path) -> Result<File> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options
+        .open(file)
+        .with_context(|| format!("error creating file {file:?}"))
+}
+
+// number of characters per block and blocks per line of an exported master key
+const EXPORT_BLOCK_LEN: usize = 5;
+const EXPORT_LINE_BLOCKS: usize = 5;
+// length of the checksum appended to the exported master key
+const EXPORT_CHECKSUM_LEN: usize = 4;
+
+fn checksum(data: &[u8]) -> Vec<u8> {
+    Sha256::digest(data)[..EXPORT_CHECKSUM_LEN].to_vec()
+}
+
+/// Encode the master key as base32 in blocks of 5 characters. A checksum is appended to detect
+/// transcription errors.
+fn encode_master_key(key: &Key) -> String {
+    let mut data = key.as_bytes().to_vec();
+    data.extend_from_slice(&checksum(key.as_bytes()));
+    let encoded = BASE32_NOPAD.encode(&data);
+    let blocks: Vec<_> = encoded
+        .as_bytes()
+        .chunks(EXPORT_BLOCK_LEN)
+        .map(|block| String::from_utf8_lossy(block).to_string())
+        .collect();
+    blocks
+        .chunks(EXPORT_LINE_BLOCKS)
+        .map(|line| line.join(" "))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Decode a master key exported by [`encode_master_key`]. Lines starting with '#' are ignored.
+fn decode_master_key(export: &str) -> Result<Key> {
+    let encoded: String = export
+        .lines()
+        .filter(|line| !line.trim_start().starts_with('#'))
+        .flat_map(str::chars)
+        .filter(|c| !c.is_whitespace() && *c != '-')
+        // 0, 1 and 8 are not used by base32 and are likely to be misread letters
+        .map(|c| match c.to_ascii_uppercase() {
+            '0' => 'O',
+            '1' => 'I',
+            '8' => 'B',
+            c => c,
+        })
+        .collect();
+    let data = BASE32_NOPAD
+        .decode(encoded.as_bytes())
+        .map_err(|err| anyhow!("invalid master key export: {err}"))?;
+    if data.len() != 64 + EXPORT_CHECKSUM_LEN {
+        bail!("invalid master key export: wrong length");
+    }
+    let (key, checksum_read) = data.split_at(64);
+    if checksum(key) != checksum_read {
+        bail!("invalid master key export: checksum does not match. Please check for typos.");
+    }
+    Ok(Key::from_slice(key))
+}
+
+fn export_master(repo: &OpenRepository, opts: ExportMasterOpts) -> Result<()> {
+    let export = format!(
+        "# rustic master key of repository {}\n{}\n",
+        repo.config.id.to_hex().as_str(),
+        encode_master_key(master_key(repo)?)
+    );
+    match &opts.output {
+        Some(file) => {
+            let mut f = create_secret_file(file)?;
+            f.write_all(export.as_bytes())?;
+            f.sync_all()?;
+            println!("saved master key to {file:?}.");
+        }
+        None => print!("{export}"),
+    }
+    Ok(())
+}
+
+/// Add a new key for the master key given in the export file
+pub(super) fn import_master(repo: &Repository, opts: &ImportMasterOpts) -> Result<()> {
+    let export = match opts.file.to_str() {
+        Some("-") => {
+            let mut export = String::new();
+            std::io::stdin().read_to_string(&mut export)?;
+            export
+        }
+        _ => fs::read_to_string(&opts.file)
+            .with_context(|| format!("error reading {:?}", opts.file))?,
+    };
+    let key = decode_master_key(&export)?;
+
+    let config_ids = repo.be.list(FileType::Config)?;
+    let [config_id] = config_ids[..] else {
+        bail!("no unique repository config file found at {}", repo.name);
+    };
+    let config = repo.be.read_full(FileType::Config, &config_id)?;
+    if key.decrypt_data(&config).is_err() {
+        bail!("master key does not belong to the repository {}", repo.name);
+    }
 
+    let id = add_key(&repo.be, key, KdfParams::default(), opts.add_opts.clone())?;
     println!("key {id} successfully added.");
     Ok(())
 }
+
+fn list_keys(repo: &OpenRepository, opts: ListOpts) -> Result<()> {
+    #[derive(Serialize)]
+    struct KeyInfo {
+        id: Id,
+        current: bool,
+        hostname: Option<String>,
+        username: Option<String>,
+        created: Option<String>,
+    }
+
+    let mut keys = repo
+        .be
+        .list(FileType::Key)?
+        .into_iter()
+        .map(|id| {
+            let key = KeyFile::from_backend(&repo.be, &id)?;
+            Ok(KeyInfo {
+                id,
+                current: id == repo.key_id,
+                hostname: key.hostname,
+                username: key.username,
+                created: key
+                    .created
+                    .map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string()),
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    keys.sort_unstable_by(|k1, k2| k1.created.cmp(&k2.created).then(k1.id.cmp(&k2.id)));
+
+    if opts.json {
+        let mut stdout = std::io::stdout();
+        serde_json::to_writer_pretty(&mut stdout, &keys)?;
+        return Ok(());
+    }
+
+    let mut table = table_with_titles(["ID", "User", "Host", "Created"]);
+    for key in keys {
+        let id = if key.current {
+            format!("*{}", key.id)
+        } else {
+            format!(" {}", key.id)
+        };
+        table.add_row([
+            id,
+            key.username.unwrap_or_default(),
+            key.hostname.unwrap_or_default(),
+            key.created.unwrap_or_default(),
+        ]);
+    }
+    println!("{table}");
+    Ok(())
+}
+
+fn remove_key(repo: &OpenRepository, opts: RemoveOpts) -> Result<()> {
+    // a compromised write-only key must not be able to make the repository unreadable
+    _ = master_key(repo)?;
+    let id = repo.be.find_id(FileType::Key, &opts.id)?;
+    if id == repo.key_id {
+        bail!("key {id} is currently used and cannot be removed.");
+    }
+    if !KeyFile::from_backend(&repo.be, &id)?.write_only {
+        let mut master_keys = 0;
+        for key_id in repo.be.list(FileType::Key)? {
+            if !KeyFile::from_backend(&repo.be, &key_id)?.write_only {
+                master_keys += 1;
+            }
+        }
+        if master_keys <= 1 {
+            bail!("key {id} is the last master key of the repository and cannot be removed.");
+        }
+    }
+    repo.be.remove(FileType::Key, &id, false)?;
+    println!("key {id} successfully removed.");
+    Ok(())
+}
+
+fn change_password(repo: &OpenRepository, mut opts: AddOpts) -> Result<()> {
+    // keep the information and the key derivation of the old key unless new values are given
+    let old_key = KeyFile::from_backend(&repo.be, &repo.key_id)?;
+    let kdf = old_key.kdf_params()?;
+    let ko = &mut opts.key_opts;
+    ko.hostname = ko.hostname.take().or(old_key.hostname);
+    ko.username = ko.username.take().or(old_key.username);
+    ko.with_created |= old_key.created.is_some();
+
+    let id = add_key(&repo.be, master_key(repo)?.clone(), kdf, opts)?;
+    repo.be.remove(FileType::Key, &repo.key_id, false)?;
+    println!(
+        "password changed: key {} replaced by key {id}.",
+        repo.key_id
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn export_import_master_key() {
+        let key = Key::new();
+        let export = encode_master_key(&key);
+        assert_eq!(export.lines().count(), 5);
+        let export = format!("# comment\n{}", export.to_lowercase());
+        let imported = decode_master_key(&export).unwrap();
+        assert_eq!(imported.as_bytes(), key.as_bytes());
+
+        // a typo is detected by the checksum
+        let mut export = export.into_bytes();
+        let pos = export.iter().position(|c| *c == b'\n').unwrap() + 1;
+        export[pos] = if export[pos] == b'a' { b'b' } else { b'a' };
+        assert!(decode_master_key(&String::from_utf8(export).unwrap()).is_err());
+    }
+}
@@ -1,15 +1,15 @@
 use anyhow::{bail, Result};
 use clap::Parser;
-use indicatif::ProgressBar;
 
 use crate::backend::{DecryptReadBackend, FileType, ReadBackend};
+use crate::progress::Progress;
 use crate::repofile::IndexFile;
 use crate::repository::OpenRepository;
 
 #[derive(Parser)]
 pub(super) struct Opts {
     /// File type to list
-    #[clap(possible_values=["blobs", "index", "packs", "snapshots", "keys"])]
+    #[clap(possible_values=["blobs", "index", "packs", "snapshots", "keys", "locks"])]
     tpe: String,
 }
 
@@ -17,7 +17,7 @@ pub(super) fn execute(repo: OpenRepository, opts: Opts) -> Result<()> {
     let tpe = match opts.tpe.as_str() {
         // special treatment for listing blobs: read the index and display it
         "blobs" => {
-            for index in repo.dbe.stream_all::<IndexFile>(ProgressBar::hidden())? {
+            for index in repo.dbe.stream_all::<IndexFile>(Progress::hidden())? {
                 for pack in index?.1.packs {
                     for blob in pack.blobs {
                         println!("{:?} {:?}", blob.tpe, blob.id);
@@ -30,6 +30,7 @@ pub(super) fn execute(repo: OpenRepository, opts: Opts) -> Result<()> {
         "packs" => FileType::Pack,
         "snapshots" => FileType::Snapshot,
         "keys" => FileType::Key,
+        "locks" => FileType::Lock,
         t => bail!("invalid type: {}", t),
     };
 
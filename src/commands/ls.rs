@@ -6,7 +6,7 @@ use super::progress_counter;
 use super::rustic_config::RusticConfig;
 use crate::blob::{NodeStreamer, Tree};
 use crate::index::IndexBackend;
-use crate::repofile::{SnapshotFile, SnapshotFilter};
+use crate::repofile::{split_snapshot_path, SnapshotFile, SnapshotFilter, SnapshotRefGroup};
 use crate::repository::OpenRepository;
 
 #[derive(Parser)]
@@ -14,6 +14,9 @@ pub(super) struct Opts {
     #[clap(flatten, help_heading = "SNAPSHOT FILTER OPTIONS (when using latest)")]
     filter: SnapshotFilter,
 
+    #[clap(flatten)]
+    group: SnapshotRefGroup,
+
     /// Snapshot/path to list
     #[clap(value_name = "SNAPSHOT[:PATH]")]
     snap: String,
@@ -27,8 +30,14 @@ pub(super) fn execute(
     config_file.merge_into("snapshot-filter", &mut opts.filter)?;
     let be = &repo.dbe;
 
-    let (id, path) = opts.snap.split_once(':').unwrap_or((&opts.snap, ""));
-    let snap = SnapshotFile::from_str(be, id, |sn| sn.matches(&opts.filter), progress_counter(""))?;
+    let (id, path) = split_snapshot_path(&opts.snap).unwrap_or((&opts.snap, ""));
+    let snap = SnapshotFile::from_str(
+        be,
+        id,
+        |sn| sn.matches(&opts.filter),
+        opts.group.criterion(),
+        progress_counter(""),
+    )?;
     let index = IndexBackend::new(be, progress_counter(""))?;
     let node = Tree::node_from_path(&index, snap.tree, Path::new(path))?;
 
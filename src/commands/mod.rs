@@ -1,7 +1,7 @@
 use std::fs::File;
 use std::path::PathBuf;
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 use clap::{Parser, Subcommand};
 use merge::Merge;
 use serde::Deserialize;
@@ -9,6 +9,8 @@ use serde_with::{serde_as, DisplayFromStr};
 use simplelog::*;
 
 use crate::backend::{FileType, ReadBackend};
+use crate::metrics::{enable_metrics, export_metrics, run_metrics};
+use crate::progress::{set_json_writer, EventLogger};
 use crate::repository::{Repository, RepositoryOptions};
 
 use helpers::*;
@@ -20,7 +22,9 @@ mod completions;
 mod config;
 mod copy;
 mod diff;
+mod du;
 mod dump;
+mod find;
 mod forget;
 mod helpers;
 mod init;
@@ -28,14 +32,20 @@ mod key;
 mod list;
 mod ls;
 mod merge_cmd;
+#[cfg(all(feature = "mount", target_os = "linux"))]
+mod mount;
 mod prune;
+mod rekey;
 mod repair;
 mod repoinfo;
 mod restore;
+mod rewrite;
 mod rustic_config;
 mod self_update;
 mod snapshots;
+mod stats;
 mod tag;
+mod unlock;
 
 use rustic_config::RusticConfig;
 
@@ -91,6 +101,48 @@ struct GlobalOpts {
     )]
     #[serde_as(as = "Option<DisplayFromStr>")]
     progress_interval: Option<humantime::Duration>,
+
+    /// Report progress, warnings, errors and a final summary as line-delimited JSON events on stderr
+    /// instead of showing progress bars and log messages (use --log-file to keep a text log)
+    #[clap(
+        long,
+        global = true,
+        env = "RUSTIC_JSON_PROGRESS",
+        conflicts_with = "no-progress"
+    )]
+    #[merge(strategy=merge::bool::overwrite_false)]
+    json_progress: bool,
+
+    /// Write JSON progress events to the given file instead of stderr (implies --json-progress)
+    #[clap(
+        long,
+        global = true,
+        env = "RUSTIC_JSON_PROGRESS_FILE",
+        value_name = "FILE",
+        conflicts_with = "no-progress"
+    )]
+    json_progress_file: Option<PathBuf>,
+
+    /// Write metrics of the backup, prune and check commands in the Prometheus text format, e.g. to be
+    /// used by the textfile collector of the `node_exporter`. The command name is appended to the
+    /// file name, e.g. `rustic.prom` becomes `rustic_backup.prom`
+    #[clap(
+        long,
+        global = true,
+        env = "RUSTIC_PROMETHEUS_FILE",
+        value_name = "FILE"
+    )]
+    prometheus_file: Option<PathBuf>,
+
+    /// Push metrics of the backup, prune and check commands to the Prometheus pushgateway at the
+    /// given URL
+    #[clap(
+        long,
+        global = true,
+        env = "RUSTIC_PROMETHEUS_PUSHGATEWAY",
+        value_name = "URL"
+    )]
+    prometheus_pushgateway: Option<String>,
 }
 
 #[derive(Subcommand)]
@@ -118,9 +170,15 @@ enum Command {
     /// Note that the exclude options only apply for comparison with a local path
     Diff(diff::Opts),
 
-    /// dump the contents of a file in a snapshot to stdout
+    /// Show the size of the directories within a snapshot
+    Du(du::Opts),
+
+    /// dump the contents of a file or a directory (as tar or zip archive) in a snapshot
     Dump(dump::Opts),
 
+    /// Find files or directories in snapshots
+    Find(find::Opts),
+
     /// Remove snapshots from the repository
     Forget(forget::Opts),
 
@@ -139,18 +197,31 @@ enum Command {
     /// Merge snapshots
     Merge(merge_cmd::Opts),
 
+    /// Mount the repository as read-only filesystem
+    #[cfg(all(feature = "mount", target_os = "linux"))]
+    Mount(mount::Opts),
+
     /// Show a detailed overview of the snapshots within the repository
     Snapshots(snapshots::Opts),
 
     /// Update to the latest rustic release
     SelfUpdate(self_update::Opts),
 
+    /// Show statistics about the size of snapshots
+    Stats(stats::Opts),
+
     /// Remove unused data or repack repository pack files
     Prune(prune::Opts),
 
     /// Restore a snapshot/path
     Restore(restore::Opts),
 
+    /// Remove files from existing snapshots
+    Rewrite(rewrite::Opts),
+
+    /// Re-encrypt the whole repository using a new master key
+    Rekey(rekey::Opts),
+
     /// Restore a snapshot/path
     Repair(repair::Opts),
 
@@ -159,46 +230,64 @@ enum Command {
 
     /// Change tags of snapshots
     Tag(tag::Opts),
+
+    /// Remove stale locks from the repository
+    Unlock(unlock::Opts),
 }
 
 pub fn execute() -> Result<()> {
+    use clap::{CommandFactory, FromArgMatches};
+
     let command: Vec<_> = std::env::args_os().collect();
-    let args = Opts::parse_from(&command);
+    let matches = Opts::command().get_matches_from(&command);
+    let args = Opts::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+    let command_name = matches.subcommand_name().unwrap_or_default().to_string();
 
     // get global options from command line / env and config file
     let config_file = RusticConfig::new(&args.config_profile)?;
     let mut opts = args.global;
     config_file.merge_into("global", &mut opts)?;
 
+    // JSON events on stderr must not be interleaved with text log output, so the terminal logger is
+    // not used in this case. Warnings and errors are then reported as JSON events instead.
+    let json_on_stderr = opts.json_progress && opts.json_progress_file.is_none();
+
     // start logger
     let level_filter = opts.log_level.unwrap_or(LevelFilter::Info);
-    match opts.log_file {
-        None => TermLogger::init(
-            level_filter,
+    let mut loggers: Vec<Box<dyn SharedLogger>> = Vec::new();
+    if !json_on_stderr {
+        let term_level = match opts.log_file {
+            None => level_filter,
+            Some(_) => level_filter.max(LevelFilter::Warn),
+        };
+        loggers.push(TermLogger::new(
+            term_level,
             ConfigBuilder::new()
                 .set_time_level(LevelFilter::Off)
                 .build(),
             TerminalMode::Stderr,
             ColorChoice::Auto,
-        )?,
-
-        Some(file) => CombinedLogger::init(vec![
-            TermLogger::new(
-                level_filter.max(LevelFilter::Warn),
-                ConfigBuilder::new()
-                    .set_time_level(LevelFilter::Off)
-                    .build(),
-                TerminalMode::Stderr,
-                ColorChoice::Auto,
-            ),
-            WriteLogger::new(
-                level_filter,
-                Config::default(),
-                File::options().create(true).append(true).open(file)?,
-            ),
-        ])?,
+        ));
+    }
+    if let Some(file) = opts.log_file {
+        loggers.push(WriteLogger::new(
+            level_filter,
+            Config::default(),
+            File::options().create(true).append(true).open(file)?,
+        ));
     }
 
+    // setup JSON progress events
+    match opts.json_progress_file {
+        Some(file) => set_json_writer(Box::new(
+            File::options().create(true).append(true).open(file)?,
+        )),
+        None if json_on_stderr => set_json_writer(Box::new(std::io::stderr())),
+        None => {}
+    }
+    loggers.push(Box::new(EventLogger));
+    CombinedLogger::init(loggers)?;
+
     if opts.no_progress {
         let mut no_progress = NO_PROGRESS.lock().unwrap();
         *no_progress = true;
@@ -209,6 +298,16 @@ pub fn execute() -> Result<()> {
         *interval = *duration;
     }
 
+    // metrics are only exported for commands which record some
+    if (opts.prometheus_file.is_some() || opts.prometheus_pushgateway.is_some())
+        && matches!(
+            args.command,
+            Command::Backup(_) | Command::Prune(_) | Command::Check(_)
+        )
+    {
+        enable_metrics();
+    }
+
     if let Command::SelfUpdate(opts) = args.command {
         self_update::execute(opts)?;
         return Ok(());
@@ -234,32 +333,76 @@ pub fn execute() -> Result<()> {
         return init::execute(&repo.be, &repo.be_hot, opts, repo.password()?, config_ids);
     }
 
+    // importing a master key is used when no password for the repository is available
+    if let Command::Key(opts) = &args.command {
+        if let Some(opts) = opts.import_master_opts() {
+            return key::import_master(&repo, opts);
+        }
+    }
+
     let repo = repo.open()?;
 
+    if let Some(id) = repo.config.rekey_key {
+        if !matches!(args.command, Command::Rekey(_) | Command::Unlock(_)) {
+            bail!("an interrupted rekey to key {id} is in progress. Please finish it by running `rustic rekey`.");
+        }
+    }
+
+    if repo.key.is_write_only() && !matches!(args.command, Command::Backup(_)) {
+        bail!("only `rustic backup` can be used with a write-only key.");
+    }
+
+    // lock the repository if locking is enabled in the repository config
+    let _lock = match &args.command {
+        Command::Backup(_) => repo.lock(false)?,
+        Command::Forget(_)
+        | Command::Prune(_)
+        | Command::Rekey(_)
+        | Command::Repair(_)
+        | Command::Rewrite(_) => repo.lock(true)?,
+        _ => None,
+    };
+
     #[allow(clippy::match_same_arms)]
-    match args.command {
-        Command::Backup(opts) => backup::execute(repo, opts, config_file, command)?,
-        Command::Config(opts) => config::execute(repo, opts)?,
-        Command::Cat(opts) => cat::execute(repo, opts, config_file)?,
-        Command::Check(opts) => check::execute(repo, opts)?,
-        Command::Completions(_) => {} // already handled above
-        Command::Copy(opts) => copy::execute(repo, opts, config_file)?,
-        Command::Diff(opts) => diff::execute(repo, opts, config_file)?,
-        Command::Dump(opts) => dump::execute(repo, opts, config_file)?,
-        Command::Forget(opts) => forget::execute(repo, opts, config_file)?,
-        Command::Init(_) => {} // already handled above
-        Command::Key(opts) => key::execute(repo, opts)?,
-        Command::List(opts) => list::execute(repo, opts)?,
-        Command::Ls(opts) => ls::execute(repo, opts, config_file)?,
-        Command::Merge(opts) => merge_cmd::execute(repo, opts, config_file, command)?,
-        Command::SelfUpdate(_) => {} // already handled above
-        Command::Snapshots(opts) => snapshots::execute(repo, opts, config_file)?,
-        Command::Prune(opts) => prune::execute(repo, opts, vec![])?,
-        Command::Restore(opts) => restore::execute(repo, opts, config_file)?,
-        Command::Repair(opts) => repair::execute(repo, opts, config_file)?,
-        Command::Repoinfo(opts) => repoinfo::execute(repo, opts)?,
-        Command::Tag(opts) => tag::execute(repo, opts, config_file)?,
+    let result = match args.command {
+        Command::Backup(opts) => backup::execute(repo, opts, config_file, command),
+        Command::Config(opts) => config::execute(repo, opts),
+        Command::Cat(opts) => cat::execute(repo, opts, config_file),
+        Command::Check(opts) => check::execute(repo, opts),
+        Command::Completions(_) => Ok(()), // already handled above
+        Command::Copy(opts) => copy::execute(repo, opts, config_file),
+        Command::Diff(opts) => diff::execute(repo, opts, config_file),
+        Command::Du(opts) => du::execute(repo, opts, config_file),
+        Command::Dump(opts) => dump::execute(repo, opts, config_file),
+        Command::Find(opts) => find::execute(repo, opts, config_file),
+        Command::Forget(opts) => forget::execute(repo, opts, config_file),
+        Command::Init(_) => Ok(()), // already handled above
+        Command::Key(opts) => key::execute(repo, opts),
+        Command::List(opts) => list::execute(repo, opts),
+        Command::Ls(opts) => ls::execute(repo, opts, config_file),
+        Command::Merge(opts) => merge_cmd::execute(repo, opts, config_file, command),
+        #[cfg(all(feature = "mount", target_os = "linux"))]
+        Command::Mount(opts) => mount::execute(repo, opts, config_file),
+        Command::SelfUpdate(_) => Ok(()), // already handled above
+        Command::Snapshots(opts) => snapshots::execute(repo, opts, config_file),
+        Command::Stats(opts) => stats::execute(repo, opts, config_file),
+        Command::Prune(opts) => prune::execute(repo, opts, vec![]),
+        Command::Restore(opts) => restore::execute(repo, opts, config_file),
+        Command::Rekey(opts) => rekey::execute(repo, opts),
+        Command::Rewrite(opts) => rewrite::execute(repo, opts, config_file),
+        Command::Repair(opts) => repair::execute(repo, opts, config_file),
+        Command::Repoinfo(opts) => repoinfo::execute(repo, opts),
+        Command::Tag(opts) => tag::execute(repo, opts, config_file),
+        Command::Unlock(opts) => unlock::execute(repo, opts),
     };
 
-    Ok(())
+    // export metrics also for failed runs
+    run_metrics(&command_name, result.is_ok())?;
+    let exported = export_metrics(
+        &command_name,
+        opts.prometheus_file.as_deref(),
+        opts.prometheus_pushgateway.as_deref(),
+    );
+    result?;
+    exported
 }
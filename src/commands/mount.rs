@@ -0,0 +1,863 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::ffi::{OsStr, OsString};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use clap::Parser;
+use fuser::{
+    consts::FOPEN_KEEP_CACHE, FileAttr, FileType, Filesystem, MountOption, ReplyAttr, ReplyData,
+    ReplyDirectory, ReplyEmpty, ReplyEntry, ReplyOpen, ReplyXattr, Request, Session, FUSE_ROOT_ID,
+};
+use log::*;
+use nix::errno::Errno;
+use nix::unistd::{getgid, getuid};
+use signal_hook::{consts::SIGINT, consts::SIGTERM, iterator::Signals};
+
+use super::{progress_counter, RusticConfig};
+use crate::backend::mapper::map_mode_from_go;
+use crate::blob::{BlobType, Metadata, Node, NodeType, Tree};
+use crate::id::Id;
+use crate::index::{IndexBackend, IndexedBackend};
+use crate::repofile::{SnapshotFile, SnapshotFilter};
+use crate::repository::OpenRepository;
+
+#[derive(Parser)]
+pub(super) struct Opts {
+    #[clap(flatten, help_heading = "SNAPSHOT FILTER OPTIONS")]
+    filter: SnapshotFilter,
+
+    /// Allow other users to access the mounted filesystem (permissions are still checked)
+    #[clap(long)]
+    allow_other: bool,
+
+    /// Directory to mount the repository at
+    #[clap(value_name = "MOUNTPOINT")]
+    mountpoint: PathBuf,
+}
+
+pub(super) fn execute(
+    repo: OpenRepository,
+    mut opts: Opts,
+    config_file: RusticConfig,
+) -> Result<()> {
+    config_file.merge_into("snapshot-filter", &mut opts.filter)?;
+    let be = &repo.dbe;
+
+    let mut snapshots = SnapshotFile::all_from_backend(be, &opts.filter)?;
+    snapshots.sort_unstable();
+    let index = IndexBackend::new(be, progress_counter(""))?;
+
+    info!(
+        "serving {} snapshots at {:?}; use Ctrl-C or umount to unmount.",
+        snapshots.len(),
+        opts.mountpoint
+    );
+    let fs = SnapshotFs::new(index, snapshots);
+    mount(fs, &opts.mountpoint, opts.allow_other)
+}
+
+/// Mount `fs` at `mountpoint` and serve requests until the filesystem is unmounted.
+///
+/// The filesystem is unmounted when receiving SIGINT or SIGTERM.
+fn mount(fs: impl Filesystem, mountpoint: &Path, allow_other: bool) -> Result<()> {
+    let mountpoint = dunce::canonicalize(mountpoint)
+        .with_context(|| format!("mount point {mountpoint:?} does not exist"))?;
+    let mut session = Session::new(fs, &mountpoint, &mount_options(allow_other))
+        .with_context(|| format!("error mounting {mountpoint:?}"))?;
+
+    // unmounting in a separate thread causes the session loop to end
+    let mut unmounter = session.unmount_callable();
+    let mut signals = Signals::new([SIGINT, SIGTERM])?;
+    std::thread::spawn(move || {
+        if signals.forever().next().is_some() {
+            info!("unmounting {mountpoint:?}...");
+            if let Err(err) = unmounter.unmount() {
+                error!("error unmounting {mountpoint:?}: {err}");
+            }
+        }
+    });
+    session.run()?;
+    Ok(())
+}
+
+fn mount_options(allow_other: bool) -> Vec<MountOption> {
+    let mut options = vec![
+        MountOption::RO,
+        MountOption::NoSuid,
+        MountOption::NoDev,
+        MountOption::DefaultPermissions,
+        MountOption::FSName("rustic".to_string()),
+        MountOption::Subtype("rustic".to_string()),
+    ];
+    if allow_other {
+        options.push(MountOption::AllowOther);
+    }
+    options
+}
+
+const SNAPSHOTS_DIR: &str = "snapshots";
+const LATEST: &str = "latest";
+
+/// Inode number of the root directory
+const ROOT_INO: u64 = FUSE_ROOT_ID;
+/// Inode number reported by readdir for entries which have not been looked up yet
+const UNKNOWN_INO: u64 = 0xffff_ffff;
+/// Time the kernel is allowed to cache entries and attributes
+const TTL: Duration = Duration::from_secs(60);
+/// Number of trees kept in memory; lookups and readdirs usually access the same few directories
+const TREE_CACHE_SIZE: usize = 64;
+
+enum InodeKind {
+    Root,
+    SnapshotsDir,
+    // a node within a snapshot; the snapshot root dirs are also given as nodes
+    Node(Box<Node>),
+}
+
+struct Inode {
+    parent: u64,
+    name: OsString,
+    kind: InodeKind,
+    // number of lookups not yet forgotten by the kernel
+    nlookup: u64,
+}
+
+// inode of the snapshots dir; this and the root dir are never removed
+const SNAPSHOTS_INO: u64 = ROOT_INO + 1;
+
+/// All inodes known to the kernel. Inodes are added by lookups and removed once the kernel has
+/// forgotten all of their lookups, so the table doesn't grow during a long-running mount.
+struct Inodes {
+    inodes: HashMap<u64, Inode>,
+    children: HashMap<(u64, OsString), u64>,
+    next_ino: u64,
+}
+
+impl Inodes {
+    fn new() -> Self {
+        let mut inodes = Self {
+            inodes: HashMap::new(),
+            children: HashMap::new(),
+            next_ino: ROOT_INO,
+        };
+        inodes.insert(ROOT_INO, OsStr::new(""), InodeKind::Root);
+        inodes.insert(ROOT_INO, OsStr::new(SNAPSHOTS_DIR), InodeKind::SnapshotsDir);
+        inodes
+    }
+
+    fn get(&self, ino: u64) -> Result<&Inode, Errno> {
+        self.inodes.get(&ino).ok_or(Errno::ENOENT)
+    }
+
+    fn child(&self, parent: u64, name: &OsStr) -> Option<u64> {
+        self.children.get(&(parent, name.to_os_string())).copied()
+    }
+
+    fn insert(&mut self, parent: u64, name: &OsStr, kind: InodeKind) -> u64 {
+        let ino = self.next_ino;
+        self.next_ino += 1;
+        let name = name.to_os_string();
+        if ino != ROOT_INO {
+            self.children.insert((parent, name.clone()), ino);
+        }
+        self.inodes.insert(
+            ino,
+            Inode {
+                parent,
+                name,
+                kind,
+                nlookup: 0,
+            },
+        );
+        ino
+    }
+
+    fn lookup(&mut self, ino: u64) {
+        if let Some(inode) = self.inodes.get_mut(&ino) {
+            inode.nlookup += 1;
+        }
+    }
+
+    fn forget(&mut self, ino: u64, nlookup: u64) {
+        if ino <= SNAPSHOTS_INO {
+            return;
+        }
+        let Some(inode) = self.inodes.get_mut(&ino) else {
+            return;
+        };
+        inode.nlookup = inode.nlookup.saturating_sub(nlookup);
+        if inode.nlookup == 0 {
+            let inode = self.inodes.remove(&ino).unwrap();
+            self.children.remove(&(inode.parent, inode.name));
+        }
+    }
+}
+
+struct DirEntry {
+    ino: u64,
+    kind: FileType,
+    name: OsString,
+}
+
+/// Nodes of the trees which have been read recently
+#[derive(Default)]
+struct TreeCache {
+    trees: HashMap<Id, Arc<Vec<Node>>>,
+    // ids in the order the trees have been added
+    order: VecDeque<Id>,
+}
+
+impl TreeCache {
+    fn get(&self, id: &Id) -> Option<Arc<Vec<Node>>> {
+        self.trees.get(id).cloned()
+    }
+
+    fn insert(&mut self, id: Id, tree: Arc<Vec<Node>>) {
+        if self.trees.insert(id, tree).is_none() {
+            self.order.push_back(id);
+        }
+        if self.order.len() > TREE_CACHE_SIZE {
+            let oldest = self.order.pop_front().unwrap();
+            self.trees.remove(&oldest);
+        }
+    }
+}
+
+struct OpenFile {
+    // content blobs with their start position in the file
+    blobs: Vec<(Id, u64)>,
+    size: u64,
+    // last read blob; reads usually are sequential and smaller than a blob
+    cache: Option<(usize, Bytes)>,
+}
+
+enum Handle {
+    File(OpenFile),
+    Dir(Vec<DirEntry>),
+}
+
+/// Read-only filesystem showing the snapshots as `snapshots/<time>/<path>`.
+///
+/// Snapshots can also be accessed by their (short) id using `snapshots/<id>/<path>`.
+struct SnapshotFs<BE: IndexedBackend> {
+    index: BE,
+    // snapshot dirs and the `latest` symlink
+    snapshots: Vec<(OsString, Node)>,
+    // snapshot ids with the index of the corresponding entry in `snapshots`
+    ids: Vec<(Id, usize)>,
+    inodes: Inodes,
+    trees: RefCell<TreeCache>,
+    handles: HashMap<u64, Handle>,
+    next_fh: u64,
+    mount_time: SystemTime,
+}
+
+impl<BE: IndexedBackend> SnapshotFs<BE> {
+    fn new(index: BE, snapshots: Vec<SnapshotFile>) -> Self {
+        let mut names: Vec<(OsString, Node)> = Vec::new();
+        let mut ids = Vec::new();
+        for sn in snapshots {
+            let time = sn.time.format("%Y-%m-%dT%H:%M:%S%:z").to_string();
+            // snapshots are sorted by time, so equal names are adjacent
+            let count = names
+                .iter()
+                .rev()
+                .take_while(|(name, _)| name.to_string_lossy().starts_with(&time))
+                .count();
+            let name = match count {
+                0 => time,
+                n => format!("{time}-{n}"),
+            };
+            let meta = Metadata {
+                mode: None,
+                mtime: Some(sn.time),
+                atime: Some(sn.time),
+                ctime: Some(sn.time),
+                uid: Some(sn.uid),
+                gid: Some(sn.gid),
+                ..Default::default()
+            };
+            let mut node = Node::new_node(OsStr::new(&name), NodeType::Dir, meta);
+            node.set_subtree(sn.tree);
+            ids.push((sn.id, names.len()));
+            names.push((OsString::from(name), node));
+        }
+
+        if let Some((name, node)) = names.last() {
+            let linktarget = name.to_string_lossy().to_string();
+            let latest = Node::new_node(
+                OsStr::new(LATEST),
+                NodeType::Symlink { linktarget },
+                node.meta.clone(),
+            );
+            names.push((OsString::from(LATEST), latest));
+        }
+
+        Self {
+            index,
+            snapshots: names,
+            ids,
+            inodes: Inodes::new(),
+            trees: RefCell::new(TreeCache::default()),
+            handles: HashMap::new(),
+            next_fh: 0,
+            mount_time: SystemTime::now(),
+        }
+    }
+
+    fn node(&self, ino: u64) -> Result<&Node, Errno> {
+        match &self.inodes.get(ino)?.kind {
+            InodeKind::Node(node) => Ok(node),
+            _ => Err(Errno::EISDIR),
+        }
+    }
+
+    fn attr(&self, ino: u64) -> Result<FileAttr, Errno> {
+        let node = match &self.inodes.get(ino)?.kind {
+            InodeKind::Root | InodeKind::SnapshotsDir => {
+                return Ok(FileAttr {
+                    ino,
+                    size: 0,
+                    blocks: 0,
+                    atime: self.mount_time,
+                    mtime: self.mount_time,
+                    ctime: self.mount_time,
+                    crtime: self.mount_time,
+                    kind: FileType::Directory,
+                    perm: 0o755,
+                    nlink: 1,
+                    uid: getuid().as_raw(),
+                    gid: getgid().as_raw(),
+                    rdev: 0,
+                    blksize: 512,
+                    flags: 0,
+                });
+            }
+            InodeKind::Node(node) => node,
+        };
+        let meta = &node.meta;
+        let time = |t: &Option<_>| t.map_or(UNIX_EPOCH, SystemTime::from);
+        let (size, rdev) = match &node.node_type {
+            NodeType::File => (meta.size, 0),
+            NodeType::Symlink { linktarget } => (linktarget.len() as u64, 0),
+            // the kernel expects devices in the "new" 32-bit encoding which equals the
+            // lower bits of the 64-bit encoding for all usual major and minor numbers
+            NodeType::Dev { device } | NodeType::Chardev { device } => (0, *device as u32),
+            _ => (0, 0),
+        };
+        let default_perm = match node.node_type {
+            NodeType::Dir => 0o755,
+            NodeType::Symlink { .. } => 0o777,
+            _ => 0o644,
+        };
+        let perm = meta
+            .mode
+            .map_or(default_perm, |mode| map_mode_from_go(mode) & 0o7777);
+
+        Ok(FileAttr {
+            ino,
+            size,
+            blocks: size.div_ceil(512),
+            atime: time(&meta.atime),
+            mtime: time(&meta.mtime),
+            ctime: time(&meta.ctime),
+            crtime: time(&meta.ctime),
+            kind: file_type(node),
+            perm: perm as u16,
+            nlink: u32::try_from(meta.links.max(1)).unwrap_or(u32::MAX),
+            uid: meta.uid.unwrap_or(0),
+            gid: meta.gid.unwrap_or(0),
+            rdev,
+            blksize: 512,
+            flags: 0,
+        })
+    }
+
+    // get the nodes of the tree of a dir node
+    fn tree(&self, node: &Node) -> Result<Arc<Vec<Node>>, Errno> {
+        let id = node.subtree.ok_or(Errno::ENOTDIR)?;
+        if let Some(tree) = self.trees.borrow().get(&id) {
+            return Ok(tree);
+        }
+        let tree = Tree::from_backend(&self.index, id).map_err(|err| {
+            error!("error reading tree {id}: {err}");
+            Errno::EIO
+        })?;
+        let tree: Arc<Vec<Node>> = Arc::new(tree.into_iter().collect());
+        self.trees.borrow_mut().insert(id, tree.clone());
+        Ok(tree)
+    }
+
+    fn entries(&self, ino: u64) -> Result<Vec<DirEntry>, Errno> {
+        let inode = self.inodes.get(ino)?;
+        let nodes = match &inode.kind {
+            InodeKind::Root => vec![(OsString::from(SNAPSHOTS_DIR), FileType::Directory)],
+            InodeKind::SnapshotsDir => self
+                .snapshots
+                .iter()
+                .map(|(name, node)| (name.clone(), file_type(node)))
+                .collect(),
+            InodeKind::Node(node) => self
+                .tree(node)?
+                .iter()
+                .map(|node| (node.name(), file_type(node)))
+                .collect(),
+        };
+
+        let mut entries = vec![
+            DirEntry {
+                ino,
+                kind: FileType::Directory,
+                name: OsString::from("."),
+            },
+            DirEntry {
+                ino: inode.parent,
+                kind: FileType::Directory,
+                name: OsString::from(".."),
+            },
+        ];
+        // readdir doesn't count as lookup, so entries only get an inode when looked up
+        for (name, kind) in nodes {
+            let ino = self.inodes.child(ino, &name).unwrap_or(UNKNOWN_INO);
+            entries.push(DirEntry { ino, kind, name });
+        }
+        Ok(entries)
+    }
+
+    fn open_file(&self, ino: u64) -> Result<OpenFile, Errno> {
+        let node = self.node(ino)?;
+        if node.node_type != NodeType::File {
+            return Err(Errno::EINVAL);
+        }
+        let mut size = 0;
+        let mut blobs = Vec::new();
+        for id in node.content.iter().flatten() {
+            let ie = self.index.get_data(id).ok_or_else(|| {
+                error!("blob {id} not found in index");
+                Errno::EIO
+            })?;
+            blobs.push((*id, size));
+            size += u64::from(ie.data_length());
+        }
+        Ok(OpenFile {
+            blobs,
+            size,
+            cache: None,
+        })
+    }
+
+    fn read_blob(index: &BE, file: &mut OpenFile, i: usize) -> Result<Bytes, Errno> {
+        match &file.cache {
+            Some((j, data)) if *j == i => Ok(data.clone()),
+            _ => {
+                let id = &file.blobs[i].0;
+                let data = index.blob_from_backend(BlobType::Data, id).map_err(|err| {
+                    error!("error reading blob {id}: {err}");
+                    Errno::EIO
+                })?;
+                file.cache = Some((i, data.clone()));
+                Ok(data)
+            }
+        }
+    }
+
+    fn child_kind(&self, parent: u64, name: &OsStr) -> Result<InodeKind, Errno> {
+        Ok(match &self.inodes.get(parent)?.kind {
+            InodeKind::Root if name == SNAPSHOTS_DIR => InodeKind::SnapshotsDir,
+            InodeKind::Root => return Err(Errno::ENOENT),
+            InodeKind::SnapshotsDir => {
+                let node = match self.snapshots.iter().find(|(n, _)| n == name) {
+                    Some((_, node)) => node,
+                    // try to find the snapshot by (short) id
+                    None => {
+                        let name = name.to_string_lossy();
+                        let (_, i) = self
+                            .ids
+                            .iter()
+                            .find(|(id, _)| id.to_hex().as_str() == name || id.to_string() == name)
+                            .ok_or(Errno::ENOENT)?;
+                        &self.snapshots[*i].1
+                    }
+                };
+                InodeKind::Node(Box::new(node.clone()))
+            }
+            InodeKind::Node(node) => {
+                let node = self
+                    .tree(node)?
+                    .iter()
+                    .find(|node| node.name() == name)
+                    .ok_or(Errno::ENOENT)?
+                    .clone();
+                InodeKind::Node(Box::new(node))
+            }
+        })
+    }
+
+    fn new_handle(&mut self, handle: Handle) -> u64 {
+        let fh = self.next_fh;
+        self.next_fh += 1;
+        self.handles.insert(fh, handle);
+        fh
+    }
+
+    // look up a directory entry; each successful lookup counts as a reference to the inode
+    // which the kernel releases using forget
+    fn lookup_entry(&mut self, parent: u64, name: &OsStr) -> Result<FileAttr, Errno> {
+        let ino = match self.inodes.child(parent, name) {
+            Some(ino) => ino,
+            None => {
+                let kind = self.child_kind(parent, name)?;
+                self.inodes.insert(parent, name, kind)
+            }
+        };
+        let attr = self.attr(ino)?;
+        self.inodes.lookup(ino);
+        Ok(attr)
+    }
+
+    fn link_target(&self, ino: u64) -> Result<Vec<u8>, Errno> {
+        match &self.node(ino)?.node_type {
+            NodeType::Symlink { linktarget } => Ok(linktarget.as_bytes().to_vec()),
+            _ => Err(Errno::EINVAL),
+        }
+    }
+
+    fn read_data(&mut self, fh: u64, offset: u64, size: u32) -> Result<Vec<u8>, Errno> {
+        let file = match self.handles.get_mut(&fh) {
+            Some(Handle::File(file)) => file,
+            _ => return Err(Errno::EBADF),
+        };
+        let end = file.size.min(offset + u64::from(size));
+        let mut data = Vec::new();
+        if offset >= end {
+            return Ok(data);
+        }
+
+        // first blob containing offset
+        let mut i = file.blobs.partition_point(|(_, start)| *start <= offset) - 1;
+        let mut pos = offset;
+        while pos < end {
+            let blob = Self::read_blob(&self.index, file, i)?;
+            let start = file.blobs[i].1;
+            let from = usize::try_from(pos - start).map_err(|_| Errno::EIO)?;
+            let to = usize::try_from(end - start)
+                .map_err(|_| Errno::EIO)?
+                .min(blob.len());
+            if from >= to {
+                // blob is shorter than given by the index
+                return Err(Errno::EIO);
+            }
+            data.extend_from_slice(&blob[from..to]);
+            pos = start + to as u64;
+            i += 1;
+        }
+        Ok(data)
+    }
+
+    fn xattr(&self, ino: u64, name: &OsStr) -> Result<Vec<u8>, Errno> {
+        let node = self.node(ino).map_err(|_| Errno::ENODATA)?;
+        node.meta
+            .extended_attributes
+            .iter()
+            .find(|attr| OsStr::new(&attr.name) == name)
+            .map(|attr| attr.value.clone())
+            .ok_or(Errno::ENODATA)
+    }
+
+    // names of the extended attributes, each terminated by a null byte
+    fn xattr_names(&self, ino: u64) -> Vec<u8> {
+        let mut names = Vec::new();
+        if let Ok(node) = self.node(ino) {
+            for attr in &node.meta.extended_attributes {
+                names.extend_from_slice(attr.name.as_bytes());
+                names.push(0);
+            }
+        }
+        names
+    }
+}
+
+// reply with the value of an extended attribute or its size if `size` is 0
+fn reply_xattr(reply: ReplyXattr, value: Result<Vec<u8>, Errno>, size: u32) {
+    match value {
+        Err(err) => reply.error(err as i32),
+        Ok(value) if size == 0 => match u32::try_from(value.len()) {
+            Ok(len) => reply.size(len),
+            Err(_) => reply.error(libc::E2BIG),
+        },
+        Ok(value) if value.len() > size as usize => reply.error(libc::ERANGE),
+        Ok(value) => reply.data(&value),
+    }
+}
+
+impl<BE: IndexedBackend> Filesystem for SnapshotFs<BE> {
+    fn lookup(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        match self.lookup_entry(parent, name) {
+            Ok(attr) => reply.entry(&TTL, &attr, 0),
+            Err(err) => reply.error(err as i32),
+        }
+    }
+
+    fn forget(&mut self, _req: &Request<'_>, ino: u64, nlookup: u64) {
+        self.inodes.forget(ino, nlookup);
+    }
+
+    fn getattr(&mut self, _req: &Request<'_>, ino: u64, reply: ReplyAttr) {
+        match self.attr(ino) {
+            Ok(attr) => reply.attr(&TTL, &attr),
+            Err(err) => reply.error(err as i32),
+        }
+    }
+
+    fn readlink(&mut self, _req: &Request<'_>, ino: u64, reply: ReplyData) {
+        match self.link_target(ino) {
+            Ok(target) => reply.data(&target),
+            Err(err) => reply.error(err as i32),
+        }
+    }
+
+    fn open(&mut self, _req: &Request<'_>, ino: u64, flags: i32, reply: ReplyOpen) {
+        if flags & libc::O_ACCMODE != libc::O_RDONLY {
+            reply.error(libc::EROFS);
+            return;
+        }
+        match self.open_file(ino) {
+            Ok(file) => reply.opened(self.new_handle(Handle::File(file)), FOPEN_KEEP_CACHE),
+            Err(err) => reply.error(err as i32),
+        }
+    }
+
+    fn read(
+        &mut self,
+        _req: &Request<'_>,
+        _ino: u64,
+        fh: u64,
+        offset: i64,
+        size: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyData,
+    ) {
+        let data = u64::try_from(offset)
+            .map_err(|_| Errno::EINVAL)
+            .and_then(|offset| self.read_data(fh, offset, size));
+        match data {
+            Ok(data) => reply.data(&data),
+            Err(err) => reply.error(err as i32),
+        }
+    }
+
+    fn release(
+        &mut self,
+        _req: &Request<'_>,
+        _ino: u64,
+        fh: u64,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        _flush: bool,
+        reply: ReplyEmpty,
+    ) {
+        self.handles.remove(&fh);
+        reply.ok();
+    }
+
+    fn opendir(&mut self, _req: &Request<'_>, ino: u64, _flags: i32, reply: ReplyOpen) {
+        match self.entries(ino) {
+            Ok(entries) => reply.opened(self.new_handle(Handle::Dir(entries)), 0),
+            Err(err) => reply.error(err as i32),
+        }
+    }
+
+    fn readdir(
+        &mut self,
+        _req: &Request<'_>,
+        _ino: u64,
+        fh: u64,
+        offset: i64,
+        mut reply: ReplyDirectory,
+    ) {
+        let Some(Handle::Dir(entries)) = self.handles.get(&fh) else {
+            reply.error(libc::EBADF);
+            return;
+        };
+        let Ok(offset) = usize::try_from(offset) else {
+            reply.error(libc::EINVAL);
+            return;
+        };
+        for (i, entry) in entries.iter().enumerate().skip(offset) {
+            // the offset is the offset of the next entry
+            if reply.add(entry.ino, i as i64 + 1, entry.kind, &entry.name) {
+                break;
+            }
+        }
+        reply.ok();
+    }
+
+    fn releasedir(
+        &mut self,
+        _req: &Request<'_>,
+        _ino: u64,
+        fh: u64,
+        _flags: i32,
+        reply: ReplyEmpty,
+    ) {
+        self.handles.remove(&fh);
+        reply.ok();
+    }
+
+    fn getxattr(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        name: &OsStr,
+        size: u32,
+        reply: ReplyXattr,
+    ) {
+        reply_xattr(reply, self.xattr(ino, name), size);
+    }
+
+    fn listxattr(&mut self, _req: &Request<'_>, ino: u64, size: u32, reply: ReplyXattr) {
+        reply_xattr(reply, Ok(self.xattr_names(ino)), size);
+    }
+}
+
+fn file_type(node: &Node) -> FileType {
+    match node.node_type {
+        NodeType::File => FileType::RegularFile,
+        NodeType::Dir => FileType::Directory,
+        NodeType::Symlink { .. } => FileType::Symlink,
+        NodeType::Dev { .. } => FileType::BlockDevice,
+        NodeType::Chardev { .. } => FileType::CharDevice,
+        NodeType::Fifo => FileType::NamedPipe,
+        NodeType::Socket => FileType::Socket,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+
+    use crate::backend::{DecryptBackend, LocalBackend, WriteBackend};
+    use crate::blob::Packer;
+    use crate::crypto::{hash, Key};
+    use crate::index::Indexer;
+    use crate::progress::Progress;
+    use crate::repofile::ConfigFile;
+
+    #[test]
+    fn mount_snapshot() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let be = LocalBackend::new(dir.path().join("repo").to_str().unwrap())?;
+        be.create()?;
+        let be = DecryptBackend::new(&be, Key::new());
+        let config = ConfigFile::new(2, Id::random(), 0);
+
+        // save a snapshot containing /file.txt, /link -> file.txt and /sub/file.txt
+        let indexer = Indexer::new(be.clone()).into_shared();
+        let data_packer = Packer::new(be.clone(), BlobType::Data, indexer.clone(), &config, 0)?;
+        let tree_packer = Packer::new(be.clone(), BlobType::Tree, indexer.clone(), &config, 0)?;
+        let content = b"Hello!";
+        let blob = hash(content);
+        data_packer.add(content, &blob)?;
+        let meta = Metadata {
+            size: content.len() as u64,
+            ..Default::default()
+        };
+        let mut file = Node::new_node(OsStr::new("file.txt"), NodeType::File, meta);
+        file.content = Some(vec![blob]);
+        let save = |nodes: Vec<Node>| -> Result<Id> {
+            let mut tree = Tree::new();
+            for node in nodes {
+                tree.add(node);
+            }
+            let (chunk, id) = tree.serialize()?;
+            tree_packer.add(&chunk, &id)?;
+            Ok(id)
+        };
+        let mut sub = Node::new_node(OsStr::new("sub"), NodeType::Dir, Metadata::default());
+        sub.set_subtree(save(vec![file.clone()])?);
+        let linktarget = "file.txt".to_string();
+        let link = Node::new_node(
+            OsStr::new("link"),
+            NodeType::Symlink { linktarget },
+            Metadata::default(),
+        );
+        let root = save(vec![file, link, sub])?;
+        data_packer.finalize()?;
+        tree_packer.finalize()?;
+        indexer.write().unwrap().finalize()?;
+        let snap = SnapshotFile {
+            tree: root,
+            id: Id::random(),
+            ..Default::default()
+        };
+
+        let index = IndexBackend::new(&be, Progress::hidden())?;
+        let fs = SnapshotFs::new(index, vec![snap]);
+        let mountpoint = dir.path().join("mnt");
+        fs::create_dir(&mountpoint)?;
+        let session = match fuser::spawn_mount2(fs, &mountpoint, &mount_options(false)) {
+            Ok(session) => session,
+            Err(err) => {
+                eprintln!("skipping test, mounting is not possible: {err}");
+                return Ok(());
+            }
+        };
+
+        let snapshot = mountpoint.join(SNAPSHOTS_DIR).join(LATEST);
+        let mut names: Vec<_> = fs::read_dir(&snapshot)?
+            .map(|entry| entry.map(|entry| entry.file_name()))
+            .collect::<Result<_, _>>()?;
+        names.sort();
+        assert_eq!(names, ["file.txt", "link", "sub"]);
+        assert_eq!(fs::read(snapshot.join("file.txt"))?, content);
+        assert_eq!(fs::read(snapshot.join("sub/file.txt"))?, content);
+        assert_eq!(fs::read_link(snapshot.join("link"))?, Path::new("file.txt"));
+        assert_eq!(fs::metadata(snapshot.join("file.txt"))?.len(), 6);
+        assert!(fs::write(snapshot.join("file.txt"), "changed").is_err());
+        assert!(fs::metadata(snapshot.join("missing")).is_err());
+
+        drop(session);
+        Ok(())
+    }
+
+    #[test]
+    fn forget_inodes() {
+        let mut inodes = Inodes::new();
+        assert_eq!(
+            inodes.child(ROOT_INO, OsStr::new(SNAPSHOTS_DIR)),
+            Some(SNAPSHOTS_INO)
+        );
+
+        let node = Node::new_node(OsStr::new("snap"), NodeType::Dir, Metadata::default());
+        let ino = inodes.insert(
+            SNAPSHOTS_INO,
+            OsStr::new("snap"),
+            InodeKind::Node(Box::new(node)),
+        );
+        inodes.lookup(ino);
+        inodes.lookup(ino);
+        assert_eq!(inodes.child(SNAPSHOTS_INO, OsStr::new("snap")), Some(ino));
+
+        // the inode is removed once all lookups are forgotten
+        inodes.forget(ino, 1);
+        assert!(inodes.get(ino).is_ok());
+        inodes.forget(ino, 1);
+        assert!(inodes.get(ino).is_err());
+        assert_eq!(inodes.child(SNAPSHOTS_INO, OsStr::new("snap")), None);
+        assert_eq!(inodes.inodes.len(), 2);
+
+        // root and snapshots dir are never removed
+        inodes.lookup(SNAPSHOTS_INO);
+        inodes.forget(SNAPSHOTS_INO, 1);
+        inodes.forget(ROOT_INO, 1);
+        assert!(inodes.get(ROOT_INO).is_ok());
+        assert!(inodes.get(SNAPSHOTS_INO).is_ok());
+    }
+}
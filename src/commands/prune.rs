@@ -11,6 +11,7 @@ use derive_more::Add;
 use itertools::Itertools;
 use log::*;
 use rayon::prelude::*;
+use serde_json::json;
 
 use super::{bytes, no_progress, progress_bytes, progress_counter, warm_up_wait};
 use crate::backend::{DecryptReadBackend, DecryptWriteBackend, FileType, ReadBackend};
@@ -20,6 +21,8 @@ use crate::blob::{
 use crate::commands::helpers::progress_spinner;
 use crate::id::Id;
 use crate::index::{IndexBackend, IndexCollector, IndexType, IndexedBackend, Indexer, ReadIndex};
+use crate::metrics::prune_metrics;
+use crate::progress::json_summary;
 use crate::repofile::{HeaderEntry, IndexBlob, IndexFile, IndexPack, SnapshotFile};
 use crate::repository::OpenRepository;
 
@@ -130,12 +133,16 @@ pub(super) fn execute(repo: OpenRepository, opts: Opts, ignore_snaps: Vec<Id>) -
     pruner.check_existing_packs()?;
     pruner.filter_index_files(opts.instant_delete);
     pruner.print_stats();
+    let summary = pruner.summary();
 
     warm_up_wait(&repo, pruner.repack_packs().into_iter(), !opts.dry_run)?;
 
-    if !opts.dry_run {
+    let dry_run = opts.dry_run;
+    if !dry_run {
         pruner.do_prune(repo, opts)?;
+        prune_metrics(&summary)?;
     }
+    json_summary("prune", json!({ "dry_run": dry_run, "stats": summary }));
     Ok(())
 }
 
@@ -780,6 +787,28 @@ impl Pruner {
         );
     }
 
+    // statistics as shown by print_stats
+    fn summary(&self) -> serde_json::Value {
+        let pack_stat = &self.stats.packs;
+        let blob_stat = self.stats.blobs.sum();
+        let size_stat = self.stats.size.sum();
+        json!({
+            "packs_to_repack": pack_stat.repack,
+            "blobs_to_repack": blob_stat.repack,
+            "size_to_repack": size_stat.repack,
+            "blobs_removed_by_repack": blob_stat.repackrm,
+            "size_removed_by_repack": size_stat.repackrm,
+            "packs_to_delete": pack_stat.unused,
+            "blobs_to_delete": blob_stat.remove,
+            "size_to_delete": size_stat.remove,
+            "unindexed_packs": self.existing_packs.len(),
+            "size_unindexed": self.stats.size_unref,
+            "blobs_remaining": blob_stat.total_after_prune(),
+            "size_remaining": size_stat.total_after_prune(),
+            "size_unused_after_prune": size_stat.unused_after_prune(),
+        })
+    }
+
     fn repack_packs(&self) -> Vec<Id> {
         self.index_files
             .iter()
@@ -0,0 +1,324 @@
+use std::collections::{HashMap, HashSet};
+
+use anyhow::{bail, Context, Result};
+use chrono::Local;
+use clap::Parser;
+use log::*;
+use rayon::prelude::*;
+
+use super::config::save_config;
+use super::key::{add_key, new_password, AddOpts};
+use super::{progress_bytes, progress_counter};
+use crate::backend::{
+    CachedBackend, DecryptBackend, DecryptFullBackend, DecryptReadBackend, DecryptWriteBackend,
+    FileType, ReadBackend, WriteBackend,
+};
+use crate::blob::{BlobType, BlobTypeMap, Packer};
+use crate::crypto::{hash, CryptoKey, Key, WriterKeys};
+use crate::id::Id;
+use crate::index::Indexer;
+use crate::repofile::{
+    find_key_in_backend, ConfigFile, IndexFile, IndexPack, KdfParams, SnapshotFile,
+};
+use crate::repository::OpenRepository;
+
+#[derive(Parser)]
+pub(super) struct Opts {
+    #[clap(flatten)]
+    add_opts: AddOpts,
+}
+
+pub(super) fn execute(repo: OpenRepository, opts: Opts) -> Result<()> {
+    // data saved by write-only keys must be re-encrypted, too
+    if !repo.config.write_only_keys.is_empty()
+        && repo.key.writer_keys().is_none_or(WriterKeys::is_empty)
+    {
+        bail!("the repository contains data of write-only keys. Please give the private key using --private-key-file.");
+    }
+
+    let mut config = repo.config.clone();
+    let (key_id, key) = match config.rekey_key {
+        Some(id) => {
+            info!("continuing interrupted rekey using key {id}");
+            let pass = new_password(opts.add_opts.new_password_file)?;
+            find_key_in_backend(&repo.be, &pass, Some(&id))
+                .with_context(|| format!("wrong password for key {id}"))?
+        }
+        None => {
+            let key = Key::new();
+            let id = add_key(&repo.be, key.clone(), KdfParams::default(), opts.add_opts)?;
+            info!("saved new master key in key {id}");
+            // remember the new key in the (still old) config such that an interrupted rekey can be
+            // continued and other commands refuse to work on the repository meanwhile
+            config.rekey_key = Some(id);
+            save_config(&repo, &config, &repo.key)?;
+            (id, key)
+        }
+    };
+
+    let mut new_be = DecryptBackend::new(
+        &CachedBackend::new(repo.be.clone(), repo.cache.clone()),
+        key.clone(),
+    );
+    new_be.set_zstd(config.zstd()?);
+
+    let old_indexes = rekey_packs(&repo, &new_be, &config)?;
+    rekey_snapshots(&repo.dbe, &new_be)?;
+
+    // the new index files are complete, so the old ones are no longer needed
+    let p = progress_counter("removing old index files...");
+    repo.dbe
+        .delete_list(FileType::Index, true, old_indexes.iter(), p)?;
+
+    // finally switch to the new key; write-only keys are removed, so their sealed keys are no longer needed
+    config.rekey_key = None;
+    config.write_only_keys.clear();
+    save_config(&repo, &config, &key)?;
+
+    for id in repo.be.list(FileType::Key)? {
+        if id != key_id {
+            repo.be.remove(FileType::Key, &id, false)?;
+            info!("removed key {id}");
+        }
+    }
+
+    println!("repository is now encrypted using the master key in key {key_id}.");
+    println!("Other keys have been removed and need to be added again using `rustic key add`.");
+    println!("The old pack files are marked for deletion and will be removed by `rustic prune`.");
+    Ok(())
+}
+
+// Re-encrypt all blobs which are not yet contained in index files using the new key and mark the
+// old packs for deletion. Returns the ids of the old index files.
+fn rekey_packs(
+    repo: &OpenRepository,
+    new_be: &impl DecryptFullBackend,
+    config: &ConfigFile,
+) -> Result<Vec<Id>> {
+    // index files which can be read using the new key have been written by an interrupted rekey
+    let mut done = HashSet::new();
+    let mut old_indexes = Vec::new();
+    let mut old_packs = Vec::new();
+    let mut old_packs_to_delete = Vec::new();
+    let p = progress_counter("reading index...");
+    let index_ids = repo.be.list(FileType::Index)?;
+    p.set_length(index_ids.len() as u64);
+    for id in index_ids {
+        match new_be.get_file::<IndexFile>(&id) {
+            Ok(index) => done.extend(
+                index
+                    .packs
+                    .iter()
+                    .flat_map(|pack| &pack.blobs)
+                    .map(|blob| blob.id),
+            ),
+            Err(_) => {
+                let index: IndexFile = repo.dbe.get_file(&id)?;
+                old_packs.extend(index.packs);
+                old_packs_to_delete.extend(index.packs_to_delete);
+                old_indexes.push(id);
+            }
+        }
+        p.inc(1);
+    }
+    p.finish();
+
+    let mut seen = HashSet::new();
+    old_packs.retain(|pack| seen.insert(pack.id));
+    old_packs_to_delete.retain(|pack| seen.insert(pack.id));
+
+    let mut total_size = BlobTypeMap::<u64>::default();
+    let mut todo_size = 0;
+    for pack in &old_packs {
+        for blob in &pack.blobs {
+            total_size[blob.tpe] += u64::from(blob.length);
+            if !done.contains(&blob.id) {
+                todo_size += u64::from(blob.length);
+            }
+        }
+    }
+
+    let indexer = Indexer::new(new_be.clone()).into_shared();
+    let tree_packer = Packer::new(
+        new_be.clone(),
+        BlobType::Tree,
+        indexer.clone(),
+        config,
+        total_size[BlobType::Tree],
+    )?;
+    let data_packer = Packer::new(
+        new_be.clone(),
+        BlobType::Data,
+        indexer.clone(),
+        config,
+        total_size[BlobType::Data],
+    )?;
+
+    let p = progress_bytes("re-encrypting packs...");
+    p.set_length(todo_size);
+    old_packs
+        .par_iter()
+        .filter(|pack| pack.blobs.iter().any(|blob| !done.contains(&blob.id)))
+        .try_for_each(|pack| -> Result<_> {
+            let blob_type = pack.blob_type();
+            let data = repo.dbe.read_partial(
+                FileType::Pack,
+                &pack.id,
+                blob_type.is_cacheable(),
+                0,
+                pack.pack_size(),
+            )?;
+            let packer = match blob_type {
+                BlobType::Data => &data_packer,
+                BlobType::Tree => &tree_packer,
+            };
+            for blob in pack.blobs.iter().filter(|blob| !done.contains(&blob.id)) {
+                let start = blob.offset as usize;
+                let end = start + blob.length as usize;
+                // the decrypted data is still compressed, so it can be saved as is
+                let decrypted = repo.key.decrypt_data(&data[start..end])?;
+                let encrypted = new_be.key().encrypt_data(&decrypted)?;
+                packer.add_raw(&encrypted, &blob.id, 0, blob.uncompressed_length, None)?;
+                p.inc(u64::from(blob.length));
+            }
+            Ok(())
+        })?;
+    tree_packer.finalize()?;
+    data_packer.finalize()?;
+
+    // mark all old packs for deletion
+    let time = Local::now();
+    let old_packs = old_packs.into_iter().map(|pack| (pack, time));
+    // packs which have already been marked keep their time of marking
+    let old_packs_to_delete = old_packs_to_delete.into_iter().map(|pack| {
+        let time = pack.time.unwrap_or(time);
+        (pack, time)
+    });
+    for (pack, time) in old_packs.chain(old_packs_to_delete) {
+        let pack = IndexPack {
+            id: pack.id,
+            size: Some(pack.pack_size()),
+            time: Some(time),
+            blobs: Vec::new(),
+        };
+        indexer.write().unwrap().add_remove(pack)?;
+    }
+    indexer.write().unwrap().finalize()?;
+    p.finish();
+
+    Ok(old_indexes)
+}
+
+// Re-encrypt all snapshots using the new key and remove the old snapshot files. The old id is kept
+// as original id and parent references are changed to the new ids.
+fn rekey_snapshots(
+    old_be: &impl DecryptFullBackend,
+    new_be: &impl DecryptFullBackend,
+) -> Result<()> {
+    let p = progress_counter("re-encrypting snapshots...");
+    // snapshots which can be read using the new key have been written by an interrupted rekey
+    let mut rewritten = HashMap::new();
+    let mut old_snaps = HashMap::new();
+    for id in old_be.list(FileType::Snapshot)? {
+        match new_be.read_encrypted_full(FileType::Snapshot, &id) {
+            Ok(data) => {
+                rewritten.insert(hash(&data), id);
+            }
+            Err(_) => {
+                let snap: SnapshotFile = old_be.get_file(&id)?;
+                old_snaps.insert(id, snap);
+            }
+        }
+    }
+
+    // rekey parents before their children such that the new parent ids are known
+    let mut order = Vec::new();
+    let mut visited = HashSet::new();
+    for id in old_snaps.keys() {
+        let mut chain = Vec::new();
+        let mut next = Some(*id);
+        while let Some(id) = next.filter(|id| old_snaps.contains_key(id) && visited.insert(*id)) {
+            chain.push(id);
+            next = old_snaps[&id].parent;
+        }
+        order.extend(chain.into_iter().rev());
+    }
+
+    p.set_length(order.len() as u64);
+    let mut new_ids = HashMap::new();
+    for id in &order {
+        let mut snap = old_snaps.remove(id).unwrap();
+        snap.original.get_or_insert(*id);
+        if let Some(parent) = snap.parent.and_then(|parent| new_ids.get(&parent)) {
+            snap.parent = Some(*parent);
+        }
+        let data = serde_json::to_vec(&snap)?;
+        // don't save snapshots twice if the old file has not been removed by an interrupted rekey
+        let new_id = match rewritten.get(&hash(&data)) {
+            Some(new_id) => *new_id,
+            None => new_be.hash_write_full(FileType::Snapshot, &data)?,
+        };
+        new_ids.insert(*id, new_id);
+        p.inc(1);
+    }
+    p.finish();
+
+    // remove children before their parents: when continuing an interrupted rekey, the parents of
+    // the remaining old snapshots still exist and the same new snapshots are computed
+    let p = progress_counter("removing old snapshots...");
+    p.set_length(order.len() as u64);
+    for id in order.iter().rev() {
+        old_be.remove(FileType::Snapshot, id, true)?;
+        p.inc(1);
+    }
+    p.finish();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::backend::LocalBackend;
+
+    #[test]
+    fn rekey_snapshot_parents() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let be = LocalBackend::new(dir.path().to_str().unwrap())?;
+        be.create()?;
+        let old_be = DecryptBackend::new(&be, Key::new());
+        let new_be = DecryptBackend::new(&be, Key::new());
+
+        // a chain of snapshots; the last one has been rewritten before
+        let first = old_be.save_file(&SnapshotFile::default())?;
+        let second = old_be.save_file(&SnapshotFile {
+            parent: Some(first),
+            ..Default::default()
+        })?;
+        let original = Id::random();
+        let third = old_be.save_file(&SnapshotFile {
+            parent: Some(second),
+            original: Some(original),
+            ..Default::default()
+        })?;
+
+        // an interrupted rekey already saved the first snapshot, but didn't remove the old file
+        let mut snap: SnapshotFile = old_be.get_file(&first)?;
+        snap.original = Some(first);
+        _ = new_be.save_file(&snap)?;
+
+        rekey_snapshots(&old_be, &new_be)?;
+
+        let mut snaps = HashMap::new();
+        for id in be.list(FileType::Snapshot)? {
+            let snap: SnapshotFile = new_be.get_file(&id)?;
+            snaps.insert(snap.original.unwrap(), (id, snap));
+        }
+        assert_eq!(snaps.len(), 3);
+        assert!(snaps[&first].1.parent.is_none());
+        assert_eq!(snaps[&second].1.parent, Some(snaps[&first].0));
+        assert_eq!(snaps[&original].1.parent, Some(snaps[&second].0));
+        assert!(!snaps.contains_key(&third));
+        Ok(())
+    }
+}
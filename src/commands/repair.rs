@@ -1,4 +1,5 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::HashMap;
+use std::path::Path;
 
 use anyhow::Result;
 use clap::{AppSettings, Parser, Subcommand};
@@ -8,8 +9,7 @@ use crate::backend::{
     DecryptFullBackend, DecryptReadBackend, DecryptWriteBackend, FileType, ReadBackend,
     WriteBackend,
 };
-use crate::blob::{BlobType, NodeType, Packer, Tree};
-use crate::id::Id;
+use crate::blob::{BlobType, Node, NodeType, NodeVisitor, Packer, Rewritten, TreeRewriter};
 use crate::index::{IndexBackend, IndexedBackend, Indexer, ReadIndex};
 use crate::repofile::{
     ConfigFile, IndexFile, IndexPack, PackHeader, PackHeaderRef, SnapshotFile, SnapshotFilter,
@@ -197,39 +197,35 @@ fn repair_snaps(
         false => SnapshotFile::from_ids(be, &opts.ids)?,
     };
 
-    let mut replaced = HashMap::new();
-    let mut seen = HashSet::new();
     let mut delete = Vec::new();
 
     let index = IndexBackend::new(&be.clone(), progress_counter(""))?;
     let indexer = Indexer::new(be.clone()).into_shared();
-    let mut packer = Packer::new(
+    let packer = Packer::new(
         be.clone(),
         BlobType::Tree,
         indexer.clone(),
         config,
         index.total_size(BlobType::Tree),
     )?;
+    let mut repairer = Repairer {
+        index: &index,
+        suffix: &opts.suffix,
+    };
+    let mut rewriter = TreeRewriter::new(&index, &packer, opts.dry_run);
 
     for mut snap in snapshots {
         let snap_id = snap.id;
         info!("processing snapshot {snap_id}");
-        match repair_tree(
-            &index,
-            &mut packer,
-            Some(snap.tree),
-            &mut replaced,
-            &mut seen,
-            &opts,
-        )? {
-            (Changed::None, _) => {
+        match rewriter.rewrite_tree(&mut repairer, Path::new(""), Some(snap.tree))? {
+            Rewritten::Unchanged => {
                 info!("snapshot {snap_id} is ok.");
             }
-            (Changed::This, _) => {
+            Rewritten::Missing(_) => {
                 warn!("snapshot {snap_id}: root tree is damaged -> marking for deletion!");
                 delete.push(snap_id);
             }
-            (Changed::SubTree, id) => {
+            Rewritten::Changed(id) => {
                 // change snapshot tree
                 if snap.original.is_none() {
                     snap.original = Some(snap.id);
@@ -268,109 +264,48 @@ fn repair_snaps(
     Ok(())
 }
 
-#[derive(Clone, Copy)]
-enum Changed {
-    This,
-    SubTree,
-    None,
+// removes missing blobs from files and marks all damaged files and dirs by adding a suffix
+struct Repairer<'a, I: IndexedBackend> {
+    index: &'a I,
+    suffix: &'a str,
 }
 
-fn repair_tree<BE: DecryptWriteBackend>(
-    be: &impl IndexedBackend,
-    packer: &mut Packer<BE>,
-    id: Option<Id>,
-    replaced: &mut HashMap<Id, (Changed, Id)>,
-    seen: &mut HashSet<Id>,
-    opts: &SnapOpts,
-) -> Result<(Changed, Id)> {
-    let (tree, changed) = match id {
-        None => (Tree::new(), Changed::This),
-        Some(id) => {
-            if seen.contains(&id) {
-                return Ok((Changed::None, id));
-            }
-            if let Some(r) = replaced.get(&id) {
-                return Ok(*r);
-            }
-
-            let (tree, mut changed) = match Tree::from_backend(be, id) {
-                Ok(tree) => (tree, Changed::None),
-                Err(_) => {
-                    warn!("tree {id} could not be loaded.");
-                    (Tree::new(), Changed::This)
-                }
-            };
-            let mut new_tree = Tree::new();
-
-            for mut node in tree {
-                match node.node_type {
-                    NodeType::File {} => {
-                        let mut file_changed = false;
-                        let mut new_content = Vec::new();
-                        let mut new_size = 0;
-                        for blob in node.content.take().unwrap() {
-                            match be.get_data(&blob) {
-                                Some(ie) => {
-                                    new_content.push(blob);
-                                    new_size += u64::from(ie.data_length());
-                                }
-                                None => {
-                                    file_changed = true;
-                                }
-                            }
-                        }
-                        if file_changed {
-                            warn!("file {}: contents are missing", node.name);
-                            node.name += &opts.suffix;
-                            changed = Changed::SubTree;
-                        } else if new_size != node.meta.size {
-                            info!("file {}: corrected file size", node.name);
-                            changed = Changed::SubTree;
-                        }
-                        node.content = Some(new_content);
-                        node.meta.size = new_size;
+impl<I: IndexedBackend> NodeVisitor for Repairer<'_, I> {
+    fn visit(&mut self, _path: &Path, mut node: Node) -> Option<Node> {
+        if node.node_type == NodeType::File {
+            let mut file_changed = false;
+            let mut new_content = Vec::new();
+            let mut new_size = 0;
+            for blob in node.content.take().unwrap_or_default() {
+                match self.index.get_data(&blob) {
+                    Some(ie) => {
+                        new_content.push(blob);
+                        new_size += u64::from(ie.data_length());
                     }
-                    NodeType::Dir {} => {
-                        let (c, tree_id) =
-                            repair_tree(be, packer, node.subtree, replaced, seen, opts)?;
-                        match c {
-                            Changed::None => {}
-                            Changed::This => {
-                                warn!("dir {}: tree is missing", node.name);
-                                node.subtree = Some(tree_id);
-                                node.name += &opts.suffix;
-                                changed = Changed::SubTree;
-                            }
-                            Changed::SubTree => {
-                                node.subtree = Some(tree_id);
-                                changed = Changed::SubTree;
-                            }
-                        }
+                    None => {
+                        file_changed = true;
                     }
-                    _ => {} // Other types: no check needed
                 }
-                new_tree.add(node);
             }
-            if let Changed::None = changed {
-                seen.insert(id);
+            if file_changed {
+                warn!("file {}: contents are missing", node.name);
+                node.name += self.suffix;
+            } else if new_size != node.meta.size {
+                info!("file {}: corrected file size", node.name);
             }
-            (new_tree, changed)
+            node.content = Some(new_content);
+            node.meta.size = new_size;
         }
-    };
+        Some(node)
+    }
 
-    match (id, changed) {
-        (None, Changed::None) => panic!("this should not happen!"),
-        (Some(id), Changed::None) => Ok((Changed::None, id)),
-        (_, c) => {
-            // the tree has been changed => save it
-            let (chunk, new_id) = tree.serialize()?;
-            if !be.has_tree(&new_id) && !opts.dry_run {
-                packer.add(&chunk, &new_id)?;
-            }
-            if let Some(id) = id {
-                replaced.insert(id, (c, new_id));
-            }
-            Ok((c, new_id))
-        }
+    fn missing_tree(&mut self, _path: &Path, node: &mut Node) -> Result<()> {
+        warn!("dir {}: tree is missing", node.name);
+        node.name += self.suffix;
+        Ok(())
+    }
+
+    fn path_dependent(&self) -> bool {
+        false
     }
 }
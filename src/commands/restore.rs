@@ -8,19 +8,23 @@ use anyhow::{anyhow, bail, Context, Result};
 use chrono::{DateTime, Local, Utc};
 use clap::{AppSettings, Parser};
 use derive_getters::Dissolve;
-use ignore::{DirEntry, WalkBuilder};
+use ignore::{overrides::Override, DirEntry, WalkBuilder};
 use log::*;
 use rayon::ThreadPoolBuilder;
+use serde::Serialize;
+use serde_json::json;
 
 use super::rustic_config::RusticConfig;
 use super::{bytes, progress_bytes, progress_counter, warm_up_wait};
-use crate::backend::{DecryptReadBackend, FileType, LocalDestination};
-use crate::blob::{Node, NodeStreamer, NodeType, Tree};
+use crate::archiver::{ZERO_BLOB_ID, ZERO_BLOB_SIZE};
+use crate::backend::{glob_overrides, DecryptReadBackend, FileType, LocalDestination};
+use crate::blob::{HardLinks, Node, NodeStreamer, NodeType, Tree};
 use crate::commands::helpers::progress_spinner;
 use crate::crypto::hash;
 use crate::id::Id;
 use crate::index::{IndexBackend, IndexedBackend};
-use crate::repofile::{SnapshotFile, SnapshotFilter};
+use crate::progress::json_summary;
+use crate::repofile::{split_snapshot_path, SnapshotFile, SnapshotFilter, SnapshotRefGroup};
 use crate::repository::OpenRepository;
 
 #[derive(Parser)]
@@ -29,11 +33,15 @@ pub(super) struct Opts {
     #[clap(flatten, help_heading = "SNAPSHOT FILTER OPTIONS (when using latest)")]
     filter: SnapshotFilter,
 
+    #[clap(flatten)]
+    group: SnapshotRefGroup,
+
     /// Dry-run: don't restore, only show what would be done
     #[clap(long, short = 'n')]
     dry_run: bool,
 
     /// Remove all files/dirs in destination which are not contained in snapshot.
+    /// Entries excluded by glob patterns are kept.
     /// WARNING: Use with care, maybe first try this first with --dry-run?
     #[clap(long)]
     delete: bool,
@@ -54,6 +62,10 @@ pub(super) struct Opts {
     #[clap(long)]
     verify_existing: bool,
 
+    /// Restore files as sparse files, i.e. don't write large blocks which only contain zeros
+    #[clap(long)]
+    sparse: bool,
+
     /// Warm up needed data pack files by running the command with %id replaced by pack id
     #[clap(long, conflicts_with = "warm-up")]
     warm_up_command: Option<String>,
@@ -62,6 +74,22 @@ pub(super) struct Opts {
     #[clap(long, value_name = "DURATION", conflicts_with = "dry-run")]
     warm_up_wait: Option<humantime::Duration>,
 
+    /// Glob pattern to exclude/include (can be specified multiple times)
+    #[clap(long, help_heading = "EXCLUDE OPTIONS")]
+    glob: Vec<String>,
+
+    /// Same as --glob pattern but ignores the casing of filenames
+    #[clap(long, value_name = "GLOB", help_heading = "EXCLUDE OPTIONS")]
+    iglob: Vec<String>,
+
+    /// Read glob patterns to exclude/include from this file (can be specified multiple times)
+    #[clap(long, value_name = "FILE", help_heading = "EXCLUDE OPTIONS")]
+    glob_file: Vec<String>,
+
+    /// Same as --glob-file ignores the casing of filenames in patterns
+    #[clap(long, value_name = "FILE", help_heading = "EXCLUDE OPTIONS")]
+    iglob_file: Vec<String>,
+
     /// Snapshot/path to restore
     #[clap(value_name = "SNAPSHOT[:PATH]")]
     snap: String,
@@ -86,16 +114,38 @@ pub(super) fn execute(
         info!("using warm-up command {command}");
     }
 
-    let (id, path) = opts.snap.split_once(':').unwrap_or((&opts.snap, ""));
-    let snap = SnapshotFile::from_str(be, id, |sn| sn.matches(&opts.filter), progress_counter(""))?;
+    let (id, path) = split_snapshot_path(&opts.snap).unwrap_or((&opts.snap, ""));
+    let snap = SnapshotFile::from_str(
+        be,
+        id,
+        |sn| sn.matches(&opts.filter),
+        opts.group.criterion(),
+        progress_counter(""),
+    )?;
 
     let index = IndexBackend::new(be, progress_counter(""))?;
     let node = Tree::node_from_path(&index, snap.tree, Path::new(path))?;
 
     let dest = LocalDestination::new(&opts.dest, true, !node.is_dir())?;
 
+    // the glob patterns are matched against the path within the snapshot
+    let overrides = glob_overrides(&opts.glob, &opts.iglob, &opts.glob_file, &opts.iglob_file)?;
+    let snap_path = Path::new("/").join(path);
+    let base = match node.is_dir() {
+        true => snap_path.as_path(),
+        false => snap_path.parent().unwrap_or(&snap_path),
+    };
+    let node_streamer =
+        || NodeStreamer::new_with_glob(index.clone(), &node, base, overrides.clone());
+
     let p = progress_spinner("collecting file information...");
-    let (file_infos, stats) = allocate_and_collect(&dest, index.clone(), &node, &opts)?;
+    let (file_infos, stats) = allocate_and_collect(
+        &dest,
+        index.clone(),
+        node_streamer()?,
+        (base, &overrides),
+        &opts,
+    )?;
     p.finish();
 
     let fs = stats.file;
@@ -117,6 +167,7 @@ pub(super) fn execute(
         );
     }
 
+    let (restore_size, matched_size) = (file_infos.restore_size, file_infos.matched_size);
     if file_infos.restore_size == 0 {
         info!("all file contents are fine.");
     } else {
@@ -128,15 +179,25 @@ pub(super) fn execute(
 
     if !opts.dry_run {
         let p = progress_spinner("setting metadata...");
-        restore_metadata(&dest, index, &node, &opts)?;
+        restore_metadata(&dest, node_streamer()?, &opts)?;
         p.finish();
         info!("restore done.");
     }
 
+    json_summary(
+        "restore",
+        json!({
+            "dry_run": opts.dry_run,
+            "files": fs,
+            "dirs": ds,
+            "restore_size": restore_size,
+            "matched_size": matched_size,
+        }),
+    );
     Ok(())
 }
 
-#[derive(Default)]
+#[derive(Default, Serialize)]
 struct FileStats {
     restore: u64,
     unchanged: u64,
@@ -152,10 +213,11 @@ struct RestoreStats {
 }
 
 /// collect restore information, scan existing files and allocate non-existing files
-fn allocate_and_collect(
+fn allocate_and_collect<BE: IndexedBackend + Unpin>(
     dest: &LocalDestination,
-    index: impl IndexedBackend + Unpin,
-    node: &Node,
+    index: BE,
+    mut node_streamer: NodeStreamer<BE>,
+    (base, overrides): (&Path, &Override),
     opts: &Opts,
 ) -> Result<(FileInfos, RestoreStats)> {
     let dest_path = Path::new(&opts.dest);
@@ -164,6 +226,7 @@ fn allocate_and_collect(
     let mut file_infos = FileInfos::new();
     let mut additional_existing = false;
     let mut removed_dir = None;
+    let mut ignored_dir: Option<PathBuf> = None;
 
     let mut process_existing = |entry: &DirEntry| -> Result<_> {
         if entry.depth() == 0 {
@@ -171,17 +234,32 @@ fn allocate_and_collect(
             return Ok(());
         }
 
+        // entries excluded by the glob patterns are not restored, so keep them untouched
+        let path = entry.path();
+        if matches!(&ignored_dir, Some(dir) if path.starts_with(dir)) {
+            return Ok(());
+        }
+        let is_dir = entry.file_type().unwrap().is_dir();
+        let snap_path = base.join(path.strip_prefix(dest_path).unwrap_or(path));
+        if overrides.matched(snap_path, is_dir).is_ignore() {
+            trace!("ignored {path:?}");
+            if is_dir {
+                ignored_dir = Some(path.to_path_buf());
+            }
+            return Ok(());
+        }
+
         debug!("additional {:?}", entry.path());
-        if entry.file_type().unwrap().is_dir() {
+        if is_dir {
             stats.dir.additional += 1;
         } else {
             stats.file.additional += 1;
         }
-        match (
-            opts.delete,
-            opts.dry_run,
-            entry.file_type().unwrap().is_dir(),
-        ) {
+        match (opts.delete, opts.dry_run, is_dir) {
+            // the dir may contain entries excluded by the glob patterns, so only remove its contents
+            (true, _, true) if !overrides.is_empty() => {
+                debug!("keeping the additional dir {path:?} as glob patterns are given");
+            }
             (true, true, true) => {
                 info!("would have removed the additional dir: {:?}", entry.path());
             }
@@ -215,6 +293,8 @@ fn allocate_and_collect(
         Ok(())
     };
 
+    let mut hardlinks = HardLinks::default();
+
     let mut process_node = |path: &PathBuf, node: &Node, exists: bool| -> Result<_> {
         match node.node_type() {
             NodeType::Dir => {
@@ -231,11 +311,31 @@ fn allocate_and_collect(
                 }
             }
             NodeType::File => {
+                // files with several links are restored as hard links to the first restored path
+                if let Some(target) = hardlinks.link_target(path, node) {
+                    if exists && dest.is_hardlink(path, target) {
+                        stats.file.unchanged += 1;
+                        trace!("identical hard link: {path:?}");
+                    } else {
+                        if exists {
+                            stats.file.modify += 1;
+                        } else {
+                            stats.file.restore += 1;
+                        }
+                        debug!("to restore: {path:?} as hard link to {target:?}");
+                        if !opts.dry_run {
+                            dest.create_hardlink(path, target)
+                                .with_context(|| format!("error creating hard link {path:?}"))?;
+                        }
+                    }
+                    return Ok(());
+                }
+
                 // collect blobs needed for restoring
                 match (
                     exists,
                     file_infos
-                        .add_file(dest, node, path.clone(), &index, opts.verify_existing)
+                        .add_file(dest, node, path.clone(), &index, opts)
                         .with_context(|| format!("error collecting information for {path:?}"))?,
                 ) {
                     // Note that exists = false and Existing or Verified can happen if the file is changed between scanning the dir
@@ -248,6 +348,16 @@ fn allocate_and_collect(
                         stats.file.verified += 1;
                         trace!("verified identical file: {path:?}");
                     }
+                    (true, AddFileResult::New(size)) if opts.sparse => {
+                        stats.file.modify += 1;
+                        debug!("to modify: {path:?}");
+                        if !opts.dry_run {
+                            // zero blocks are not written, so the existing contents must be removed
+                            dest.set_length(path, 0)
+                                .and_then(|()| dest.set_length(path, size))
+                                .with_context(|| format!("error setting length for {path:?}"))?;
+                        }
+                    }
                     // TODO: The differentiation between files to modify and files to create could be done only by add_file
                     // Currently, add_file never returns Modify, but always New, so we differentiate based on exists
                     (true, AddFileResult::New(size) | AddFileResult::Modify(size)) => {
@@ -284,7 +394,6 @@ fn allocate_and_collect(
         .filter_map(Result::ok); // TODO: print out the ignored error
     let mut next_dst = dst_iter.next();
 
-    let mut node_streamer = NodeStreamer::new(index.clone(), node)?;
     let mut next_node = node_streamer.next().transpose()?;
 
     loop {
@@ -404,12 +513,10 @@ fn restore_contents(
 
 fn restore_metadata(
     dest: &LocalDestination,
-    index: impl IndexedBackend + Unpin,
-    node: &Node,
+    mut node_streamer: NodeStreamer<impl IndexedBackend>,
     opts: &Opts,
 ) -> Result<()> {
     // walk over tree in repository and compare with tree in dest
-    let mut node_streamer = NodeStreamer::new(index, node)?;
     let mut dir_stack = Vec::new();
     while let Some((path, node)) = node_streamer.next().transpose()? {
         match node.node_type() {
@@ -523,12 +630,12 @@ impl FileInfos {
         file: &Node,
         name: PathBuf,
         index: &impl IndexedBackend,
-        ignore_mtime: bool,
+        opts: &Opts,
     ) -> Result<AddFileResult> {
         let mut open_file = dest.get_matching_file(&name, *file.meta().size());
         let file_meta = file.meta();
 
-        if !ignore_mtime {
+        if !opts.verify_existing {
             if let Some(meta) = open_file.as_ref().map(|f| f.metadata()).transpose()? {
                 // TODO: This is the same logic as in backend/ignore.rs => consollidate!
                 let mtime = meta
@@ -559,6 +666,17 @@ impl FileInfos {
             };
             let length = bl.data_length();
 
+            // a new file only contains holes, so zero blocks need not to be written. Only the blobs
+            // of zeros produced by the chunker and for holes by the backup are detected.
+            if opts.sparse
+                && open_file.is_none()
+                && length == ZERO_BLOB_SIZE
+                && id == &*ZERO_BLOB_ID
+            {
+                file_pos += length;
+                continue;
+            }
+
             let matches = match &mut open_file {
                 Some(file) => {
                     // Existing file content; check if SHA256 matches
@@ -602,3 +720,222 @@ impl FileInfos {
             .collect()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::ffi::OsStr;
+    use std::fs;
+
+    use crate::backend::{DecryptBackend, LocalBackend, WriteBackend};
+    use crate::blob::{BlobType, Metadata, Packer};
+    use crate::crypto::Key;
+    use crate::index::Indexer;
+    use crate::progress::Progress;
+    use crate::repofile::ConfigFile;
+
+    type TestBackend = DecryptBackend<LocalBackend, Key>;
+
+    fn node(name: &str, node_type: NodeType) -> Node {
+        Node::new_node(OsStr::new(name), node_type, Metadata::default())
+    }
+
+    fn dir(name: &str, id: Id) -> Node {
+        let mut node = node(name, NodeType::Dir);
+        node.set_subtree(id);
+        node
+    }
+
+    // saves the trees given by `trees` (called with a file node saver and a tree saver) into a
+    // new repository and returns the backend and the root node
+    fn test_repo(
+        repo: &Path,
+        trees: impl FnOnce(
+            &dyn Fn(&str, Metadata) -> Result<Node>,
+            &dyn Fn(Vec<Node>) -> Result<Id>,
+        ) -> Result<Id>,
+    ) -> Result<(TestBackend, Node)> {
+        let be = LocalBackend::new(repo.to_str().unwrap())?;
+        be.create()?;
+        let be = DecryptBackend::new(&be, Key::new());
+        let config = ConfigFile::new(2, Id::random(), 0);
+        let indexer = Indexer::new(be.clone()).into_shared();
+        let data_packer = Packer::new(be.clone(), BlobType::Data, indexer.clone(), &config, 0)?;
+        let tree_packer = Packer::new(be.clone(), BlobType::Tree, indexer.clone(), &config, 0)?;
+
+        // the content of each file is its name
+        let file = |name: &str, meta: Metadata| -> Result<Node> {
+            let id = hash(name.as_bytes());
+            data_packer.add(name.as_bytes(), &id)?;
+            let meta = Metadata {
+                size: name.len() as u64,
+                ..meta
+            };
+            let mut node = Node::new_node(OsStr::new(name), NodeType::File, meta);
+            node.set_content(vec![id]);
+            Ok(node)
+        };
+        let tree = |nodes: Vec<Node>| -> Result<Id> {
+            let mut tree = Tree::new();
+            for node in nodes {
+                tree.add(node);
+            }
+            let (chunk, id) = tree.serialize()?;
+            tree_packer.add(&chunk, &id)?;
+            Ok(id)
+        };
+        let root = trees(&file, &tree)?;
+        data_packer.finalize()?;
+        tree_packer.finalize()?;
+        indexer.write().unwrap().finalize()?;
+        Ok((be, dir("", root)))
+    }
+
+    // runs the restore of `root` to `dest` with the given command line arguments
+    fn restore(be: &TestBackend, root: &Node, dest: &Path, args: &[&str]) -> Result<RestoreStats> {
+        let dest = dest.to_str().unwrap();
+        let opts = Opts::try_parse_from(["restore"].iter().chain(args).chain(&[
+            "snap",
+            dest,
+            "--no-ownership",
+        ]))?;
+        let index = IndexBackend::new(be, Progress::hidden())?;
+        let dest = LocalDestination::new(dest, true, false)?;
+        let overrides = glob_overrides(&opts.glob, &opts.iglob, &opts.glob_file, &opts.iglob_file)?;
+        let base = Path::new("/");
+        let node_streamer =
+            || NodeStreamer::new_with_glob(index.clone(), root, base, overrides.clone());
+        let (file_infos, stats) = allocate_and_collect(
+            &dest,
+            index.clone(),
+            node_streamer()?,
+            (base, &overrides),
+            &opts,
+        )?;
+        restore_contents(be, &dest, file_infos)?;
+        restore_metadata(&dest, node_streamer()?, &opts)?;
+        Ok(stats)
+    }
+
+    fn restored_paths(dest: &Path) -> Result<Vec<String>> {
+        let mut paths = Vec::new();
+        for entry in WalkBuilder::new(dest).hidden(false).build().skip(1) {
+            let entry = entry?;
+            let path = entry.path().strip_prefix(dest)?;
+            paths.push(path.to_str().unwrap().to_string());
+        }
+        paths.sort();
+        Ok(paths)
+    }
+
+    #[test]
+    fn restore_include_globs() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let (be, root) = test_repo(&tmp.path().join("repo"), |file, tree| {
+            let meta = Metadata::default;
+            let sub = tree(vec![file("b.conf", meta())?, file("c.txt", meta())?])?;
+            let other = tree(vec![file("d.txt", meta())?])?;
+            let etc = tree(vec![
+                file("a.conf", meta())?,
+                dir("other", other),
+                dir("sub", sub),
+            ])?;
+            let var = tree(vec![file("e.conf", meta())?])?;
+            let usr = tree(vec![dir("lib", other), file("f.txt", meta())?])?;
+            tree(vec![dir("etc", etc), dir("usr", usr), dir("var", var)])
+        })?;
+
+        // only the parents of matching files and matching dirs are created
+        let dest = tmp.path().join("dest");
+        let stats = restore(
+            &be,
+            &root,
+            &dest,
+            &["--glob", "/etc/**/*.conf", "--glob", "/var"],
+        )?;
+        assert_eq!(
+            restored_paths(&dest)?,
+            ["etc", "etc/a.conf", "etc/sub", "etc/sub/b.conf", "var"]
+        );
+        assert_eq!(fs::read_to_string(dest.join("etc/sub/b.conf"))?, "b.conf");
+        assert_eq!(stats.dir.restore, 3);
+        assert_eq!(stats.file.restore, 2);
+
+        // restoring again doesn't change anything
+        let stats = restore(
+            &be,
+            &root,
+            &dest,
+            &["--glob", "/etc/**/*.conf", "--glob", "/var"],
+        )?;
+        assert_eq!(stats.dir.restore, 0);
+        assert_eq!(stats.file.restore, 0);
+        assert_eq!(stats.file.verified, 2);
+        assert_eq!(stats.dir.additional, 0);
+
+        // without include globs, all dirs are restored
+        let dest = tmp.path().join("all");
+        _ = restore(&be, &root, &dest, &["--glob", "!*.conf"])?;
+        assert_eq!(
+            restored_paths(&dest)?,
+            [
+                "etc",
+                "etc/other",
+                "etc/other/d.txt",
+                "etc/sub",
+                "etc/sub/c.txt",
+                "usr",
+                "usr/f.txt",
+                "usr/lib",
+                "usr/lib/d.txt",
+                "var"
+            ]
+        );
+        Ok(())
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn restore_hardlinks() -> Result<()> {
+        use std::os::unix::fs::MetadataExt;
+
+        let tmp = tempfile::tempdir()?;
+        let (be, root) = test_repo(&tmp.path().join("repo"), |file, tree| {
+            let linked = || Metadata {
+                inode: 42,
+                device_id: 1,
+                links: 2,
+                ..Default::default()
+            };
+            // both files have the same name, so they have the same contents
+            let mut a = file("file", linked())?;
+            a.name = "a".to_string();
+            let mut b = file("file", linked())?;
+            b.name = "b".to_string();
+            tree(vec![a, b])
+        })?;
+        let dest = tmp.path().join("dest");
+        let inode = |name| -> Result<_> { Ok(fs::metadata(dest.join(name))?.ino()) };
+
+        let stats = restore(&be, &root, &dest, &[])?;
+        assert_eq!(stats.file.restore, 2);
+        assert_eq!(inode("a")?, inode("b")?);
+        assert_eq!(fs::read_to_string(dest.join("b"))?, "file");
+
+        // the existing link is kept
+        let stats = restore(&be, &root, &dest, &[])?;
+        assert_eq!(stats.file.unchanged, 1);
+        assert_eq!(stats.file.modify, 0);
+        assert_eq!(inode("a")?, inode("b")?);
+
+        // an existing file which is no link is replaced by a link
+        fs::remove_file(dest.join("b"))?;
+        fs::write(dest.join("b"), "file")?;
+        assert_ne!(inode("a")?, inode("b")?);
+        let stats = restore(&be, &root, &dest, &[])?;
+        assert_eq!(stats.file.modify, 1);
+        assert_eq!(inode("a")?, inode("b")?);
+        Ok(())
+    }
+}
@@ -0,0 +1,263 @@
+use std::path::Path;
+
+use anyhow::{bail, Result};
+use chrono::Local;
+use clap::{AppSettings, Parser};
+use ignore::overrides::Override;
+use log::*;
+
+use super::{progress_counter, RusticConfig};
+use crate::backend::{glob_overrides, DecryptWriteBackend, FileType};
+use crate::blob::{BlobType, Node, NodeVisitor, Packer, Rewritten, TreeRewriter};
+use crate::index::{IndexBackend, IndexedBackend, Indexer, ReadIndex};
+use crate::repofile::{SnapshotFile, SnapshotFilter, StringList};
+use crate::repository::OpenRepository;
+
+#[derive(Parser)]
+#[clap(global_setting(AppSettings::DeriveDisplayOrder))]
+pub(super) struct Opts {
+    #[clap(flatten, help_heading = "SNAPSHOT FILTER OPTIONS")]
+    filter: SnapshotFilter,
+
+    /// Only show what would be rewritten
+    #[clap(long, short = 'n')]
+    dry_run: bool,
+
+    /// Remove the original snapshots after rewriting them
+    #[clap(long)]
+    forget: bool,
+
+    /// Tag list to add to rewritten snapshots (can be specified multiple times)
+    #[clap(long, value_name = "TAG[,TAG,..]")]
+    tag: Vec<StringList>,
+
+    /// Glob pattern to exclude/include (can be specified multiple times)
+    #[clap(long, help_heading = "EXCLUDE OPTIONS")]
+    glob: Vec<String>,
+
+    /// Same as --glob pattern but ignores the casing of filenames
+    #[clap(long, value_name = "GLOB", help_heading = "EXCLUDE OPTIONS")]
+    iglob: Vec<String>,
+
+    /// Read glob patterns to exclude/include from this file (can be specified multiple times)
+    #[clap(long, value_name = "FILE", help_heading = "EXCLUDE OPTIONS")]
+    glob_file: Vec<String>,
+
+    /// Same as --glob-file ignores the casing of filenames in patterns
+    #[clap(long, value_name = "FILE", help_heading = "EXCLUDE OPTIONS")]
+    iglob_file: Vec<String>,
+
+    /// Snapshots to rewrite. If none is given, use filter to filter from all snapshots.
+    #[clap(value_name = "ID")]
+    ids: Vec<String>,
+}
+
+pub(super) fn execute(
+    repo: OpenRepository,
+    mut opts: Opts,
+    config_file: RusticConfig,
+) -> Result<()> {
+    config_file.merge_into("snapshot-filter", &mut opts.filter)?;
+    let be = &repo.dbe;
+
+    let overrides = glob_overrides(&opts.glob, &opts.iglob, &opts.glob_file, &opts.iglob_file)?;
+    if overrides.is_empty() {
+        warn!("no exclude patterns given, snapshots will not be changed.");
+    }
+
+    let snapshots = match opts.ids.is_empty() {
+        true => SnapshotFile::all_from_backend(be, &opts.filter)?,
+        false => SnapshotFile::from_ids(be, &opts.ids)?,
+    };
+
+    let index = IndexBackend::only_full_trees(&be.clone(), progress_counter(""))?;
+    let indexer = Indexer::new(be.clone()).into_shared();
+    let packer = Packer::new(
+        be.clone(),
+        BlobType::Tree,
+        indexer.clone(),
+        &repo.config,
+        index.total_size(BlobType::Tree),
+    )?;
+
+    let mut excludes = Excludes(overrides);
+    let mut rewriter = TreeRewriter::new(&index, &packer, opts.dry_run);
+
+    let mut rewritten = Vec::new();
+    for mut snap in snapshots {
+        let snap_id = snap.id;
+        info!("processing snapshot {snap_id}");
+        if !rewrite_snapshot(&mut rewriter, &mut excludes, &mut snap, &opts.tag)? {
+            info!("snapshot {snap_id} is unchanged.");
+            continue;
+        }
+        if opts.dry_run {
+            info!("would have rewritten snapshot {snap_id}.");
+        } else {
+            let new_id = be.save_file(&snap)?;
+            info!("saved rewritten snapshot {snap_id} as {new_id}.");
+        }
+        rewritten.push(snap);
+    }
+
+    if !opts.dry_run {
+        packer.finalize()?;
+        indexer.write().unwrap().finalize()?;
+    }
+
+    if opts.forget {
+        let now = Local::now();
+        let snap_ids: Vec<_> = rewritten
+            .iter()
+            .filter(|sn| !sn.must_keep(now))
+            .map(|sn| sn.id)
+            .collect();
+        if opts.dry_run {
+            info!("would have removed {} snapshots.", snap_ids.len());
+        } else {
+            let p = progress_counter("removing rewritten snapshots...");
+            be.delete_list(FileType::Snapshot, true, snap_ids.iter(), p)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Remove all excluded nodes from the tree of the snapshot and add the tags. The original id is
+/// already set when reading the snapshot, so it is kept. Returns false if the snapshot is unchanged.
+fn rewrite_snapshot<I: IndexedBackend, BE: DecryptWriteBackend>(
+    rewriter: &mut TreeRewriter<'_, I, BE>,
+    excludes: &mut Excludes,
+    snap: &mut SnapshotFile,
+    tags: &[StringList],
+) -> Result<bool> {
+    match rewriter.rewrite_tree(excludes, Path::new(""), Some(snap.tree))? {
+        Rewritten::Unchanged => Ok(false),
+        Rewritten::Missing(_) => bail!("snapshot {}: tree could not be loaded", snap.id),
+        Rewritten::Changed(id) => {
+            snap.tree = id;
+            snap.add_tags(tags.to_vec());
+            Ok(true)
+        }
+    }
+}
+
+// removes all nodes excluded by the glob patterns
+struct Excludes(Override);
+
+impl NodeVisitor for Excludes {
+    fn visit(&mut self, path: &Path, node: Node) -> Option<Node> {
+        if self
+            .0
+            .matched(Path::new("/").join(path), node.is_dir())
+            .is_ignore()
+        {
+            info!("removing {}", path.display());
+            return None;
+        }
+        Some(node)
+    }
+
+    fn missing_tree(&mut self, path: &Path, _node: &mut Node) -> Result<()> {
+        bail!("tree {} could not be loaded", path.display())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::ffi::OsStr;
+
+    use ignore::overrides::OverrideBuilder;
+
+    use crate::backend::{DecryptBackend, LocalBackend, WriteBackend};
+    use crate::blob::{Metadata, NodeStreamer, NodeType, Tree};
+    use crate::crypto::Key;
+    use crate::id::Id;
+    use crate::progress::Progress;
+    use crate::repofile::ConfigFile;
+
+    fn node(name: &str, node_type: NodeType) -> Node {
+        Node::new_node(OsStr::new(name), node_type, Metadata::default())
+    }
+
+    fn tree(nodes: Vec<Node>) -> Tree {
+        let mut tree = Tree::new();
+        for node in nodes {
+            tree.add(node);
+        }
+        tree
+    }
+
+    #[test]
+    fn rewrite_snapshot_excludes() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let be = LocalBackend::new(dir.path().to_str().unwrap())?;
+        be.create()?;
+        let be = DecryptBackend::new(&be, Key::new());
+        let config = ConfigFile::new(2, Id::random(), 0);
+
+        // save a snapshot with the trees /a.log, /b.txt, /sub/c.log and /sub/d.txt
+        let indexer = Indexer::new(be.clone()).into_shared();
+        let packer = Packer::new(be.clone(), BlobType::Tree, indexer.clone(), &config, 0)?;
+        let save = |tree: &Tree| -> Result<Id> {
+            let (chunk, id) = tree.serialize()?;
+            packer.add(&chunk, &id)?;
+            Ok(id)
+        };
+        let mut sub = node("sub", NodeType::Dir);
+        sub.set_subtree(save(&tree(vec![
+            node("c.log", NodeType::File),
+            node("d.txt", NodeType::File),
+        ]))?);
+        let root = save(&tree(vec![
+            node("a.log", NodeType::File),
+            node("b.txt", NodeType::File),
+            sub,
+        ]))?;
+        packer.finalize()?;
+        indexer.write().unwrap().finalize()?;
+
+        let original = Id::random();
+        let snap = SnapshotFile {
+            tree: root,
+            original: Some(original),
+            ..Default::default()
+        };
+        let snap_id = be.save_file(&snap)?;
+
+        // rewrite the snapshot as read from the backend, excluding all .log files
+        let index = IndexBackend::new(&be, Progress::hidden())?;
+        let indexer = Indexer::new(be.clone()).into_shared();
+        let packer = Packer::new(be.clone(), BlobType::Tree, indexer.clone(), &config, 0)?;
+        let mut rewriter = TreeRewriter::new(&index, &packer, false);
+        let mut excludes = Excludes(OverrideBuilder::new("/").add("!*.log")?.build()?);
+        let mut snap = SnapshotFile::from_backend(&be, &snap_id)?;
+        assert!(rewrite_snapshot(
+            &mut rewriter,
+            &mut excludes,
+            &mut snap,
+            &["rewritten".parse()?]
+        )?);
+        packer.finalize()?;
+        indexer.write().unwrap().finalize()?;
+
+        // the original is kept and the excluded paths disappeared
+        assert_eq!(snap.original, Some(original));
+        assert!(snap.tags.contains(&"rewritten".to_string()));
+        let index = IndexBackend::new(&be, Progress::hidden())?;
+        let paths: Vec<_> = NodeStreamer::new(index, &node_with_tree(snap.tree))?
+            .map(|item| item.map(|(path, _)| path))
+            .collect::<Result<_>>()?;
+        let paths: Vec<_> = paths.iter().map(|p| p.to_str().unwrap()).collect();
+        assert_eq!(paths, ["b.txt", "sub", "sub/d.txt"]);
+        Ok(())
+    }
+
+    fn node_with_tree(id: Id) -> Node {
+        let mut node = node("", NodeType::Dir);
+        node.set_subtree(id);
+        node
+    }
+}
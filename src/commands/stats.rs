@@ -0,0 +1,489 @@
+use std::collections::{HashMap, HashSet};
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Local};
+use clap::{AppSettings, Parser};
+use serde::Serialize;
+
+use super::{bytes, progress_counter, table_right_from, RusticConfig};
+use crate::blob::{BlobType, NodeType, TreeStreamerOnce};
+use crate::id::Id;
+use crate::index::{IndexBackend, IndexEntry, ReadIndex};
+use crate::repofile::{SnapshotFile, SnapshotFilter};
+use crate::repository::OpenRepository;
+
+#[derive(Parser)]
+#[clap(global_setting(AppSettings::DeriveDisplayOrder))]
+pub(super) struct Opts {
+    #[clap(
+        flatten,
+        help_heading = "SNAPSHOT FILTER OPTIONS (if no snapshot is given)"
+    )]
+    filter: SnapshotFilter,
+
+    /// Show statistics in json format
+    #[clap(long)]
+    json: bool,
+
+    /// Snapshots to show statistics for. If none is given, use filter to filter from all snapshots.
+    #[clap(value_name = "ID")]
+    ids: Vec<String>,
+}
+
+pub(super) fn execute(
+    repo: OpenRepository,
+    mut opts: Opts,
+    config_file: RusticConfig,
+) -> Result<()> {
+    config_file.merge_into("snapshot-filter", &mut opts.filter)?;
+    let be = &repo.dbe;
+
+    let mut snapshots = match opts.ids.is_empty() {
+        true => SnapshotFile::all_from_backend(be, &opts.filter)?,
+        false => SnapshotFile::from_ids(be, &opts.ids)?,
+    };
+    snapshots.sort_unstable();
+    let selected: HashSet<_> = snapshots.iter().map(|sn| sn.id).collect();
+
+    // unique data is data which is not referenced by any other snapshot in the repository,
+    // so all snapshots are needed
+    let all_snapshots = SnapshotFile::all_from_backend(be, &SnapshotFilter::default())?;
+
+    let index = IndexBackend::new(be, progress_counter(""))?;
+
+    // visit each tree only once and save the information needed to compute the statistics
+    let p = progress_counter("scanning trees...");
+    let snap_trees = all_snapshots.iter().map(|sn| sn.tree).collect();
+    let mut trees = HashMap::new();
+    let mut tree_streamer = TreeStreamerOnce::new(index.clone(), snap_trees, p)?;
+    while let Some(item) = tree_streamer.next_with_id().transpose()? {
+        let (_, id, tree) = item;
+        let mut info = TreeInfo::default();
+        for node in tree.nodes() {
+            if let Some(subtree) = node.subtree {
+                info.subtrees.push(subtree);
+            }
+            if node.node_type == NodeType::File {
+                info.files += 1;
+                info.size += node.meta.size;
+                info.blobs.extend(node.content.iter().flatten().copied());
+            }
+        }
+        trees.insert(id, info);
+    }
+
+    let mut stats = StatsComputer {
+        index,
+        trees,
+        restore_sizes: HashMap::new(),
+    };
+
+    // the references of all blobs by the snapshots; these are computed for each tree only once
+    let roots: Vec<_> = all_snapshots
+        .iter()
+        .enumerate()
+        .map(|(idx, sn)| {
+            let selected = selected.contains(&sn.id);
+            let refs = Refs {
+                owner: Owner::One(idx),
+                selected,
+                other: !selected,
+            };
+            (sn.tree, refs)
+        })
+        .collect();
+    let refs = stats.refs(&roots)?;
+    let snapshot_idx: HashMap<_, _> = all_snapshots
+        .iter()
+        .enumerate()
+        .map(|(idx, sn)| (sn.id, idx))
+        .collect();
+
+    // data unique to a snapshot is data which is only referenced by it
+    let mut unique_data: HashMap<usize, BlobStats> = HashMap::new();
+    for (blob, r) in &refs {
+        if let Owner::One(idx) = r.owner {
+            unique_data
+                .entry(idx)
+                .or_default()
+                .add(&stats.index_entry(blob)?);
+        }
+    }
+
+    let mut result = StatsResult {
+        snapshots: Vec::new(),
+        total: Stats::default(),
+    };
+    for sn in snapshots {
+        let idx = *snapshot_idx
+            .get(&sn.id)
+            .ok_or_else(|| anyhow!("snapshot {} has been modified", sn.id))?;
+        let (files, restore_size) = stats.restore_size(sn.tree)?;
+        result.total.files += files;
+        result.total.restore_size += restore_size;
+        result.snapshots.push(SnapshotStats {
+            snapshot: sn.id,
+            time: sn.time,
+            stats: Stats {
+                files,
+                restore_size,
+                raw_data: stats.blob_stats(stats.blobs(sn.tree)?.iter())?,
+                unique_data: unique_data.remove(&idx).unwrap_or_default(),
+            },
+        });
+    }
+    // data unique to the selected snapshots is data which is only referenced by them
+    result.total.raw_data = stats.blob_stats(
+        refs.iter()
+            .filter(|(_, r)| r.selected)
+            .map(|(blob, _)| blob),
+    )?;
+    result.total.unique_data = stats.blob_stats(
+        refs.iter()
+            .filter(|(_, r)| r.selected && !r.other)
+            .map(|(blob, _)| blob),
+    )?;
+
+    if opts.json {
+        let mut stdout = std::io::stdout();
+        serde_json::to_writer_pretty(&mut stdout, &result)?;
+        return Ok(());
+    }
+
+    let mut table = table_right_from(
+        2,
+        [
+            "Snapshot",
+            "Time",
+            "Files",
+            "Restore Size",
+            "Raw Data",
+            "Raw Data in Packs",
+            "Unique Data",
+            "Unique Data in Packs",
+        ],
+    );
+    let row = |id: String, time: String, stats: &Stats| {
+        [
+            id,
+            time,
+            stats.files.to_string(),
+            bytes(stats.restore_size),
+            bytes(stats.raw_data.data_size),
+            bytes(stats.raw_data.size),
+            bytes(stats.unique_data.data_size),
+            bytes(stats.unique_data.size),
+        ]
+    };
+    for sn in &result.snapshots {
+        let time = sn.time.format("%Y-%m-%d %H:%M:%S").to_string();
+        table.add_row(row(sn.snapshot.to_string(), time, &sn.stats));
+    }
+    table.add_row(row("Total".to_string(), String::new(), &result.total));
+
+    println!("{table}");
+    println!(
+        "unique data: data which is not referenced by any other snapshot and would be removed by forget and prune"
+    );
+
+    Ok(())
+}
+
+type BlobId = (BlobType, Id);
+
+/// Sizes of a set of blobs
+#[derive(Default, Serialize)]
+struct BlobStats {
+    blobs: u64,
+    /// uncompressed size
+    data_size: u64,
+    /// size within the pack files, i.e. compressed and encrypted
+    size: u64,
+}
+
+impl BlobStats {
+    fn add(&mut self, ie: &IndexEntry) {
+        self.blobs += 1;
+        self.data_size += u64::from(ie.data_length());
+        self.size += u64::from(*ie.length());
+    }
+}
+
+#[derive(Default, Serialize)]
+struct Stats {
+    files: u64,
+    restore_size: u64,
+    raw_data: BlobStats,
+    unique_data: BlobStats,
+}
+
+#[derive(Serialize)]
+struct SnapshotStats {
+    snapshot: Id,
+    time: DateTime<Local>,
+    #[serde(flatten)]
+    stats: Stats,
+}
+
+#[derive(Serialize)]
+struct StatsResult {
+    snapshots: Vec<SnapshotStats>,
+    total: Stats,
+}
+
+/// The snapshots referencing a tree or blob
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum Owner {
+    #[default]
+    None,
+    /// referenced only by the snapshot with the given index
+    One(usize),
+    Many,
+}
+
+/// References of a tree or blob by the snapshots
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+struct Refs {
+    owner: Owner,
+    // referenced by a selected snapshot
+    selected: bool,
+    // referenced by a snapshot which is not selected
+    other: bool,
+}
+
+impl Refs {
+    fn add(&mut self, other: Self) {
+        self.owner = match (self.owner, other.owner) {
+            (owner, Owner::None) | (Owner::None, owner) => owner,
+            (Owner::One(a), Owner::One(b)) if a == b => Owner::One(a),
+            _ => Owner::Many,
+        };
+        self.selected |= other.selected;
+        self.other |= other.other;
+    }
+}
+
+#[derive(Default)]
+struct TreeInfo {
+    subtrees: Vec<Id>,
+    // data blobs of all files within the tree (not within subtrees)
+    blobs: Vec<Id>,
+    files: u64,
+    size: u64,
+}
+
+struct StatsComputer<I: ReadIndex> {
+    index: I,
+    trees: HashMap<Id, TreeInfo>,
+    // number of files and restore size of already computed trees
+    restore_sizes: HashMap<Id, (u64, u64)>,
+}
+
+impl<I: ReadIndex> StatsComputer<I> {
+    fn tree_info(&self, id: &Id) -> Result<&TreeInfo> {
+        self.trees
+            .get(id)
+            .ok_or_else(|| anyhow!("tree {id} has not been visited"))
+    }
+
+    // all blobs referenced by the tree including the tree itself
+    fn blobs(&self, id: Id) -> Result<HashSet<BlobId>> {
+        let mut blobs = HashSet::new();
+        let mut pending = vec![id];
+        while let Some(id) = pending.pop() {
+            if !blobs.insert((BlobType::Tree, id)) {
+                continue;
+            }
+            let info = self.tree_info(&id)?;
+            blobs.extend(info.blobs.iter().map(|id| (BlobType::Data, *id)));
+            pending.extend(&info.subtrees);
+        }
+        Ok(blobs)
+    }
+
+    /// Compute the references of all trees and blobs reachable from the given root trees. The
+    /// references are propagated from each tree to its subtrees and blobs, so each tree is only
+    /// processed once, regardless of how many snapshots it is referenced.
+    fn refs(&self, roots: &[(Id, Refs)]) -> Result<HashMap<BlobId, Refs>> {
+        // order the trees such that each tree comes before its subtrees (reverse postorder)
+        let mut order = Vec::new();
+        let mut visited = HashSet::new();
+        for (root, _) in roots {
+            let mut stack = vec![(*root, false)];
+            while let Some((id, expanded)) = stack.pop() {
+                if expanded {
+                    order.push(id);
+                    continue;
+                }
+                if !visited.insert(id) {
+                    continue;
+                }
+                stack.push((id, true));
+                let info = self.tree_info(&id)?;
+                stack.extend(info.subtrees.iter().map(|id| (*id, false)));
+            }
+        }
+        order.reverse();
+
+        let mut tree_refs: HashMap<Id, Refs> = HashMap::new();
+        for (root, refs) in roots {
+            tree_refs.entry(*root).or_default().add(*refs);
+        }
+        let mut refs = HashMap::new();
+        for id in order {
+            let tree_ref = tree_refs[&id];
+            let info = self.tree_info(&id)?;
+            for subtree in &info.subtrees {
+                tree_refs.entry(*subtree).or_default().add(tree_ref);
+            }
+            for blob in &info.blobs {
+                refs.entry((BlobType::Data, *blob))
+                    .or_insert_with(Refs::default)
+                    .add(tree_ref);
+            }
+        }
+        refs.extend(
+            tree_refs
+                .into_iter()
+                .map(|(id, r)| ((BlobType::Tree, id), r)),
+        );
+        Ok(refs)
+    }
+
+    // number of files and their total size when restoring the tree
+    fn restore_size(&mut self, id: Id) -> Result<(u64, u64)> {
+        if let Some(size) = self.restore_sizes.get(&id) {
+            return Ok(*size);
+        }
+        let info = self.tree_info(&id)?;
+        let (mut files, mut size) = (info.files, info.size);
+        for subtree in info.subtrees.clone() {
+            let (sub_files, sub_size) = self.restore_size(subtree)?;
+            files += sub_files;
+            size += sub_size;
+        }
+        self.restore_sizes.insert(id, (files, size));
+        Ok((files, size))
+    }
+
+    fn index_entry(&self, (tpe, id): &BlobId) -> Result<IndexEntry> {
+        self.index
+            .get_id(*tpe, id)
+            .ok_or_else(|| anyhow!("{tpe:?} blob {id} is missing in index"))
+    }
+
+    fn blob_stats<'a>(&self, blobs: impl Iterator<Item = &'a BlobId>) -> Result<BlobStats> {
+        let mut stats = BlobStats::default();
+        for blob in blobs {
+            stats.add(&self.index_entry(blob)?);
+        }
+        Ok(stats)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::index::{IndexCollector, IndexType};
+    use crate::repofile::{IndexBlob, IndexPack};
+
+    fn tree(subtrees: &[Id], blobs: &[Id]) -> TreeInfo {
+        TreeInfo {
+            subtrees: subtrees.to_vec(),
+            blobs: blobs.to_vec(),
+            ..Default::default()
+        }
+    }
+
+    fn refs(owner: Owner, selected: bool, other: bool) -> Refs {
+        Refs {
+            owner,
+            selected,
+            other,
+        }
+    }
+
+    #[test]
+    fn shared_refs() -> Result<()> {
+        let [root0, root1, root2, only0, shared, common, inner] = [(); 7].map(|()| Id::random());
+        let [a0, u0, a1, s, c, b02, i2] = [(); 7].map(|()| Id::random());
+
+        // snapshots 0 and 1 are selected and share the tree "shared"; all snapshots contain the
+        // tree "common" and the blob b02 is contained in snapshots 0 and 2. The tree "inner" is
+        // contained twice in snapshot 2.
+        let trees = HashMap::from([
+            (root0, tree(&[only0, shared, common], &[a0, b02])),
+            (only0, tree(&[], &[u0])),
+            (root1, tree(&[shared, common], &[a1, s])),
+            (shared, tree(&[], &[s])),
+            (root2, tree(&[common, inner, inner], &[b02])),
+            (common, tree(&[], &[c])),
+            (inner, tree(&[], &[i2])),
+        ]);
+
+        // each blob has a data size of 100 bytes
+        let mut collector = IndexCollector::new(IndexType::Full);
+        let blob = |tpe, id| IndexBlob {
+            id,
+            tpe,
+            offset: 0,
+            length: 132,
+            uncompressed_length: None,
+        };
+        let tree_blobs = trees.keys().map(|id| blob(BlobType::Tree, *id)).collect();
+        let data_blobs = [a0, u0, a1, s, c, b02, i2].map(|id| blob(BlobType::Data, id));
+        collector.extend([tree_blobs, data_blobs.to_vec()].map(|blobs| IndexPack {
+            id: Id::random(),
+            blobs,
+            time: None,
+            size: None,
+        }));
+
+        let stats = StatsComputer {
+            index: collector.into_index(),
+            trees,
+            restore_sizes: HashMap::new(),
+        };
+        let roots = [
+            (root0, refs(Owner::One(0), true, false)),
+            (root1, refs(Owner::One(1), true, false)),
+            (root2, refs(Owner::One(2), false, true)),
+        ];
+        let result = stats.refs(&roots)?;
+
+        assert_eq!(result.len(), 14);
+        let data = |id| result[&(BlobType::Data, id)];
+        let tree = |id| result[&(BlobType::Tree, id)];
+        assert_eq!(tree(root0), refs(Owner::One(0), true, false));
+        assert_eq!(tree(only0), refs(Owner::One(0), true, false));
+        assert_eq!(data(u0), refs(Owner::One(0), true, false));
+        assert_eq!(data(a1), refs(Owner::One(1), true, false));
+        assert_eq!(tree(shared), refs(Owner::Many, true, false));
+        assert_eq!(data(s), refs(Owner::Many, true, false));
+        assert_eq!(tree(common), refs(Owner::Many, true, true));
+        assert_eq!(data(c), refs(Owner::Many, true, true));
+        assert_eq!(data(b02), refs(Owner::Many, true, true));
+        assert_eq!(tree(inner), refs(Owner::One(2), false, true));
+        assert_eq!(data(i2), refs(Owner::One(2), false, true));
+
+        // unique data of the selected snapshots: root0, root1, only0, shared, a0, u0, a1 and s
+        let unique = stats.blob_stats(
+            result
+                .iter()
+                .filter(|(_, r)| r.selected && !r.other)
+                .map(|(blob, _)| blob),
+        )?;
+        assert_eq!(unique.blobs, 8);
+        assert_eq!(unique.data_size, 800);
+        assert_eq!(unique.size, 8 * 132);
+        let raw = stats.blob_stats(
+            result
+                .iter()
+                .filter(|(_, r)| r.selected)
+                .map(|(blob, _)| blob),
+        )?;
+        assert_eq!(raw.blobs, 11);
+        Ok(())
+    }
+}
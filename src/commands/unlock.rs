@@ -0,0 +1,43 @@
+use anyhow::Result;
+use clap::Parser;
+use log::*;
+
+use crate::backend::{DecryptReadBackend, FileType, ReadBackend, WriteBackend};
+use crate::repofile::LockFile;
+use crate::repository::OpenRepository;
+
+#[derive(Parser)]
+pub(super) struct Opts {
+    /// Remove all locks, even non-stale ones
+    #[clap(long)]
+    remove_all: bool,
+}
+
+pub(super) fn execute(repo: OpenRepository, opts: Opts) -> Result<()> {
+    let be = &repo.dbe;
+
+    let mut removed = 0;
+    for id in be.list(FileType::Lock)? {
+        let remove = match be.get_file::<LockFile>(&id) {
+            Ok(lock) if opts.remove_all || lock.is_stale() => {
+                info!("removing {lock}");
+                true
+            }
+            Ok(lock) => {
+                info!("keeping {lock}");
+                false
+            }
+            Err(err) => {
+                warn!("error reading lock {id}: {err}");
+                opts.remove_all
+            }
+        };
+        if remove {
+            be.remove(FileType::Lock, &id, false)?;
+            removed += 1;
+        }
+    }
+    println!("removed {removed} locks");
+
+    Ok(())
+}
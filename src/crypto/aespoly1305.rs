@@ -14,6 +14,8 @@ type AeadKey = aead::Key<Aes256CtrPoly1305Aes>;
 pub enum KeyError {
     #[error("crypto error")]
     CryptoError,
+    #[error("invalid key")]
+    InvalidKey,
 }
 
 #[derive(Clone, Default)]
@@ -49,6 +51,27 @@ impl Key {
 
         (encrypt, k, r)
     }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// Encrypt the data using the given nonce. Note that a nonce must never be reused!
+    pub fn encrypt_data_with_nonce(
+        &self,
+        nonce: &[u8; 16],
+        data: &[u8],
+    ) -> Result<Vec<u8>, KeyError> {
+        let nonce = Nonce::from_slice(nonce);
+        let mut res = Vec::with_capacity(data.len() + 32);
+        res.extend_from_slice(nonce);
+        res.extend_from_slice(data);
+        let tag = Aes256CtrPoly1305Aes::new(&self.0)
+            .encrypt_in_place_detached(nonce, &[], &mut res[16..])
+            .map_err(|_| KeyError::CryptoError)?;
+        res.extend_from_slice(&tag);
+        Ok(res)
+    }
 }
 
 impl CryptoKey for Key {
@@ -66,17 +89,9 @@ impl CryptoKey for Key {
     }
 
     fn encrypt_data(&self, data: &[u8]) -> Result<Vec<u8>, Self::CryptoError> {
-        let mut nonce = Nonce::default();
+        let mut nonce = [0; 16];
         thread_rng().fill_bytes(&mut nonce);
-
-        let mut res = Vec::with_capacity(data.len() + 32);
-        res.extend_from_slice(&nonce);
-        res.extend_from_slice(data);
-        let tag = Aes256CtrPoly1305Aes::new(&self.0)
-            .encrypt_in_place_detached(&nonce, &[], &mut res[16..])
-            .map_err(|_| KeyError::CryptoError)?;
-        res.extend_from_slice(&tag);
-        Ok(res)
+        self.encrypt_data_with_nonce(&nonce, data)
     }
 }
 
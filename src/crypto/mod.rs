@@ -2,8 +2,10 @@ use std::fmt::Debug;
 
 mod aespoly1305;
 mod hasher;
+mod writeonly;
 pub use aespoly1305::*;
 pub use hasher::*;
+pub use writeonly::*;
 
 pub trait CryptoKey: Clone + Sized + Send + Sync + 'static {
     type CryptoError: Debug + Send + Sync + 'static + std::error::Error;
@@ -0,0 +1,393 @@
+//! Keys for write-only access to a repository
+//!
+//! A write-only key can add data to the repository but cannot read data which has been saved
+//! earlier. Each write-only key (called writer) owns a symmetric key which is replaced by a key
+//! derived from it each time the writer is used. So a compromised writer cannot decrypt data saved
+//! in earlier runs. The initial symmetric key of each writer is sealed using the public key of the
+//! repository and can only be unsealed using the corresponding private key.
+//!
+//! Data encrypted by a writer is identified by its nonce: The first 4 bytes contain the id of the
+//! writer and the following 4 bytes the number of the derived key used.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+
+use rand::{thread_rng, RngCore};
+use sha2::{Digest, Sha256};
+use x25519_dalek::{EphemeralSecret, PublicKey as X25519PublicKey, StaticSecret};
+
+use super::{CryptoKey, Key, KeyError};
+
+// derive a key from the given input using SHA-256
+fn derive_key(context: &[u8], input: &[&[u8]]) -> Key {
+    let mut key = [0; 64];
+    for (i, half) in key.chunks_mut(32).enumerate() {
+        let mut hasher = Sha256::new();
+        hasher.update(context);
+        hasher.update([i as u8]);
+        for part in input {
+            hasher.update(part);
+        }
+        half.copy_from_slice(&hasher.finalize());
+    }
+    Key::from_slice(&key)
+}
+
+/// Public key of a repository, used to seal the keys of writers
+#[derive(Clone)]
+pub struct PublicKey(X25519PublicKey);
+
+impl PublicKey {
+    pub fn from_hex(s: &str) -> Result<Self, KeyError> {
+        let bytes: [u8; 32] = hex::decode(s)
+            .ok()
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or(KeyError::InvalidKey)?;
+        Ok(Self(bytes.into()))
+    }
+
+    pub fn to_hex(&self) -> String {
+        hex::encode(self.0.as_bytes())
+    }
+
+    /// Encrypt data such that it can only be decrypted using the corresponding [`PrivateKey`].
+    /// The result contains an ephemeral public key followed by the encrypted data.
+    pub fn seal(&self, data: &[u8]) -> Result<Vec<u8>, KeyError> {
+        let secret = EphemeralSecret::random_from_rng(thread_rng());
+        let ephemeral = X25519PublicKey::from(&secret);
+        let shared = secret.diffie_hellman(&self.0);
+        let key = derive_key(
+            b"rustic seal",
+            &[shared.as_bytes(), ephemeral.as_bytes(), self.0.as_bytes()],
+        );
+        let mut res = ephemeral.as_bytes().to_vec();
+        res.extend(key.encrypt_data(data)?);
+        Ok(res)
+    }
+}
+
+/// Private key of a repository, needed to read data saved by writers
+pub struct PrivateKey(StaticSecret);
+
+impl PrivateKey {
+    pub fn generate() -> Self {
+        Self(StaticSecret::random_from_rng(thread_rng()))
+    }
+
+    pub fn from_hex(s: &str) -> Result<Self, KeyError> {
+        let bytes: [u8; 32] = hex::decode(s.trim())
+            .ok()
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or(KeyError::InvalidKey)?;
+        Ok(Self(bytes.into()))
+    }
+
+    pub fn to_hex(&self) -> String {
+        hex::encode(self.0.as_bytes())
+    }
+
+    pub fn public_key(&self) -> PublicKey {
+        PublicKey(X25519PublicKey::from(&self.0))
+    }
+
+    /// Decrypt data which has been sealed using [`PublicKey::seal`]
+    pub fn unseal(&self, data: &[u8]) -> Result<Vec<u8>, KeyError> {
+        if data.len() < 32 {
+            return Err(KeyError::CryptoError);
+        }
+        let ephemeral: [u8; 32] = data[0..32].try_into().unwrap();
+        let ephemeral = X25519PublicKey::from(ephemeral);
+        let shared = self.0.diffie_hellman(&ephemeral);
+        if !shared.was_contributory() {
+            return Err(KeyError::CryptoError);
+        }
+        let key = derive_key(
+            b"rustic seal",
+            &[
+                shared.as_bytes(),
+                ephemeral.as_bytes(),
+                X25519PublicKey::from(&self.0).as_bytes(),
+            ],
+        );
+        key.decrypt_data(&data[32..])
+    }
+}
+
+/// Key of a writer: The initial key derived `counter` times
+#[derive(Clone)]
+pub struct WriteOnlyKey {
+    pub writer: u32,
+    pub counter: u32,
+    pub key: Key,
+}
+
+impl WriteOnlyKey {
+    /// Create the initial key of a new writer
+    pub fn new() -> Self {
+        Self {
+            writer: thread_rng().next_u32(),
+            counter: 0,
+            key: Key::new(),
+        }
+    }
+
+    /// Return the next derived key. The current key cannot be computed from the result.
+    pub fn next(&self) -> Self {
+        Self {
+            writer: self.writer,
+            counter: self.counter + 1,
+            key: next_key(&self.key),
+        }
+    }
+
+    /// Seal the key using the public key of the repository. This must be the initial key.
+    pub fn seal(&self, public_key: &PublicKey) -> Result<Vec<u8>, KeyError> {
+        if self.counter != 0 {
+            return Err(KeyError::InvalidKey);
+        }
+        let mut data = self.writer.to_be_bytes().to_vec();
+        data.extend_from_slice(self.key.as_bytes());
+        public_key.seal(&data)
+    }
+}
+
+impl Default for WriteOnlyKey {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn next_key(key: &Key) -> Key {
+    derive_key(b"rustic writer", &[key.as_bytes()])
+}
+
+impl CryptoKey for WriteOnlyKey {
+    type CryptoError = KeyError;
+
+    // Only data saved using the current key can be decrypted
+    fn decrypt_data(&self, data: &[u8]) -> Result<Vec<u8>, Self::CryptoError> {
+        self.key.decrypt_data(data)
+    }
+
+    fn encrypt_data(&self, data: &[u8]) -> Result<Vec<u8>, Self::CryptoError> {
+        let mut nonce = [0; 16];
+        nonce[0..4].copy_from_slice(&self.writer.to_be_bytes());
+        nonce[4..8].copy_from_slice(&self.counter.to_be_bytes());
+        thread_rng().fill_bytes(&mut nonce[8..]);
+        self.key.encrypt_data_with_nonce(&nonce, data)
+    }
+}
+
+// Maximum number of keys derived beyond the highest key of a writer which has decrypted data.
+// Counters are read from the data, so this bounds the work caused by malformed or corrupted data.
+// As each run of a writer uses one key, this only rejects data after about a million runs.
+const MAX_COUNTER_WINDOW: u32 = 1 << 20;
+
+// Number of derived keys of a writer kept in addition to the latest one
+const KEY_CACHE_SIZE: usize = 32;
+
+// keys of a single writer
+struct WriterState {
+    initial: Key,
+    // highest counter of a key which successfully decrypted data
+    verified: u32,
+    // highest derived key
+    latest: (u32, Key),
+    // recently used keys
+    cache: BTreeMap<u32, Key>,
+}
+
+impl WriterState {
+    fn new(initial: Key) -> Self {
+        Self {
+            latest: (0, initial.clone()),
+            initial,
+            verified: 0,
+            cache: BTreeMap::new(),
+        }
+    }
+
+    fn key(&mut self, counter: u32) -> Option<Key> {
+        if counter > self.verified.saturating_add(MAX_COUNTER_WINDOW) {
+            return None;
+        }
+        if let Some(key) = self.cache.get(&counter) {
+            return Some(key.clone());
+        }
+        // start deriving from the nearest known key
+        let (mut current, mut key) = if counter >= self.latest.0 {
+            self.latest.clone()
+        } else {
+            self.cache
+                .range(..=counter)
+                .next_back()
+                .map_or_else(|| (0, self.initial.clone()), |(c, k)| (*c, k.clone()))
+        };
+        while current < counter {
+            key = next_key(&key);
+            current += 1;
+        }
+        if counter > self.latest.0 {
+            self.latest = (counter, key.clone());
+        }
+        self.cache.insert(counter, key.clone());
+        if self.cache.len() > KEY_CACHE_SIZE {
+            _ = self.cache.pop_first();
+        }
+        Some(key)
+    }
+}
+
+/// Keys of all writers of a repository, used to decrypt the data they saved
+#[derive(Default)]
+pub struct WriterKeys {
+    keys: HashMap<u32, Mutex<WriterState>>,
+}
+
+impl WriterKeys {
+    /// Unseal the given initial keys of the writers using the private key
+    pub fn unseal(private_key: &PrivateKey, sealed: &[Vec<u8>]) -> Result<Self, KeyError> {
+        let mut keys = HashMap::new();
+        for sealed in sealed {
+            let data = private_key.unseal(sealed)?;
+            if data.len() != 68 {
+                return Err(KeyError::InvalidKey);
+            }
+            let writer = u32::from_be_bytes(data[0..4].try_into().unwrap());
+            let key = Key::from_slice(&data[4..]);
+            keys.insert(writer, Mutex::new(WriterState::new(key)));
+        }
+        Ok(Self { keys })
+    }
+
+    /// Return whether no keys of writers are known
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    // decrypt data using the key of the writer which has been used to encrypt it
+    fn decrypt_data(&self, data: &[u8]) -> Result<Vec<u8>, KeyError> {
+        if data.len() < 8 {
+            return Err(KeyError::CryptoError);
+        }
+        let writer = u32::from_be_bytes(data[0..4].try_into().unwrap());
+        let counter = u32::from_be_bytes(data[4..8].try_into().unwrap());
+        let state = self.keys.get(&writer).ok_or(KeyError::CryptoError)?;
+        let key = state
+            .lock()
+            .unwrap()
+            .key(counter)
+            .ok_or(KeyError::CryptoError)?;
+        let res = key.decrypt_data(data)?;
+        let mut state = state.lock().unwrap();
+        state.verified = state.verified.max(counter);
+        Ok(res)
+    }
+}
+
+/// Key used to access the repository
+#[derive(Clone)]
+pub enum RepoKey {
+    /// The master key together with the keys of all writers which could be unsealed
+    Master { key: Key, writers: Arc<WriterKeys> },
+    /// The key of a writer; this only allows to add data to the repository
+    WriteOnly(WriteOnlyKey),
+}
+
+impl RepoKey {
+    pub fn master(key: Key, writers: WriterKeys) -> Self {
+        Self::Master {
+            key,
+            writers: Arc::new(writers),
+        }
+    }
+
+    pub fn master_key(&self) -> Option<&Key> {
+        match self {
+            Self::Master { key, .. } => Some(key),
+            Self::WriteOnly(_) => None,
+        }
+    }
+
+    /// Return the keys of the writers known to the master key
+    pub fn writer_keys(&self) -> Option<&WriterKeys> {
+        match self {
+            Self::Master { writers, .. } => Some(writers),
+            Self::WriteOnly(_) => None,
+        }
+    }
+
+    pub fn is_write_only(&self) -> bool {
+        matches!(self, Self::WriteOnly(_))
+    }
+}
+
+impl CryptoKey for RepoKey {
+    type CryptoError = KeyError;
+
+    fn decrypt_data(&self, data: &[u8]) -> Result<Vec<u8>, Self::CryptoError> {
+        match self {
+            Self::Master { key, writers } => {
+                // if decryption using the master key fails, the data might have been saved by a writer
+                key.decrypt_data(data)
+                    .or_else(|err| writers.decrypt_data(data).map_err(|_| err))
+            }
+            Self::WriteOnly(key) => key.decrypt_data(data),
+        }
+    }
+
+    fn encrypt_data(&self, data: &[u8]) -> Result<Vec<u8>, Self::CryptoError> {
+        match self {
+            Self::Master { key, .. } => key.encrypt_data(data),
+            Self::WriteOnly(key) => key.encrypt_data(data),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seal_unseal() {
+        let private_key = PrivateKey::generate();
+        let data = b"Hello!";
+        let sealed = private_key.public_key().seal(data).unwrap();
+        assert_eq!(private_key.unseal(&sealed).unwrap(), data);
+        assert!(PrivateKey::generate().unseal(&sealed).is_err());
+    }
+
+    #[test]
+    fn read_writer_data() {
+        let private_key = PrivateKey::generate();
+        let writer = WriteOnlyKey::new();
+        let sealed = writer.seal(&private_key.public_key()).unwrap();
+        let writers = WriterKeys::unseal(&private_key, &[sealed]).unwrap();
+        let repo_key = RepoKey::master(Key::new(), writers);
+
+        let writer = writer.next().next();
+        assert!(writer.seal(&private_key.public_key()).is_err());
+        let data = b"Hello!";
+        let enc = writer.encrypt_data(data).unwrap();
+        assert_eq!(repo_key.decrypt_data(&enc).unwrap(), data);
+        // the next key, which is saved for later runs, cannot read data saved using this key
+        assert!(writer.next().decrypt_data(&enc).is_err());
+    }
+
+    #[test]
+    fn reject_huge_counter() {
+        let private_key = PrivateKey::generate();
+        let writer = WriteOnlyKey::new();
+        let sealed = writer.seal(&private_key.public_key()).unwrap();
+        let writers = WriterKeys::unseal(&private_key, &[sealed]).unwrap();
+        let repo_key = RepoKey::master(Key::new(), writers);
+
+        let mut enc = writer.encrypt_data(b"Hello!").unwrap();
+        enc[4..8].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert!(repo_key.decrypt_data(&enc).is_err());
+        // data within the window of the highest used key can still be read
+        let writer = writer.next().next();
+        let enc = writer.encrypt_data(b"Hello!").unwrap();
+        assert_eq!(repo_key.decrypt_data(&enc).unwrap(), b"Hello!");
+    }
+}
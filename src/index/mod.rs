@@ -5,11 +5,11 @@ use anyhow::{anyhow, Result};
 use bytes::Bytes;
 use derive_getters::Getters;
 use derive_more::Constructor;
-use indicatif::ProgressBar;
 
 use crate::backend::{DecryptReadBackend, FileType};
 use crate::blob::BlobType;
 use crate::id::Id;
+use crate::progress::Progress;
 use crate::repofile::{IndexBlob, IndexFile};
 
 mod binarysorted;
@@ -121,7 +121,7 @@ impl<BE: DecryptReadBackend> IndexBackend<BE> {
         }
     }
 
-    fn new_from_collector(be: &BE, p: ProgressBar, mut collector: IndexCollector) -> Result<Self> {
+    fn new_from_collector(be: &BE, p: Progress, mut collector: IndexCollector) -> Result<Self> {
         p.set_prefix("reading index...");
         for index in be.stream_all::<IndexFile>(p.clone())? {
             collector.extend(index?.1.packs);
@@ -132,11 +132,11 @@ impl<BE: DecryptReadBackend> IndexBackend<BE> {
         Ok(Self::new_from_index(be, collector.into_index()))
     }
 
-    pub fn new(be: &BE, p: ProgressBar) -> Result<Self> {
+    pub fn new(be: &BE, p: Progress) -> Result<Self> {
         Self::new_from_collector(be, p, IndexCollector::new(IndexType::Full))
     }
 
-    pub fn only_full_trees(be: &BE, p: ProgressBar) -> Result<Self> {
+    pub fn only_full_trees(be: &BE, p: Progress) -> Result<Self> {
         Self::new_from_collector(be, p, IndexCollector::new(IndexType::FullTrees))
     }
 
@@ -44,6 +44,8 @@ mod commands;
 mod crypto;
 mod id;
 mod index;
+mod metrics;
+mod progress;
 mod repofile;
 mod repository;
 
@@ -0,0 +1,224 @@
+//! Export of metrics in the Prometheus text format
+//!
+//! Commands record their results using the functions in this module. At the end of the run, the
+//! collected metrics are written to a file (to be used with the textfile collector of the
+//! `node_exporter`) and/or pushed to a Prometheus pushgateway.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use anyhow::{anyhow, Result};
+use chrono::Local;
+use gethostname::gethostname;
+use lazy_static::lazy_static;
+use prometheus::{Encoder, GaugeVec, Opts, Registry, TextEncoder};
+use serde::Serialize;
+
+use crate::repofile::{SnapshotFile, SnapshotGroup};
+
+lazy_static! {
+    static ref METRICS: Mutex<Option<Metrics>> = Mutex::new(None);
+}
+
+#[derive(Default)]
+struct Metrics {
+    registry: Registry,
+    gauges: HashMap<String, GaugeVec>,
+}
+
+impl Metrics {
+    fn set(&mut self, name: &str, help: &str, labels: &[(&str, &str)], value: f64) -> Result<()> {
+        let gauge = match self.gauges.get(name) {
+            Some(gauge) => gauge,
+            None => {
+                let names: Vec<_> = labels.iter().map(|(name, _)| *name).collect();
+                let gauge = GaugeVec::new(Opts::new(name, help), &names)?;
+                self.registry.register(Box::new(gauge.clone()))?;
+                self.gauges.entry(name.to_string()).or_insert(gauge)
+            }
+        };
+        let values: Vec<_> = labels.iter().map(|(_, value)| *value).collect();
+        gauge.get_metric_with_label_values(&values)?.set(value);
+        Ok(())
+    }
+
+    // set a gauge for each numeric field of the serialized value
+    fn set_all(
+        &mut self,
+        prefix: &str,
+        value: impl Serialize,
+        labels: &[(&str, &str)],
+    ) -> Result<()> {
+        let serde_json::Value::Object(map) = serde_json::to_value(value)? else {
+            return Err(anyhow!("{prefix}: metrics must be given as struct"));
+        };
+        for (key, value) in map {
+            if let Some(value) = value.as_f64() {
+                let help = format!("Value of {key} in the {prefix} summary");
+                self.set(&format!("rustic_{prefix}_{key}"), &help, labels, value)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// run f on the metrics if the export of metrics is enabled
+fn with_metrics(f: impl FnOnce(&mut Metrics) -> Result<()>) -> Result<()> {
+    match METRICS.lock().unwrap().as_mut() {
+        Some(metrics) => f(metrics),
+        None => Ok(()),
+    }
+}
+
+/// Enable collecting metrics. Without calling this, all metrics are ignored.
+pub fn enable_metrics() {
+    *METRICS.lock().unwrap() = Some(Metrics::default());
+}
+
+/// Record the summary of a backup run. The labels are given by the snapshot group of the backup.
+pub fn backup_metrics(snap: &SnapshotFile, group: &SnapshotGroup) -> Result<()> {
+    with_metrics(|metrics| {
+        let key_values = group.key_values();
+        let labels: Vec<_> = key_values
+            .iter()
+            .map(|(key, value)| (*key, value.as_str()))
+            .collect();
+        if let Some(summary) = &snap.summary {
+            metrics.set_all("backup", summary, &labels)?;
+        }
+        metrics.set(
+            "rustic_backup_timestamp_seconds",
+            "Time of the last successful backup",
+            &labels,
+            snap.time.timestamp() as f64,
+        )
+    })
+}
+
+/// Record the statistics of a prune run
+pub fn prune_metrics(stats: impl Serialize) -> Result<()> {
+    with_metrics(|metrics| {
+        metrics.set_all("prune", stats, &[])?;
+        metrics.set(
+            "rustic_prune_timestamp_seconds",
+            "Time of the last successful prune",
+            &[],
+            Local::now().timestamp() as f64,
+        )
+    })
+}
+
+/// Record the result of a check run
+pub fn check_metrics(errors: u64, warnings: u64) -> Result<()> {
+    with_metrics(|metrics| {
+        metrics.set(
+            "rustic_check_errors",
+            "Number of errors found by the last check",
+            &[],
+            errors as f64,
+        )?;
+        metrics.set(
+            "rustic_check_warnings",
+            "Number of warnings found by the last check",
+            &[],
+            warnings as f64,
+        )?;
+        metrics.set(
+            "rustic_check_timestamp_seconds",
+            "Time of the last check",
+            &[],
+            Local::now().timestamp() as f64,
+        )
+    })
+}
+
+/// Record whether the run of the given command was successful
+pub fn run_metrics(command: &str, success: bool) -> Result<()> {
+    with_metrics(|metrics| {
+        let labels = [("command", command)];
+        metrics.set(
+            "rustic_last_run_success",
+            "Whether the last run of the command was successful",
+            &labels,
+            if success { 1.0 } else { 0.0 },
+        )?;
+        metrics.set(
+            "rustic_last_run_timestamp_seconds",
+            "Time of the last run of the command",
+            &labels,
+            Local::now().timestamp() as f64,
+        )
+    })
+}
+
+/// Write the collected metrics of `command` to a file derived from `file` and/or push them to the
+/// pushgateway given by `pushgateway`. Does nothing if metrics are not enabled.
+///
+/// Each command gets its own file (see [`command_file`]) and grouping key, so that running one
+/// command does not remove the metrics of another. The file is replaced atomically, so it can be
+/// directly used by the textfile collector. Metrics are pushed using the grouping key
+/// `job="rustic"`, `instance="<hostname>"` and `command="<command>"`.
+pub fn export_metrics(command: &str, file: Option<&Path>, pushgateway: Option<&str>) -> Result<()> {
+    let mut buffer = Vec::new();
+    let encoder = TextEncoder::new();
+    match METRICS.lock().unwrap().as_ref() {
+        Some(metrics) => encoder.encode(&metrics.registry.gather(), &mut buffer)?,
+        None => return Ok(()),
+    }
+
+    if let Some(file) = file {
+        let file = command_file(file, command);
+        let mut tmp_file = file.as_os_str().to_owned();
+        tmp_file.push(".tmp");
+        fs::write(&tmp_file, &buffer)?;
+        fs::rename(&tmp_file, &file)?;
+    }
+
+    if let Some(url) = pushgateway {
+        let hostname = gethostname();
+        let url = format!(
+            "{}/metrics/job/rustic/instance/{}/command/{command}",
+            url.trim_end_matches('/'),
+            hostname.to_string_lossy()
+        );
+        reqwest::blocking::Client::new()
+            .post(url)
+            .header(reqwest::header::CONTENT_TYPE, encoder.format_type())
+            .body(buffer)
+            .send()?
+            .error_for_status()?;
+    }
+    Ok(())
+}
+
+/// Get the file to write the metrics of `command` to: The command name is appended to the file stem,
+/// e.g. `rustic.prom` becomes `rustic_backup.prom`.
+fn command_file(file: &Path, command: &str) -> PathBuf {
+    let mut name = file.file_stem().unwrap_or_default().to_owned();
+    name.push("_");
+    name.push(command);
+    if let Some(ext) = file.extension() {
+        name.push(".");
+        name.push(ext);
+    }
+    file.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn command_file_name() {
+        assert_eq!(
+            command_file(Path::new("/var/lib/rustic.prom"), "backup"),
+            PathBuf::from("/var/lib/rustic_backup.prom")
+        );
+        assert_eq!(
+            command_file(Path::new("metrics"), "check"),
+            PathBuf::from("metrics_check")
+        );
+    }
+}
@@ -0,0 +1,294 @@
+//! Progress reporting
+//!
+//! Long-running operations report their progress using [`Progress`]. Depending on the global
+//! options, this is either shown as progress bar or written as line-delimited JSON events.
+
+use std::borrow::Cow;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Local};
+use indicatif::ProgressBar;
+use lazy_static::lazy_static;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use serde::Serialize;
+use simplelog::{Config, SharedLogger};
+
+lazy_static! {
+    static ref JSON_WRITER: Mutex<Option<Box<dyn Write + Send>>> = Mutex::new(None);
+}
+
+// number of warnings and errors which have been logged
+static WARNINGS: AtomicU64 = AtomicU64::new(0);
+static ERRORS: AtomicU64 = AtomicU64::new(0);
+
+/// Write progress as JSON events to the given writer instead of showing progress bars
+pub fn set_json_writer(writer: Box<dyn Write + Send>) {
+    *JSON_WRITER.lock().unwrap() = Some(writer);
+}
+
+pub fn is_json_progress() -> bool {
+    JSON_WRITER.lock().unwrap().is_some()
+}
+
+/// Number of warnings which have been logged so far
+pub fn warning_count() -> u64 {
+    WARNINGS.load(Ordering::Relaxed)
+}
+
+/// Number of errors which have been logged so far
+pub fn error_count() -> u64 {
+    ERRORS.load(Ordering::Relaxed)
+}
+
+#[derive(Serialize)]
+struct Event<'a, T: Serialize> {
+    #[serde(rename = "type")]
+    tpe: &'a str,
+    time: DateTime<Local>,
+    #[serde(flatten)]
+    content: T,
+}
+
+// write a single JSON event line; errors are ignored as they must not abort the operation
+fn write_event(tpe: &str, content: impl Serialize) {
+    if let Some(writer) = JSON_WRITER.lock().unwrap().as_mut() {
+        let event = Event {
+            tpe,
+            time: Local::now(),
+            content,
+        };
+        if serde_json::to_writer(&mut *writer, &event).is_ok() {
+            let _ = writeln!(writer);
+            let _ = writer.flush();
+        }
+    }
+}
+
+/// Emit the final summary of a command as JSON event (only if JSON progress is enabled)
+pub fn json_summary(command: &str, summary: impl Serialize) {
+    #[derive(Serialize)]
+    struct Summary<'a, T> {
+        command: &'a str,
+        warnings: u64,
+        errors: u64,
+        summary: T,
+    }
+    write_event(
+        "summary",
+        Summary {
+            command,
+            warnings: warning_count(),
+            errors: error_count(),
+            summary,
+        },
+    );
+}
+
+#[derive(Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProgressKind {
+    Spinner,
+    Counter,
+    Bytes,
+}
+
+/// [`Progress`] reports the progress of an operation either using a progress bar or as JSON events.
+#[derive(Clone)]
+pub enum Progress {
+    Bar(ProgressBar),
+    Json(Arc<JsonProgress>),
+}
+
+impl Progress {
+    pub fn hidden() -> Self {
+        Self::Bar(ProgressBar::hidden())
+    }
+
+    pub fn json(
+        kind: ProgressKind,
+        prefix: impl Into<Cow<'static, str>>,
+        interval: Duration,
+    ) -> Self {
+        let p = JsonProgress {
+            kind,
+            prefix: Mutex::new(prefix.into().to_string()),
+            length: AtomicU64::new(0),
+            pos: AtomicU64::new(0),
+            start: Instant::now(),
+            interval,
+            last_event: Mutex::new(Instant::now()),
+            finished: AtomicBool::new(false),
+        };
+        // progress without prefix gets its prefix set later on
+        if !p.prefix.lock().unwrap().is_empty() {
+            p.emit("progress");
+        }
+        Self::Json(Arc::new(p))
+    }
+
+    pub fn is_hidden(&self) -> bool {
+        match self {
+            Self::Bar(p) => p.is_hidden(),
+            Self::Json(_) => false,
+        }
+    }
+
+    pub fn set_prefix(&self, prefix: impl Into<Cow<'static, str>>) {
+        match self {
+            Self::Bar(p) => p.set_prefix(prefix),
+            Self::Json(p) => {
+                *p.prefix.lock().unwrap() = prefix.into().to_string();
+                p.emit("progress");
+            }
+        }
+    }
+
+    pub fn set_length(&self, len: u64) {
+        match self {
+            Self::Bar(p) => p.set_length(len),
+            Self::Json(p) => p.length.store(len, Ordering::Relaxed),
+        }
+    }
+
+    pub fn inc(&self, delta: u64) {
+        match self {
+            Self::Bar(p) => p.inc(delta),
+            Self::Json(p) => {
+                p.pos.fetch_add(delta, Ordering::Relaxed);
+                p.tick();
+            }
+        }
+    }
+
+    pub fn finish(&self) {
+        match self {
+            Self::Bar(p) => p.finish(),
+            Self::Json(p) => p.finish(),
+        }
+    }
+
+    pub fn finish_with_message(&self, msg: impl Into<Cow<'static, str>>) {
+        match self {
+            Self::Bar(p) => p.finish_with_message(msg),
+            Self::Json(p) => p.finish(),
+        }
+    }
+}
+
+/// Progress state which is reported as JSON events
+pub struct JsonProgress {
+    kind: ProgressKind,
+    prefix: Mutex<String>,
+    length: AtomicU64,
+    pos: AtomicU64,
+    start: Instant,
+    interval: Duration,
+    last_event: Mutex<Instant>,
+    finished: AtomicBool,
+}
+
+#[derive(Serialize)]
+struct ProgressEvent<'a> {
+    message: &'a str,
+    kind: ProgressKind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    done: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    total: Option<u64>,
+    elapsed_secs: f64,
+}
+
+impl JsonProgress {
+    fn emit(&self, tpe: &str) {
+        let (done, total) = match self.kind {
+            ProgressKind::Spinner => (None, None),
+            _ => {
+                let total = self.length.load(Ordering::Relaxed);
+                (
+                    Some(self.pos.load(Ordering::Relaxed)),
+                    (total > 0).then_some(total),
+                )
+            }
+        };
+        write_event(
+            tpe,
+            ProgressEvent {
+                message: &self.prefix.lock().unwrap(),
+                kind: self.kind,
+                done,
+                total,
+                elapsed_secs: self.start.elapsed().as_secs_f64(),
+            },
+        );
+    }
+
+    // emit a progress event if the last one is older than the progress interval
+    fn tick(&self) {
+        let mut last_event = self.last_event.lock().unwrap();
+        if last_event.elapsed() >= self.interval {
+            *last_event = Instant::now();
+            drop(last_event);
+            self.emit("progress");
+        }
+    }
+
+    fn finish(&self) {
+        if !self.finished.swap(true, Ordering::Relaxed) {
+            self.emit("finished");
+        }
+    }
+}
+
+/// Logger which counts warnings and errors and additionally reports them as JSON events if JSON
+/// progress is enabled
+pub struct EventLogger;
+
+impl Log for EventLogger {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        metadata.level() <= Level::Warn
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        #[derive(Serialize)]
+        struct Message {
+            message: String,
+        }
+
+        let tpe = match record.level() {
+            Level::Error => {
+                ERRORS.fetch_add(1, Ordering::Relaxed);
+                "error"
+            }
+            Level::Warn => {
+                WARNINGS.fetch_add(1, Ordering::Relaxed);
+                "warning"
+            }
+            _ => return,
+        };
+        write_event(
+            tpe,
+            Message {
+                message: record.args().to_string(),
+            },
+        );
+    }
+
+    fn flush(&self) {}
+}
+
+impl SharedLogger for EventLogger {
+    fn level(&self) -> LevelFilter {
+        LevelFilter::Warn
+    }
+
+    fn config(&self) -> Option<&Config> {
+        None
+    }
+
+    fn as_log(self: Box<Self>) -> Box<dyn Log> {
+        Box::new(*self)
+    }
+}
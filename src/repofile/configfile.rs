@@ -1,10 +1,13 @@
 use anyhow::{bail, Result};
 use serde::{Deserialize, Serialize};
+use serde_with::base64::Base64;
+use serde_with::serde_as;
 
 use crate::backend::{FileType, RepoFile};
 use crate::blob::BlobType;
 use crate::id::Id;
 
+#[serde_as]
 #[serde_with::apply(Option => #[serde(default, skip_serializing_if = "Option::is_none")])]
 #[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ConfigFile {
@@ -21,6 +24,12 @@ pub struct ConfigFile {
     pub datapack_size_limit: Option<u32>,
     pub min_packsize_tolerate_percent: Option<u32>,
     pub max_packsize_tolerate_percent: Option<u32>,
+    pub use_locking: Option<bool>,
+    pub rekey_key: Option<Id>, // id of the key file containing the new master key during a rekey
+    pub public_key: Option<String>, // public key used to seal the keys of write-only keys (hex)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[serde_as(as = "Vec<Base64>")]
+    pub write_only_keys: Vec<Vec<u8>>, // sealed initial keys of all write-only keys
 }
 
 impl RepoFile for ConfigFile {
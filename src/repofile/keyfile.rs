@@ -1,27 +1,34 @@
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, bail, Result};
+use argon2::{Algorithm, Argon2, Version};
 use chrono::{DateTime, Local};
 use rand::{thread_rng, RngCore};
-use scrypt::Params;
 use serde::{Deserialize, Serialize};
 use serde_with::base64::Base64;
 use serde_with::serde_as;
 
+use super::ConfigFile;
 use crate::backend::{FileType, ReadBackend};
-use crate::crypto::{CryptoKey, Key};
+use crate::crypto::{CryptoKey, Key, WriteOnlyKey};
 use crate::id::Id;
 
 #[serde_as]
 #[serde_with::apply(Option => #[serde(default, skip_serializing_if = "Option::is_none")])]
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct KeyFile {
-    hostname: Option<String>,
-    username: Option<String>,
-    created: Option<DateTime<Local>>,
+    pub hostname: Option<String>,
+    pub username: Option<String>,
+    pub created: Option<DateTime<Local>>,
+    /// whether the key file contains a write-only key; saved unencrypted so key files can be
+    /// distinguished without their password
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub write_only: bool,
     kdf: String,
     #[serde(rename = "N")]
-    n: u32,
-    r: u32,
-    p: u32,
+    n: Option<u32>, // scrypt
+    r: Option<u32>, // scrypt
+    p: Option<u32>, // scrypt and argon2id
+    m: Option<u32>, // argon2id: memory in KiB
+    t: Option<u32>, // argon2id: number of iterations
     #[serde_as(as = "Base64")]
     data: Vec<u8>,
     #[serde_as(as = "Base64")]
@@ -29,63 +36,305 @@ pub struct KeyFile {
 }
 
 impl KeyFile {
+    /// Get the parameters of the key derivation function used by this [`KeyFile`]
+    pub fn kdf_params(&self) -> Result<KdfParams> {
+        let missing = || anyhow!("missing parameters for kdf {}", self.kdf);
+        match self.kdf.as_str() {
+            "scrypt" => Ok(KdfParams::Scrypt {
+                log_n: log_2(self.n.ok_or_else(missing)?),
+                r: self.r.ok_or_else(missing)?,
+                p: self.p.ok_or_else(missing)?,
+            }),
+            "argon2id" => Ok(KdfParams::Argon2id {
+                m_cost: self.m.ok_or_else(missing)?,
+                t_cost: self.t.ok_or_else(missing)?,
+                p_cost: self.p.ok_or_else(missing)?,
+            }),
+            kdf => bail!("unsupported kdf {kdf}"),
+        }
+    }
+
     /// Generate a Key using the key derivation function from [`KeyFile`] and a given password
     fn kdf_key(&self, passwd: &impl AsRef<[u8]>) -> Result<Key> {
-        let params = Params::new(log_2(self.n), self.r, self.p, Params::RECOMMENDED_LEN)
-            .map_err(|_| anyhow!("invalid scrypt parameters"))?;
-
-        let mut key = [0; 64];
-        scrypt::scrypt(passwd.as_ref(), &self.salt, &params, &mut key)
-            .expect("output length invalid?");
-
-        Ok(Key::from_slice(&key))
+        self.kdf_params()?.derive_key(passwd, &self.salt)
     }
 
-    /// Extract a key from the data of the [`KeyFile`] using the given key.
+    /// Extract the content from the data of the [`KeyFile`] using the given key.
     /// The key usually should be the key generated by [`kdf_key()`](Self::kdf_key)
-    fn key_from_data(&self, key: &Key) -> Result<Key> {
+    fn content_from_data(&self, key: Key) -> Result<KeyContent> {
         let dec_data = key
             .decrypt_data(&self.data)
             .map_err(|_| anyhow!("decryption failed"))?;
-        serde_json::from_slice::<MasterKey>(&dec_data)?.key()
+        Ok(match serde_json::from_slice(&dec_data)? {
+            KeyData::Master(masterkey) => KeyContent::Master(masterkey.key()?),
+            KeyData::WriteOnly(data) => KeyContent::WriteOnly(Box::new(WriteOnlyKeyFile {
+                file: self.clone(),
+                kdf_key: key,
+                key: WriteOnlyKey {
+                    writer: data.writer,
+                    counter: data.counter,
+                    key: data.key.key()?,
+                },
+                cache_key: data.cache_key.key()?,
+                config: data.config,
+            })),
+        })
     }
 
-    /// Extract a key from the data of the [`KeyFile`] using the key
+    /// Extract the content from the data of the [`KeyFile`] using the key
+    /// from the derivation function in combination with the given password.
+    pub fn content_from_password(&self, passwd: &impl AsRef<[u8]>) -> Result<KeyContent> {
+        self.content_from_data(self.kdf_key(passwd)?)
+    }
+
+    /// Extract the master key from the data of the [`KeyFile`] using the key
     /// from the derivation function in combination with the given password.
     pub fn key_from_password(&self, passwd: &impl AsRef<[u8]>) -> Result<Key> {
-        self.key_from_data(&self.kdf_key(passwd)?)
+        match self.content_from_password(passwd)? {
+            KeyContent::Master(key) => Ok(key),
+            KeyContent::WriteOnly(_) => Err(anyhow!("key is a write-only key")),
+        }
     }
 
     /// Generate a new [`KeyFile`] from a given key and password.
     pub fn generate(
         key: Key,
         passwd: &impl AsRef<[u8]>,
+        kdf: KdfParams,
+        hostname: Option<String>,
+        username: Option<String>,
+        with_created: bool,
+    ) -> Result<Self> {
+        let data = KeyData::Master(MasterKey::from_key(key));
+        Self::generate_with_data(&data, passwd, kdf, hostname, username, with_created)
+    }
+
+    /// Generate a new [`KeyFile`] for a write-only key.
+    /// The config is saved in the key file as a write-only key cannot read the repository config.
+    /// The key file also contains a newly generated key for the cache of the write-only key.
+    pub fn generate_write_only(
+        key: &WriteOnlyKey,
+        config: ConfigFile,
+        passwd: &impl AsRef<[u8]>,
+        kdf: KdfParams,
+        hostname: Option<String>,
+        username: Option<String>,
+        with_created: bool,
+    ) -> Result<Self> {
+        let data = KeyData::WriteOnly(Box::new(WriteOnlyData::new(key, Key::new(), config)));
+        let mut keyfile =
+            Self::generate_with_data(&data, passwd, kdf, hostname, username, with_created)?;
+        keyfile.write_only = true;
+        Ok(keyfile)
+    }
+
+    fn generate_with_data(
+        data: &KeyData,
+        passwd: &impl AsRef<[u8]>,
+        kdf: KdfParams,
         hostname: Option<String>,
         username: Option<String>,
         with_created: bool,
     ) -> Result<Self> {
-        let masterkey = MasterKey::from_key(key);
-        let params = Params::recommended();
         let mut salt = vec![0; 64];
         thread_rng().fill_bytes(&mut salt);
 
-        let mut key = [0; 64];
-        scrypt::scrypt(passwd.as_ref(), &salt, &params, &mut key).expect("output length invalid?");
-
-        let key = Key::from_slice(&key);
-        let data = key.encrypt_data(&serde_json::to_vec(&masterkey)?)?;
+        let key = kdf.derive_key(passwd, &salt)?;
+        let data = key.encrypt_data(&serde_json::to_vec(data)?)?;
 
-        Ok(Self {
+        let mut keyfile = Self {
             hostname,
             username,
-            kdf: "scrypt".to_string(),
-            n: 2_u32.pow(u32::from(params.log_n())),
-            r: params.r(),
-            p: params.p(),
+            write_only: false,
+            kdf: String::new(),
+            n: None,
+            r: None,
+            p: None,
+            m: None,
+            t: None,
             created: with_created.then(Local::now),
             data,
             salt,
-        })
+        };
+        match kdf {
+            KdfParams::Scrypt { log_n, r, p } => {
+                keyfile.kdf = "scrypt".to_string();
+                keyfile.n = Some(2_u32.pow(u32::from(log_n)));
+                keyfile.r = Some(r);
+                keyfile.p = Some(p);
+            }
+            KdfParams::Argon2id {
+                m_cost,
+                t_cost,
+                p_cost,
+            } => {
+                keyfile.kdf = "argon2id".to_string();
+                keyfile.m = Some(m_cost);
+                keyfile.t = Some(t_cost);
+                keyfile.p = Some(p_cost);
+            }
+        }
+        Ok(keyfile)
+    }
+}
+
+/// Parameters of the key derivation function used to derive the key which encrypts a [`KeyFile`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KdfParams {
+    Scrypt {
+        log_n: u8,
+        r: u32,
+        p: u32,
+    },
+    Argon2id {
+        m_cost: u32,
+        t_cost: u32,
+        p_cost: u32,
+    },
+}
+
+impl Default for KdfParams {
+    fn default() -> Self {
+        let params = scrypt::Params::recommended();
+        Self::Scrypt {
+            log_n: params.log_n(),
+            r: params.r(),
+            p: params.p(),
+        }
+    }
+}
+
+impl KdfParams {
+    /// Parameters for the given kdf, memory (in bytes) and iterations. Values which are not given
+    /// are taken from `self` if it uses the same kdf, else the defaults of the kdf are used.
+    pub fn with(
+        self,
+        kdf: Option<&str>,
+        memory: Option<u64>,
+        iterations: Option<u32>,
+    ) -> Result<Self> {
+        let mut params = match (kdf, self) {
+            (None | Some("scrypt"), Self::Scrypt { .. })
+            | (None | Some("argon2id"), Self::Argon2id { .. }) => self,
+            (Some("scrypt"), _) => Self::default(),
+            (Some("argon2id"), _) => {
+                let params = argon2::Params::default();
+                Self::Argon2id {
+                    m_cost: params.m_cost(),
+                    t_cost: params.t_cost(),
+                    p_cost: params.p_cost(),
+                }
+            }
+            (Some(kdf), _) => bail!("unsupported kdf {kdf}"),
+        };
+
+        match &mut params {
+            Self::Scrypt { log_n, r, p } => {
+                if let Some(memory) = memory {
+                    // scrypt uses 128 * r * N bytes of memory
+                    let n = (memory / (128 * u64::from(*r))).max(2);
+                    *log_n = (63 - n.leading_zeros()) as u8;
+                }
+                if let Some(iterations) = iterations {
+                    *p = iterations;
+                }
+                scrypt::Params::new(*log_n, *r, *p, scrypt::Params::RECOMMENDED_LEN)
+                    .map_err(|_| anyhow!("invalid scrypt parameters"))?;
+            }
+            Self::Argon2id { m_cost, t_cost, .. } => {
+                if let Some(memory) = memory {
+                    *m_cost = (memory / 1024).try_into()?;
+                }
+                if let Some(iterations) = iterations {
+                    *t_cost = iterations;
+                }
+            }
+        }
+        if let Self::Argon2id { .. } = params {
+            params.argon2()?;
+        }
+        Ok(params)
+    }
+
+    fn argon2(&self) -> Result<Argon2<'static>> {
+        let Self::Argon2id {
+            m_cost,
+            t_cost,
+            p_cost,
+        } = *self
+        else {
+            bail!("not an argon2 kdf")
+        };
+        let params = argon2::Params::new(m_cost, t_cost, p_cost, Some(64))
+            .map_err(|err| anyhow!("invalid argon2 parameters: {err}"))?;
+        Ok(Argon2::new(Algorithm::Argon2id, Version::V0x13, params))
+    }
+
+    /// Derive a key from the password and salt
+    pub fn derive_key(&self, passwd: &impl AsRef<[u8]>, salt: &[u8]) -> Result<Key> {
+        let mut key = [0; 64];
+        match *self {
+            Self::Scrypt { log_n, r, p } => {
+                let params = scrypt::Params::new(log_n, r, p, scrypt::Params::RECOMMENDED_LEN)
+                    .map_err(|_| anyhow!("invalid scrypt parameters"))?;
+                scrypt::scrypt(passwd.as_ref(), salt, &params, &mut key)
+                    .expect("output length invalid?");
+            }
+            Self::Argon2id { .. } => {
+                self.argon2()?
+                    .hash_password_into(passwd.as_ref(), salt, &mut key)
+                    .map_err(|err| anyhow!("argon2 error: {err}"))?;
+            }
+        }
+        Ok(Key::from_slice(&key))
+    }
+}
+
+/// Content of a [`KeyFile`]
+pub enum KeyContent {
+    Master(Key),
+    WriteOnly(Box<WriteOnlyKeyFile>),
+}
+
+/// A write-only key read from a [`KeyFile`]
+pub struct WriteOnlyKeyFile {
+    file: KeyFile,
+    kdf_key: Key,
+    pub key: WriteOnlyKey,
+    /// the key of the cache of the write-only key; unlike the key of the writer, it is not replaced
+    pub cache_key: Key,
+    pub config: ConfigFile,
+}
+
+impl WriteOnlyKeyFile {
+    /// Return the [`KeyFile`] which must replace the old one. It contains the next derived key, so
+    /// the key used in this session cannot be read from it.
+    /// The password is not needed again as the key file is encrypted using the same derived key.
+    pub fn next(&self) -> Result<KeyFile> {
+        let data = KeyData::WriteOnly(Box::new(WriteOnlyData::new(
+            &self.key.next(),
+            self.cache_key.clone(),
+            self.config.clone(),
+        )));
+        let mut file = self.file.clone();
+        file.data = self.kdf_key.encrypt_data(&serde_json::to_vec(&data)?)?;
+        Ok(file)
+    }
+
+    /// Check that the given [`KeyFile`] can be read using the same password and contains the next
+    /// key as returned by [`next()`](Self::next).
+    pub fn verify_next(&self, file: &KeyFile) -> Result<()> {
+        let next = self.key.next();
+        match file.content_from_data(self.kdf_key.clone())? {
+            KeyContent::WriteOnly(file)
+                if file.key.writer == next.writer
+                    && file.key.counter == next.counter
+                    && file.key.key.as_bytes() == next.key.as_bytes() =>
+            {
+                Ok(())
+            }
+            _ => bail!("key file does not contain the next write-only key"),
+        }
     }
 }
 
@@ -123,6 +372,34 @@ struct MasterKey {
     encrypt: Vec<u8>,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+struct WriteOnlyData {
+    writer: u32,
+    counter: u32,
+    key: MasterKey,
+    cache_key: MasterKey,
+    config: ConfigFile,
+}
+
+impl WriteOnlyData {
+    fn new(key: &WriteOnlyKey, cache_key: Key, config: ConfigFile) -> Self {
+        Self {
+            writer: key.writer,
+            counter: key.counter,
+            key: MasterKey::from_key(key.key.clone()),
+            cache_key: MasterKey::from_key(cache_key),
+            config,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+enum KeyData {
+    Master(MasterKey),
+    WriteOnly(Box<WriteOnlyData>),
+}
+
 impl MasterKey {
     fn from_key(key: Key) -> Self {
         let (encrypt, k, r) = key.to_keys();
@@ -141,23 +418,127 @@ fn key_from_backend<B: ReadBackend>(be: &B, id: &Id, passwd: &impl AsRef<[u8]>)
     KeyFile::from_backend(be, id)?.key_from_password(passwd)
 }
 
-/// Find a [`KeyFile`] in the backend that fits to the given password and return the contained key.
+fn content_from_backend<B: ReadBackend>(
+    be: &B,
+    id: &Id,
+    passwd: &impl AsRef<[u8]>,
+) -> Result<KeyContent> {
+    KeyFile::from_backend(be, id)?.content_from_password(passwd)
+}
+
+/// Find a [`KeyFile`] in the backend that fits to the given password and return its id together
+/// with the contained key.
 /// If a key hint is given, only this key is tested.
 /// This is recommended for a large number of keys.
 pub fn find_key_in_backend<B: ReadBackend>(
     be: &B,
     passwd: &impl AsRef<[u8]>,
     hint: Option<&Id>,
-) -> Result<Key> {
+) -> Result<(Id, Key)> {
     match hint {
-        Some(id) => key_from_backend(be, id, passwd),
+        Some(id) => Ok((*id, key_from_backend(be, id, passwd)?)),
         None => {
-            for id in be.list(FileType::Key)? {
-                if let Ok(key) = key_from_backend(be, &id, passwd) {
-                    return Ok(key);
-                }
+            let (id, content) = find_key_in_backend_with(be, passwd, |content| {
+                matches!(content, KeyContent::Master(_))
+            })?;
+            match content {
+                KeyContent::Master(key) => Ok((id, key)),
+                KeyContent::WriteOnly(_) => unreachable!(),
+            }
+        }
+    }
+}
+
+/// Find a [`KeyFile`] in the backend that fits to the given password and contains a key which is
+/// accepted by `check`.
+/// This allows to choose between keys with the same password but different master keys, like during a rekey.
+pub fn find_key_in_backend_with<B: ReadBackend>(
+    be: &B,
+    passwd: &impl AsRef<[u8]>,
+    check: impl Fn(&KeyContent) -> bool,
+) -> Result<(Id, KeyContent)> {
+    for id in be.list(FileType::Key)? {
+        if let Ok(content) = content_from_backend(be, &id, passwd) {
+            if check(&content) {
+                return Ok((id, content));
             }
-            Err(anyhow!("no suitable key found!"))
         }
     }
+    Err(anyhow!("no suitable key found!"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kdf_params() {
+        let params = KdfParams::default()
+            .with(None, Some(64 * 1024 * 1024), Some(2))
+            .unwrap();
+        assert_eq!(
+            params,
+            KdfParams::Scrypt {
+                log_n: 16,
+                r: 8,
+                p: 2
+            }
+        );
+        let params = params.with(Some("argon2id"), None, Some(3)).unwrap();
+        assert_eq!(
+            params,
+            KdfParams::Argon2id {
+                m_cost: 19 * 1024,
+                t_cost: 3,
+                p_cost: 1
+            }
+        );
+        assert!(params.with(Some("pbkdf2"), None, None).is_err());
+    }
+
+    #[test]
+    fn argon2_key_file() {
+        let key = Key::new();
+        let kdf = KdfParams::default()
+            .with(Some("argon2id"), Some(64 * 1024), Some(1))
+            .unwrap();
+        let keyfile = KeyFile::generate(key.clone(), &"secret", kdf, None, None, false).unwrap();
+        let keyfile: KeyFile =
+            serde_json::from_slice(&serde_json::to_vec(&keyfile).unwrap()).unwrap();
+        assert_eq!(keyfile.kdf_params().unwrap(), kdf);
+        assert!(!keyfile.write_only);
+        let read_key = keyfile.key_from_password(&"secret").unwrap();
+        assert_eq!(read_key.as_bytes(), key.as_bytes());
+        assert!(keyfile.key_from_password(&"wrong").is_err());
+    }
+
+    #[test]
+    fn write_only_key_file_next() {
+        let kdf = KdfParams::default()
+            .with(Some("argon2id"), Some(64 * 1024), Some(1))
+            .unwrap();
+        let writer = WriteOnlyKey::new();
+        let config = ConfigFile::new(2, Id::random(), 0);
+        let keyfile =
+            KeyFile::generate_write_only(&writer, config, &"secret", kdf, None, None, false)
+                .unwrap();
+        assert!(keyfile.write_only);
+        let KeyContent::WriteOnly(file) = keyfile.content_from_password(&"secret").unwrap() else {
+            panic!("expected a write-only key");
+        };
+        let data = file.key.encrypt_data(b"Hello!").unwrap();
+
+        let next_file = file.next().unwrap();
+        assert!(next_file.write_only);
+        file.verify_next(&next_file).unwrap();
+        assert!(file.verify_next(&keyfile).is_err());
+        let KeyContent::WriteOnly(next) = next_file.content_from_password(&"secret").unwrap()
+        else {
+            panic!("expected a write-only key");
+        };
+        // data saved in this session cannot be read using the next key file
+        assert_eq!(next.key.counter, file.key.counter + 1);
+        assert!(next.key.decrypt_data(&data).is_err());
+        assert_eq!(next.cache_key.as_bytes(), file.cache_key.as_bytes());
+    }
 }
@@ -0,0 +1,101 @@
+use std::fmt;
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Duration, Local};
+use gethostname::gethostname;
+#[cfg(not(windows))]
+use nix::{errno::Errno, sys::signal::kill, unistd::Pid};
+use serde::{Deserialize, Serialize};
+
+use crate::backend::{FileType, RepoFile};
+
+/// Locks older than this many minutes are considered stale (same value as restic uses)
+pub const STALE_TIMEOUT_MINUTES: i64 = 30;
+
+/// [`LockFile`] is the repository file saved in `locks/`. Its format is compatible with restic.
+#[serde_with::apply(Option => #[serde(default, skip_serializing_if = "Option::is_none")])]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LockFile {
+    pub time: DateTime<Local>,
+    #[serde(default)]
+    pub exclusive: bool,
+    #[serde(default)]
+    pub hostname: String,
+    #[serde(default)]
+    pub username: String,
+    #[serde(default)]
+    pub pid: u32,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+}
+
+impl RepoFile for LockFile {
+    const TYPE: FileType = FileType::Lock;
+}
+
+impl LockFile {
+    pub fn new(exclusive: bool) -> Result<Self> {
+        let hostname = gethostname();
+        let hostname = hostname
+            .to_str()
+            .ok_or_else(|| anyhow!("non-unicode hostname {:?}", hostname))?
+            .to_string();
+
+        #[cfg(not(windows))]
+        let (username, uid, gid) = (
+            users::get_current_username()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            Some(users::get_current_uid()),
+            Some(users::get_current_gid()),
+        );
+        #[cfg(windows)]
+        let (username, uid, gid) = (std::env::var("USERNAME").unwrap_or_default(), None, None);
+
+        Ok(Self {
+            time: Local::now(),
+            exclusive,
+            hostname,
+            username,
+            pid: std::process::id(),
+            uid,
+            gid,
+        })
+    }
+
+    /// A lock is stale if it hasn't been refreshed for [`STALE_TIMEOUT_MINUTES`] or if it was created on
+    /// this host by a process which doesn't exist anymore.
+    pub fn is_stale(&self) -> bool {
+        if Local::now() - self.time > Duration::minutes(STALE_TIMEOUT_MINUTES) {
+            return true;
+        }
+
+        #[cfg(not(windows))]
+        if gethostname().to_str() == Some(&self.hostname) {
+            if let Ok(pid) = i32::try_from(self.pid) {
+                // EPERM means that the process exists, but belongs to another user
+                return !matches!(kill(Pid::from_raw(pid), None), Ok(()) | Err(Errno::EPERM));
+            }
+        }
+
+        false
+    }
+}
+
+impl fmt::Display for LockFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} lock by {}@{} (PID {}) from {}",
+            if self.exclusive {
+                "exclusive"
+            } else {
+                "non-exclusive"
+            },
+            self.username,
+            self.hostname,
+            self.pid,
+            self.time.format("%Y-%m-%d %H:%M:%S")
+        )
+    }
+}
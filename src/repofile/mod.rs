@@ -1,6 +1,7 @@
 mod configfile;
 mod indexfile;
 mod keyfile;
+mod lockfile;
 mod packfile;
 mod snapshotfile;
 
@@ -8,5 +9,6 @@ pub use super::id::*;
 pub use configfile::*;
 pub use indexfile::*;
 pub use keyfile::*;
+pub use lockfile::*;
 pub use packfile::*;
 pub use snapshotfile::*;
@@ -4,12 +4,11 @@ use std::str::FromStr;
 use std::{cmp::Ordering, fmt::Display};
 
 use anyhow::{anyhow, bail, Result};
-use chrono::{DateTime, Duration, Local};
+use chrono::{DateTime, Duration, Local, NaiveDate, NaiveDateTime, TimeZone, Utc};
 use clap::{AppSettings, Parser};
 use derivative::Derivative;
 use dunce::canonicalize;
 use gethostname::gethostname;
-use indicatif::ProgressBar;
 use itertools::Itertools;
 use log::*;
 use merge::Merge;
@@ -21,6 +20,7 @@ use serde_with::{serde_as, DisplayFromStr};
 
 use super::Id;
 use crate::backend::{DecryptReadBackend, FileType, RepoFile};
+use crate::progress::Progress;
 
 #[serde_as]
 #[derive(Clone, Default, Parser, Deserialize, Merge)]
@@ -217,23 +217,88 @@ impl SnapshotFile {
         Ok(Self::set_id((*id, be.get_file(id)?)))
     }
 
+    /// Get a [`SnapshotFile`] from the backend by a snapshot reference. Besides (part of the) id,
+    /// the following references are allowed:
+    /// - `latest`: the latest snapshot matching the predicate
+    /// - `latest~N`: the N-th snapshot before the latest snapshot
+    /// - `@TIME`: the latest snapshot taken at or before TIME (e.g. `@2023-05-01T12:00` or `@7d`)
+    ///
+    /// If `group_by` is given, the reference is resolved within a single group: the group of the
+    /// latest matching snapshot of this host (when grouping by host) or, if there is none, of the
+    /// latest matching snapshot.
     pub fn from_str<B: DecryptReadBackend>(
         be: &B,
         string: &str,
         predicate: impl FnMut(&Self) -> bool + Send + Sync,
-        p: ProgressBar,
+        group_by: Option<&SnapshotGroupCriterion>,
+        p: Progress,
     ) -> Result<Self> {
-        match string {
-            "latest" => Self::latest(be, predicate, p),
-            _ => Self::from_id(be, string),
+        let (time, skip) = match string {
+            "latest" => (None, 0),
+            _ => match (string.strip_prefix("latest~"), string.strip_prefix('@')) {
+                (Some(n), _) => (
+                    None,
+                    n.parse()
+                        .map_err(|_| anyhow!("invalid snapshot reference {string}"))?,
+                ),
+                (_, Some(time)) => (Some(SnapshotTime::from_str(time)?.0), 0),
+                _ => return Self::from_id(be, string),
+            },
+        };
+
+        let mut pred = predicate;
+        let mut snaps = Vec::new();
+        p.set_prefix("getting snapshots...");
+        for snap in be.stream_all::<SnapshotFile>(p.clone())? {
+            let snap = Self::set_id(snap?);
+            if pred(&snap) && !matches!(time, Some(time) if snap.time > time) {
+                snaps.push(snap);
+            }
         }
+        p.finish();
+
+        Self::select(
+            snaps,
+            string,
+            skip,
+            group_by,
+            &gethostname().to_string_lossy(),
+        )
+    }
+
+    // select the `skip`-th snapshot before the latest one from the given snapshots; if `group_by`
+    // is given, only snapshots of the group of the latest snapshot are considered
+    fn select(
+        mut snaps: Vec<Self>,
+        string: &str,
+        skip: usize,
+        group_by: Option<&SnapshotGroupCriterion>,
+        hostname: &str,
+    ) -> Result<Self> {
+        snaps.sort_unstable_by(|sn1, sn2| sn2.cmp(sn1));
+        if let Some(crit) = group_by {
+            // prefer snapshots of this host like backup does when choosing the parent
+            if crit.hostname && snaps.iter().any(|sn| sn.hostname == hostname) {
+                snaps.retain(|sn| sn.hostname == hostname);
+            }
+            if let Some(latest) = snaps.first() {
+                let group = SnapshotGroup::from_sn(latest, crit);
+                snaps.retain(|sn| sn.has_group(&group));
+            }
+        }
+
+        let count = snaps.len();
+        snaps.into_iter().nth(skip).ok_or_else(|| match count {
+            0 => anyhow!("no snapshots found"),
+            _ => anyhow!("{string} not found, only {count} snapshot(s) match"),
+        })
     }
 
     /// Get the latest [`SnapshotFile`] from the backend
     pub fn latest<B: DecryptReadBackend>(
         be: &B,
         predicate: impl FnMut(&Self) -> bool + Send + Sync,
-        p: ProgressBar,
+        p: Progress,
     ) -> Result<Self> {
         p.set_prefix("getting latest snapshot...");
         let mut latest: Option<Self> = None;
@@ -267,7 +332,7 @@ impl SnapshotFile {
     /// Get a Vector of [`SnapshotFile`] from the backend by list of (parts of the) ids
     pub fn from_ids<B: DecryptReadBackend>(be: &B, ids: &[String]) -> Result<Vec<Self>> {
         let ids = be.find_ids(FileType::Snapshot, ids)?;
-        be.stream_list::<Self>(ids, ProgressBar::hidden())?
+        be.stream_list::<Self>(ids, Progress::hidden())?
             .into_iter()
             .map_ok(Self::set_id)
             .try_collect()
@@ -333,7 +398,7 @@ impl SnapshotFile {
         be: &B,
         filter: &SnapshotFilter,
     ) -> Result<Vec<Self>> {
-        be.stream_all::<SnapshotFile>(ProgressBar::hidden())?
+        be.stream_all::<SnapshotFile>(Progress::hidden())?
             .into_iter()
             .map_ok(Self::set_id)
             .filter_ok(|sn| sn.matches(filter))
@@ -356,10 +421,42 @@ impl SnapshotFile {
 
         self.paths.matches(&filter.filter_paths)
             && self.tags.matches(&filter.filter_tags)
+            && self.matches_time(filter)
             && (filter.filter_host.is_empty() || filter.filter_host.contains(&self.hostname))
             && (filter.filter_label.is_empty() || filter.filter_label.contains(&self.label))
     }
 
+    fn matches_time(&self, filter: &SnapshotFilter) -> bool {
+        let now = Local::now();
+        // a duration reaching before the earliest representable time means that earliest time, so
+        // that no snapshot is older and all snapshots are newer than this duration
+        let ago = |d: &humantime::Duration| {
+            time_ago(now, **d).unwrap_or_else(|_| DateTime::<Utc>::MIN_UTC.with_timezone(&Local))
+        };
+
+        if let Some(time) = filter.filter_after {
+            if self.time < time.0 {
+                return false;
+            }
+        }
+        if let Some(time) = filter.filter_before {
+            if self.time > time.0 {
+                return false;
+            }
+        }
+        if let Some(d) = &filter.filter_older_than {
+            if self.time > ago(d) {
+                return false;
+            }
+        }
+        if let Some(d) = &filter.filter_newer_than {
+            if self.time < ago(d) {
+                return false;
+            }
+        }
+        true
+    }
+
     /// Add tag lists to snapshot. return whether snapshot was changed
     pub fn add_tags(&mut self, tag_lists: Vec<StringList>) -> bool {
         let old_tags = self.tags.clone();
@@ -464,12 +561,111 @@ pub struct SnapshotFilter {
     #[merge(strategy=merge::vec::overwrite_empty)]
     filter_tags: Vec<StringList>,
 
+    /// Only use snapshots taken at or after the given time (e.g. "2023-05-01 12:00" or "7d" for 7 days ago)
+    #[clap(long, value_name = "TIME")]
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    filter_after: Option<SnapshotTime>,
+
+    /// Only use snapshots taken at or before the given time (e.g. "2023-05-01 12:00" or "7d" for 7 days ago)
+    #[clap(long, value_name = "TIME")]
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    filter_before: Option<SnapshotTime>,
+
+    /// Only use snapshots older than the given duration (e.g. 90d)
+    #[clap(long, value_name = "DURATION")]
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    filter_older_than: Option<humantime::Duration>,
+
+    /// Only use snapshots newer than the given duration (e.g. 90d)
+    #[clap(long, value_name = "DURATION")]
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    filter_newer_than: Option<humantime::Duration>,
+
     /// Function to filter snapshots
     #[clap(long, value_name = "FUNC")]
     #[serde_as(as = "Option<DisplayFromStr>")]
     filter_fn: Option<SnapshotFn>,
 }
 
+/// Grouping used when resolving snapshot references like `latest`
+#[derive(Default, Parser)]
+pub struct SnapshotRefGroup {
+    /// Group snapshots by any combination of host,label,paths,tags when resolving latest,
+    /// latest~N or @TIME within the group of the latest snapshot (preferring this host)
+    #[clap(long, short = 'g', value_name = "CRITERION")]
+    group_by: Option<SnapshotGroupCriterion>,
+}
+
+impl SnapshotRefGroup {
+    pub fn criterion(&self) -> Option<&SnapshotGroupCriterion> {
+        self.group_by.as_ref()
+    }
+}
+
+/// A point in time, given either as (local) date and time or as duration before now
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SnapshotTime(pub DateTime<Local>);
+
+impl FromStr for SnapshotTime {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self> {
+        if let Ok(d) = humantime::Duration::from_str(s) {
+            return Ok(Self(time_ago(Local::now(), *d)?));
+        }
+        Ok(Self(parse_time(s)?))
+    }
+}
+
+/// Get the time the duration `d` before `now`; fails if this is before the earliest representable time
+fn time_ago(now: DateTime<Local>, d: std::time::Duration) -> Result<DateTime<Local>> {
+    Duration::from_std(d)
+        .ok()
+        .and_then(|d| now.checked_sub_signed(d))
+        .ok_or_else(|| anyhow!("duration {} is too large", humantime::format_duration(d)))
+}
+
+/// Split an argument of the form `SNAPSHOT[:PATH]` into snapshot reference and path.
+/// References of the form `@TIME` may themselves contain `:`.
+pub fn split_snapshot_path(arg: &str) -> Option<(&str, &str)> {
+    match arg.strip_prefix('@') {
+        Some(time) if SnapshotTime::from_str(time).is_ok() => None,
+        // use the longest prefix which is a valid time
+        Some(_) => arg
+            .match_indices(':')
+            .map(|(i, _)| i)
+            .rev()
+            .find(|&i| SnapshotTime::from_str(&arg[1..i]).is_ok())
+            .map(|i| (&arg[..i], &arg[i + 1..])),
+        None => arg.split_once(':'),
+    }
+}
+
+/// Parse a date and time given in RFC 3339 format or as local time like "2023-05-01 12:00:00".
+/// Seconds and the time may be omitted.
+pub fn parse_time(s: &str) -> Result<DateTime<Local>> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(s) {
+        return Ok(time.with_timezone(&Local));
+    }
+    let naive = [
+        "%Y-%m-%d %H:%M:%S",
+        "%Y-%m-%dT%H:%M:%S",
+        "%Y-%m-%d %H:%M",
+        "%Y-%m-%dT%H:%M",
+    ]
+    .iter()
+    .find_map(|fmt| NaiveDateTime::parse_from_str(s, fmt).ok())
+    .or_else(|| {
+        NaiveDate::parse_from_str(s, "%Y-%m-%d")
+            .ok()
+            .and_then(|date| date.and_hms_opt(0, 0, 0))
+    })
+    .ok_or_else(|| anyhow!("invalid time {s}, use e.g. \"2023-05-01 12:00:00\" or a duration"))?;
+    Local
+        .from_local_datetime(&naive)
+        .earliest()
+        .ok_or_else(|| anyhow!("time {s} does not exist in local time zone"))
+}
+
 #[derive(Clone, Default, Deserialize)]
 pub struct SnapshotGroupCriterion {
     hostname: bool,
@@ -540,6 +736,19 @@ impl SnapshotGroup {
     pub fn is_empty(&self) -> bool {
         self == &Self::default()
     }
+
+    /// Returns all grouping keys together with their values; keys not used for grouping have empty values
+    pub fn key_values(&self) -> [(&'static str, String); 4] {
+        fn value(v: &Option<impl ToString>) -> String {
+            v.as_ref().map(ToString::to_string).unwrap_or_default()
+        }
+        [
+            ("hostname", value(&self.hostname)),
+            ("label", value(&self.label)),
+            ("paths", value(&self.paths)),
+            ("tags", value(&self.tags)),
+        ]
+    }
 }
 
 #[derive(Default, Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Serialize, Deserialize)]
@@ -697,3 +906,115 @@ impl PathList {
         });
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Datelike;
+
+    #[test]
+    fn parse_snapshot_time() {
+        let time = Local.with_ymd_and_hms(2023, 5, 1, 12, 0, 0).unwrap();
+        for s in [
+            "2023-05-01 12:00:00",
+            "2023-05-01T12:00",
+            "2023-05-01 12:00",
+        ] {
+            assert_eq!(SnapshotTime::from_str(s).unwrap().0, time);
+        }
+        let date = Local.with_ymd_and_hms(2023, 5, 1, 0, 0, 0).unwrap();
+        assert_eq!(SnapshotTime::from_str("2023-05-01").unwrap().0, date);
+        let rfc3339 = SnapshotTime::from_str("2023-05-01T12:00:00Z").unwrap().0;
+        assert_eq!(
+            rfc3339,
+            DateTime::parse_from_rfc3339("2023-05-01T12:00:00+00:00").unwrap()
+        );
+
+        let before = Local::now() - Duration::days(7);
+        let relative = SnapshotTime::from_str("7days").unwrap().0;
+        assert!(relative >= before && relative <= Local::now() - Duration::days(7));
+
+        assert!(SnapshotTime::from_str("yesterday").is_err());
+        assert!(SnapshotTime::from_str("300000years").is_err());
+    }
+
+    #[test]
+    fn select_in_group() {
+        let snap = |hostname: &str, paths: &str, day| SnapshotFile {
+            hostname: hostname.to_string(),
+            paths: StringList(vec![paths.to_string()]),
+            time: Local.with_ymd_and_hms(2023, 5, day, 12, 0, 0).unwrap(),
+            ..Default::default()
+        };
+        let snaps = vec![
+            snap("host", "/a", 1),
+            snap("host", "/b", 2),
+            snap("host", "/a", 3),
+            snap("other", "/a", 4),
+            snap("host", "/b", 5),
+        ];
+        let day = |sn: Result<SnapshotFile>| sn.unwrap().time.day();
+        let select = |skip, crit: Option<&str>| {
+            let crit = crit.map(|c| SnapshotGroupCriterion::from_str(c).unwrap());
+            SnapshotFile::select(snaps.clone(), "latest", skip, crit.as_ref(), "host")
+        };
+
+        assert_eq!(day(select(1, None)), 4);
+        // the group of the latest snapshot of this host
+        assert_eq!(day(select(0, Some("host"))), 5);
+        assert_eq!(day(select(1, Some("host"))), 3);
+        assert_eq!(day(select(1, Some("host,paths"))), 2);
+        assert!(select(2, Some("host,paths")).is_err());
+        // without grouping by host, the group of the latest snapshot is used
+        assert_eq!(day(select(1, Some("paths"))), 2);
+        assert!(select(2, Some("paths")).is_err());
+        // the group of the latest snapshot is also used if there is no snapshot of this host
+        let crit = SnapshotGroupCriterion::from_str("host").unwrap();
+        let snaps = vec![snap("other", "/a", 1), snap("other2", "/a", 2)];
+        assert_eq!(
+            day(SnapshotFile::select(
+                snaps,
+                "latest",
+                0,
+                Some(&crit),
+                "host"
+            )),
+            2
+        );
+    }
+
+    #[test]
+    fn matches_time_overflow() {
+        let snap = SnapshotFile::default();
+        let filter = |older: Option<&str>, newer: Option<&str>| SnapshotFilter {
+            filter_older_than: older.map(|d| d.parse().unwrap()),
+            filter_newer_than: newer.map(|d| d.parse().unwrap()),
+            ..Default::default()
+        };
+        assert!(!snap.matches(&filter(Some("300000years"), None)));
+        assert!(snap.matches(&filter(None, Some("300000years"))));
+        assert!(!snap.matches(&filter(Some("1000000000years"), None)));
+        assert!(snap.matches(&filter(None, Some("1000000000years"))));
+        assert!(!snap.matches(&filter(Some("1day"), None)));
+        assert!(snap.matches(&filter(None, Some("1day"))));
+    }
+
+    #[test]
+    fn split_snapshot_and_path() {
+        assert_eq!(split_snapshot_path("latest"), None);
+        assert_eq!(
+            split_snapshot_path("latest~2:dir"),
+            Some(("latest~2", "dir"))
+        );
+        assert_eq!(
+            split_snapshot_path("12345678:a:b"),
+            Some(("12345678", "a:b"))
+        );
+        assert_eq!(split_snapshot_path("@2023-05-01T12:00"), None);
+        assert_eq!(
+            split_snapshot_path("@2023-05-01T12:00:00:dir/a:b"),
+            Some(("@2023-05-01T12:00:00", "dir/a:b"))
+        );
+        assert_eq!(split_snapshot_path("@7d:dir"), Some(("@7d", "dir")));
+    }
+}
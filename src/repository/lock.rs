@@ -0,0 +1,134 @@
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use log::*;
+
+use crate::backend::{DecryptFullBackend, FileType};
+use crate::id::Id;
+use crate::repofile::LockFile;
+
+/// Interval in which the lock file is refreshed (same value as restic uses)
+const REFRESH_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// [`RepositoryLock`] holds a lock file in the repository.
+///
+/// The lock file is regularly refreshed in a background thread and removed when the
+/// [`RepositoryLock`] is dropped.
+pub struct RepositoryLock {
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl RepositoryLock {
+    pub fn new(be: impl DecryptFullBackend, exclusive: bool) -> Result<Self> {
+        check_locks(&be, exclusive, None)?;
+        let id = be.save_file(&LockFile::new(exclusive)?)?;
+        // check again to detect locks which have been created concurrently
+        if let Err(err) = check_locks(&be, exclusive, Some(id)) {
+            be.remove(FileType::Lock, &id, false)?;
+            return Err(err);
+        }
+        debug!("created lock {id}");
+
+        let (stop, receiver) = channel();
+        let thread = thread::spawn(move || {
+            let mut id = id;
+            // refresh until the sender is dropped
+            while let Err(RecvTimeoutError::Timeout) = receiver.recv_timeout(REFRESH_INTERVAL) {
+                match refresh_lock(&be, exclusive, id) {
+                    Ok(new_id) => id = new_id,
+                    Err(err) => warn!("error refreshing lock {id}: {err}"),
+                }
+            }
+            match be.remove(FileType::Lock, &id, false) {
+                Ok(()) => debug!("removed lock {id}"),
+                Err(err) => warn!("error removing lock {id}: {err}"),
+            }
+        });
+
+        Ok(Self {
+            stop: Some(stop),
+            thread: Some(thread),
+        })
+    }
+}
+
+impl Drop for RepositoryLock {
+    fn drop(&mut self) {
+        // dropping the sender stops the thread which then removes the lock file
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+// save a new lock file and remove the old one
+fn refresh_lock(be: &impl DecryptFullBackend, exclusive: bool, id: Id) -> Result<Id> {
+    let new_id = be.save_file(&LockFile::new(exclusive)?)?;
+    be.remove(FileType::Lock, &id, false)?;
+    debug!("refreshed lock {id} -> {new_id}");
+    Ok(new_id)
+}
+
+// check if there are locks in the repository which conflict with the lock to acquire
+fn check_locks(be: &impl DecryptFullBackend, exclusive: bool, own: Option<Id>) -> Result<()> {
+    for id in be.list(FileType::Lock)? {
+        if Some(id) == own {
+            continue;
+        }
+        let lock: LockFile = match be.get_file(&id) {
+            Ok(lock) => lock,
+            // the lock might have been removed in the meantime
+            Err(err) if be.read_full(FileType::Lock, &id).is_err() => {
+                debug!("error reading lock {id}: {err}");
+                continue;
+            }
+            // locks saved using a write-only key can only be read using the private key and
+            // write-only keys cannot read other locks. As it is unknown whether such a lock is
+            // exclusive, it conflicts with every lock.
+            Err(err) => bail!(
+                "repository is locked by lock {id} which cannot be read: {err}\nIf you are sure that the lock is stale, you can remove it using `rustic unlock --remove-all`."
+            ),
+        };
+        if lock.is_stale() {
+            info!("ignoring stale {lock}");
+            continue;
+        }
+        if exclusive || lock.exclusive {
+            bail!(
+                "repository is already locked: {lock}\nIf you are sure that the lock is stale, you can remove it using `rustic unlock`."
+            );
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{DecryptBackend, DecryptWriteBackend, LocalBackend, WriteBackend};
+    use crate::crypto::Key;
+
+    #[test]
+    fn unreadable_locks_conflict() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let be = LocalBackend::new(dir.path().to_str().unwrap())?;
+        be.create()?;
+        let key = Key::new();
+        let dbe = DecryptBackend::new(&be, key.clone());
+
+        dbe.save_file(&LockFile::new(false)?)?;
+        check_locks(&dbe, false, None)?;
+        assert!(check_locks(&dbe, true, None).is_err());
+
+        // a lock saved using another key, e.g. a write-only key, may be exclusive
+        let other = DecryptBackend::new(&be, Key::new());
+        let id = other.save_file(&LockFile::new(false)?)?;
+        assert!(check_locks(&dbe, false, None).is_err());
+        check_locks(&dbe, false, Some(id))?;
+        Ok(())
+    }
+}
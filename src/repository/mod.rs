@@ -1,5 +1,5 @@
 use std::collections::HashMap;
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::{BufRead, BufReader};
 use std::path::PathBuf;
 use std::process::Command;
@@ -25,8 +25,14 @@ use crate::backend::{
     Cache, CachedBackend, ChooseBackend, DecryptBackend, DecryptReadBackend, DecryptWriteBackend,
     FileType, HotColdBackend, ReadBackend,
 };
-use crate::crypto::Key;
-use crate::repofile::{find_key_in_backend, ConfigFile};
+use crate::crypto::{CryptoKey, PrivateKey, RepoKey, WriterKeys};
+use crate::id::Id;
+use crate::repofile::{find_key_in_backend_with, ConfigFile, KeyContent, WriteOnlyKeyFile};
+
+mod lock;
+mod writeonly;
+pub use lock::RepositoryLock;
+pub use writeonly::WriteOnlyCache;
 
 #[serde_as]
 #[derive(Default, Parser, Deserialize, Merge)]
@@ -64,6 +70,15 @@ pub struct RepositoryOptions {
     )]
     password_command: Option<String>,
 
+    /// File containing the private key, needed to read data saved using write-only keys
+    #[clap(
+        long,
+        global = true,
+        parse(from_os_str),
+        env = "RUSTIC_PRIVATE_KEY_FILE"
+    )]
+    private_key_file: Option<PathBuf>,
+
     /// Don't use a cache.
     #[clap(long, global = true, env = "RUSTIC_NO_CACHE")]
     #[merge(strategy = merge::bool::overwrite_false)]
@@ -243,24 +258,47 @@ impl Repository {
             }
         }
 
-        let key = get_key(&self.be, self.password()?)?;
+        let (key_id, content) = get_key(&self.be, self.password()?, &config_ids[0])?;
         info!("repository {}: password is correct.", self.name);
 
-        let dbe = DecryptBackend::new(&self.be, key.clone());
-        let config: ConfigFile = dbe
-            .get_file(&config_ids[0])
-            .context("error accessing config file")?;
-        match (config.is_hot == Some(true), self.be_hot.is_some()) {
-                (true, false) => bail!("repository is a hot repository!\nPlease use as --repo-hot in combination with the normal repo. Aborting."),
-                (false, true) => bail!("repo-hot is not a hot repository! Aborting."),
-                _ => {}
+        let (key_id, key, config, write_only_key) = match content {
+            KeyContent::Master(key) => {
+                let dbe = DecryptBackend::new(&self.be, key.clone());
+                let config: ConfigFile = dbe
+                    .get_file(&config_ids[0])
+                    .context("error accessing config file")?;
+                match (config.is_hot == Some(true), self.be_hot.is_some()) {
+                    (true, false) => bail!("repository is a hot repository!\nPlease use as --repo-hot in combination with the normal repo. Aborting."),
+                    (false, true) => bail!("repo-hot is not a hot repository! Aborting."),
+                    _ => {}
+                }
+                let writers = self.writer_keys(&config)?;
+                (key_id, RepoKey::master(key, writers), config, None)
+            }
+            KeyContent::WriteOnly(file) => {
+                info!("using write-only key {key_id}");
+                let key = RepoKey::WriteOnly(file.key.clone());
+                (key_id, key, file.config.clone(), Some(file))
             }
+        };
+
         let cache = (!self.opts.no_cache)
             .then(|| Cache::new(config.id, self.opts.cache_dir.clone()).ok())
             .flatten();
-        match &cache {
-            None => info!("using no cache"),
-            Some(cache) => info!("using cache at {}", cache.location()),
+        // write-only keys use an own cache which is only used by the backup
+        let (cache, write_only_cache) = match &write_only_key {
+            None => (cache, None),
+            Some(file) => {
+                let cache = cache
+                    .map(|cache| WriteOnlyCache::new(&cache, file))
+                    .transpose()?;
+                (None, cache)
+            }
+        };
+        match (&cache, &write_only_cache) {
+            (Some(cache), _) => info!("using cache at {}", cache.location()),
+            (_, Some(cache)) => info!("using cache at {}", cache.location()),
+            (None, None) => info!("using no cache"),
         }
         let be_cached = CachedBackend::new(self.be.clone(), cache.clone());
         let mut dbe = DecryptBackend::new(&be_cached, key.clone());
@@ -269,40 +307,92 @@ impl Repository {
 
         Ok(OpenRepository {
             name: self.name,
+            key_id,
             key,
             dbe,
             cache,
+            write_only_cache,
+            write_only_key,
             be: self.be,
             be_hot: self.be_hot,
             config,
             opts: self.opts,
         })
     }
+
+    // unseal the keys of the write-only keys if a private key is given
+    fn writer_keys(&self, config: &ConfigFile) -> Result<WriterKeys> {
+        if config.write_only_keys.is_empty() {
+            return Ok(WriterKeys::default());
+        }
+        let Some(file) = &self.opts.private_key_file else {
+            info!("no private key given: data saved using write-only keys cannot be read.");
+            return Ok(WriterKeys::default());
+        };
+        let private_key = fs::read_to_string(file)
+            .with_context(|| format!("error reading private key file {file:?}"))?;
+        let private_key = PrivateKey::from_hex(&private_key)
+            .with_context(|| format!("invalid private key in {file:?}"))?;
+        WriterKeys::unseal(&private_key, &config.write_only_keys)
+            .context("private key does not match the public key of the repository")
+    }
 }
 
 pub struct OpenRepository {
     pub(crate) name: String,
     pub(crate) be: HotColdBackend<ChooseBackend>,
     pub(crate) be_hot: Option<ChooseBackend>,
-    pub(crate) key: Key,
+    pub(crate) key_id: Id,
+    pub(crate) key: RepoKey,
     pub(crate) cache: Option<Cache>,
-    pub(crate) dbe: DecryptBackend<CachedBackend<HotColdBackend<ChooseBackend>>, Key>,
+    pub(crate) write_only_cache: Option<WriteOnlyCache>,
+    /// the key file of the write-only key, which is replaced by the backup
+    pub(crate) write_only_key: Option<Box<WriteOnlyKeyFile>>,
+    pub(crate) dbe: DecryptBackend<CachedBackend<HotColdBackend<ChooseBackend>>, RepoKey>,
     pub(crate) config: ConfigFile,
     pub(crate) opts: RepositoryOptions,
 }
 
+impl OpenRepository {
+    /// Lock the repository if locking is enabled in the repository config.
+    /// The lock is held until the returned [`RepositoryLock`] is dropped.
+    pub fn lock(&self, exclusive: bool) -> Result<Option<RepositoryLock>> {
+        if self.config.use_locking != Some(true) {
+            return Ok(None);
+        }
+        Ok(Some(RepositoryLock::new(self.dbe.clone(), exclusive)?))
+    }
+}
+
 const MAX_PASSWORD_RETRIES: usize = 5;
-pub fn get_key(be: &impl ReadBackend, password: Option<String>) -> Result<Key> {
+/// Get the key for the given password (or prompt for it) which is able to decrypt the config file
+/// or is a write-only key.
+/// Note that during a rekey, there may be keys with the same password but another master key.
+pub fn get_key(
+    be: &impl ReadBackend,
+    password: Option<String>,
+    config_id: &Id,
+) -> Result<(Id, KeyContent)> {
+    let config = be
+        .read_full(FileType::Config, config_id)
+        .context("error accessing config file")?;
+    let check = |content: &KeyContent| match content {
+        KeyContent::Master(key) => key.decrypt_data(&config).is_ok(),
+        // write-only keys cannot read the config
+        KeyContent::WriteOnly(_) => true,
+    };
     for _ in 0..MAX_PASSWORD_RETRIES {
         match password {
             // if password is given, directly return the result of find_key_in_backend and don't retry
-            Some(pass) => return find_key_in_backend(be, &pass, None),
+            Some(pass) => return find_key_in_backend_with(be, &pass, check),
             None => {
                 // TODO: Differentiate between wrong password and other error!
-                if let Ok(key) =
-                    find_key_in_backend(be, &prompt_password("enter repository password: ")?, None)
-                {
-                    return Ok(key);
+                if let Ok(id_key) = find_key_in_backend_with(
+                    be,
+                    &prompt_password("enter repository password: ")?,
+                    check,
+                ) {
+                    return Ok(id_key);
                 }
             }
         }
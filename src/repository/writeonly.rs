@@ -0,0 +1,84 @@
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+use anyhow::Result;
+use log::*;
+
+use crate::backend::{
+    Cache, CachedBackend, ChooseBackend, DecryptBackend, DecryptReadBackend, FileType,
+    HotColdBackend, LocalBackend, ReadBackend, WriteBackend,
+};
+use crate::crypto::{Key, RepoKey, WriterKeys};
+use crate::id::Id;
+use crate::repofile::{IndexFile, WriteOnlyKeyFile};
+
+/// [`WriteOnlyCache`] is the local cache of a write-only key.
+///
+/// A write-only key cannot read index files, snapshots or trees saved in the repository. To still
+/// allow deduplication and parent detection, the backup saves these files additionally to this
+/// cache, encrypted with the cache key of the write-only key.
+///
+/// Note that the cache key is saved in the key file and is not replaced like the key of the writer.
+/// So whoever gets hold of the key file and its password, e.g. by compromising the host, can read
+/// all snapshots, index files and trees in the cache, i.e. the metadata of all backups saved using
+/// this write-only key. Only the file contents saved in earlier runs stay unreadable.
+pub struct WriteOnlyCache {
+    path: PathBuf,
+    key: Key,
+}
+
+impl WriteOnlyCache {
+    pub fn new(cache: &Cache, file: &WriteOnlyKeyFile) -> Result<Self> {
+        let path = PathBuf::from(cache.location())
+            .join("write-only")
+            .join(format!("{:08x}", file.key.writer));
+        Ok(Self {
+            path,
+            key: file.cache_key.clone(),
+        })
+    }
+
+    pub fn location(&self) -> String {
+        self.path.to_string_lossy().to_string()
+    }
+
+    /// Open the cache and remove all files which are no longer present in the repository.
+    pub fn open(
+        &self,
+        be: &impl ReadBackend,
+    ) -> Result<DecryptBackend<CachedBackend<HotColdBackend<ChooseBackend>>, RepoKey>> {
+        let local = ChooseBackend::Local(LocalBackend::new(&self.location())?);
+        local.create()?;
+        let local = CachedBackend::new(HotColdBackend::new(local, None), None);
+        let cache = DecryptBackend::new(
+            &local,
+            RepoKey::master(self.key.clone(), WriterKeys::default()),
+        );
+
+        let packs: HashSet<Id> = be.list(FileType::Pack)?.into_iter().collect();
+        for id in cache.list(FileType::Index)? {
+            // index files referencing packs which have been removed (e.g. by prune) are outdated
+            let valid = cache
+                .get_file::<IndexFile>(&id)
+                .map(|index| index.packs.iter().all(|pack| packs.contains(&pack.id)))
+                .unwrap_or(false);
+            if !valid {
+                debug!("removing index {id} from write-only cache");
+                cache.remove(FileType::Index, &id, false)?;
+            }
+        }
+        for id in cache.list(FileType::Pack)? {
+            if !packs.contains(&id) {
+                cache.remove(FileType::Pack, &id, false)?;
+            }
+        }
+        let snapshots: HashSet<Id> = be.list(FileType::Snapshot)?.into_iter().collect();
+        for id in cache.list(FileType::Snapshot)? {
+            if !snapshots.contains(&id) {
+                debug!("removing snapshot {id} from write-only cache");
+                cache.remove(FileType::Snapshot, &id, false)?;
+            }
+        }
+        Ok(cache)
+    }
+}